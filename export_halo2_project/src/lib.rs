@@ -6,7 +6,7 @@ use std::marker::PhantomData;
 use std::{collections::HashMap, io};
 use halo2_proofs::{
     circuit::{floor_planner::V1, *},
-    pasta::group::ff::PrimeField, plonk::*, poly::Rotation,
+    halo2curves::ff::PrimeField, plonk::*, poly::Rotation,
 };
 impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
     type Config = CommonConfig<F>;
@@ -93,7 +93,7 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
             },
         );
         /// build lookups
-        meta.lookup(|meta| {
+        meta.lookup("lookup_0", |meta| {
             vec![
                 ((config.query_column(meta, ColumnType::Advice, "in1_dec", 0i64).unwrap()
                 * config.query_column(meta, ColumnType::ComplexSelector, "xor_s", 0i64)
@@ -459,7 +459,7 @@ impl<F: PrimeField> CommonConfig<F> {
                         )
                     })
             }
-            ColumnType::Fixed => self.get_fixed(&col_name).map(|x| meta.query_fixed(x)),
+            ColumnType::Fixed => self.get_fixed(&col_name).map(|x| meta.query_fixed(x, Rotation::cur())),
             ColumnType::Instance => todo!(),
             ColumnType::ComplexSelector => {
                 self.get_selector(&col_name).map(|x| meta.query_selector(x))
@@ -471,7 +471,7 @@ impl<F: PrimeField> CommonConfig<F> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use halo2_proofs::{dev::MockProver, pasta::Fp as F};
+    use halo2_proofs::{dev::MockProver, halo2curves::pasta::Fp as F};
     #[test]
     fn test_simple() {
         let circuit = MyCircuit {
@@ -0,0 +1,234 @@
+use std::{fs, path::Path};
+
+use halo2_proofs::halo2curves::pasta::{EpAffine, EqAffine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    circuit::MyCircuit,
+    error::PlonkScriptError,
+    prover::{self, Backend, Ipa, Kzg, ProveResult},
+    system::{cell_expression::ToField, FieldKind, SimplifiedConstraitSystem},
+};
+
+/// Files written by [`ProveResult::write_to`] and read back by [`verify_dir`].
+pub const PARAMS_FILE: &str = "params.bin";
+pub const VERIFYING_KEY_FILE: &str = "vk.json";
+pub const PROOF_FILE: &str = "proof.json";
+
+const FORMAT_VERSION: u32 = 2;
+
+/// A verifying key on disk.
+///
+/// halo2 configures the circuit of a key while reading it, so the file also
+/// holds the circuit layout, which names the public inputs as well.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyingKeyFile {
+    pub version: u32,
+    pub k: u32,
+    /// digest of `key`, see [`prover::digest`]
+    pub digest: String,
+    /// hex encoded, as written by [`Backend::write_vk`]
+    pub key: String,
+    pub circuit: SimplifiedConstraitSystem,
+}
+
+/// A proof on disk, with the public inputs it was made for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofFile {
+    pub version: u32,
+    /// digest of the verifying key the proof is checked with
+    pub verifying_key: String,
+    pub public_inputs: Vec<PublicInput>,
+    /// hex encoded
+    pub proof: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicInput {
+    pub name: String,
+    /// `0x` prefixed, big endian
+    pub value: String,
+}
+
+impl ProveResult {
+    pub fn verifying_key_file(&self) -> VerifyingKeyFile {
+        VerifyingKeyFile {
+            version: FORMAT_VERSION,
+            k: self.k,
+            digest: self.verifying_key_digest(),
+            key: hex::encode(&self.verifying_key),
+            circuit: self.circuit.clone(),
+        }
+    }
+
+    pub fn proof_file(&self) -> ProofFile {
+        ProofFile {
+            version: FORMAT_VERSION,
+            verifying_key: self.verifying_key_digest(),
+            public_inputs: self
+                .public_inputs
+                .iter()
+                .map(|(name, value)| PublicInput {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            proof: hex::encode(&self.proof),
+        }
+    }
+
+    /// Writes the params, verifying key and proof into `dir`.
+    pub fn write_to(&self, dir: &Path) -> Result<(), PlonkScriptError> {
+        fs::create_dir_all(dir).map_err(|e| PlonkScriptError::io(dir, e))?;
+        write_params(&self.params, &dir.join(PARAMS_FILE))?;
+        write_json(&self.verifying_key_file(), &dir.join(VERIFYING_KEY_FILE))?;
+        write_json(&self.proof_file(), &dir.join(PROOF_FILE))
+    }
+}
+
+pub fn write_params(params: &[u8], path: &Path) -> Result<(), PlonkScriptError> {
+    fs::write(path, params).map_err(|e| PlonkScriptError::io(path, e))
+}
+
+/// Reads params as written by [`write_params`], they are only decoded by [`verify`]
+/// once the curve is known.
+pub fn read_params(path: &Path) -> Result<Vec<u8>, PlonkScriptError> {
+    fs::read(path).map_err(|e| PlonkScriptError::io(path, e))
+}
+
+impl VerifyingKeyFile {
+    pub fn read(path: &Path) -> Result<Self, PlonkScriptError> {
+        read_json(path)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), PlonkScriptError> {
+        write_json(self, path)
+    }
+}
+
+impl ProofFile {
+    pub fn read(path: &Path) -> Result<Self, PlonkScriptError> {
+        read_json(path)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), PlonkScriptError> {
+        write_json(self, path)
+    }
+}
+
+/// Checks a proof without running the script that produced it.
+///
+/// The verifying key is read from its file and must match the digest recorded
+/// when proving.
+pub fn verify(
+    params: &[u8],
+    vk: &VerifyingKeyFile,
+    proof: &ProofFile,
+) -> Result<(), PlonkScriptError> {
+    if vk.version != FORMAT_VERSION || proof.version != FORMAT_VERSION {
+        let file = if vk.version != FORMAT_VERSION {
+            VERIFYING_KEY_FILE
+        } else {
+            PROOF_FILE
+        };
+        return Err(invalid(file, "unsupported format version"));
+    }
+    if proof.verifying_key != vk.digest {
+        return Err(invalid(
+            PROOF_FILE,
+            "the proof was made for another verifying key",
+        ));
+    }
+    if proof.public_inputs.len() != vk.circuit.signals.len() {
+        return Err(invalid(
+            PROOF_FILE,
+            "the public inputs do not match the signals of the circuit",
+        ));
+    }
+
+    match vk.circuit.field {
+        FieldKind::Pallas => verify_with::<Ipa<EqAffine>>(params, vk, proof),
+        FieldKind::Vesta => verify_with::<Ipa<EpAffine>>(params, vk, proof),
+        FieldKind::Bn254 => verify_with::<Kzg>(params, vk, proof),
+    }
+}
+
+// `B` proves the circuits over the field of `vk`
+fn verify_with<B: Backend>(
+    params: &[u8],
+    vk: &VerifyingKeyFile,
+    proof: &ProofFile,
+) -> Result<(), PlonkScriptError> {
+    let params = B::read_params(params).map_err(|e| invalid(PARAMS_FILE, &e.to_string()))?;
+    let bytes = hex::decode(&vk.key).map_err(|e| invalid(VERIFYING_KEY_FILE, &e.to_string()))?;
+    if prover::digest(&bytes) != vk.digest {
+        return Err(invalid(
+            VERIFYING_KEY_FILE,
+            "the key does not match its digest",
+        ));
+    }
+    let circuit = MyCircuit::new(vk.circuit.clone());
+    let key = prover::read_vk::<B>(&bytes, &circuit)
+        .map_err(|e| invalid(VERIFYING_KEY_FILE, &e.to_string()))?;
+
+    // the inputs are listed column after column, like the instance is built
+    let mut inputs = proof.public_inputs.iter();
+    let instance = vk
+        .circuit
+        .instance_columns()
+        .into_iter()
+        .map(|(_, signals)| {
+            signals
+                .into_iter()
+                .map(|signal| match inputs.next() {
+                    Some(input) if input.name == signal.name => input
+                        .value
+                        .to_field::<B::Scalar>()
+                        .ok_or_else(|| PlonkScriptError::InvalidInput {
+                            name: input.name.clone(),
+                            value: input.value.clone(),
+                        }),
+                    _ => Err(invalid(
+                        PROOF_FILE,
+                        "the public inputs do not match the signals of the circuit",
+                    )),
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let proof = hex::decode(&proof.proof).map_err(|e| invalid(PROOF_FILE, &e.to_string()))?;
+
+    prover::verify::<B>(&params, &key, &instance, &proof).map_err(PlonkScriptError::ProofRejected)
+}
+
+/// [`verify`] with the files [`ProveResult::write_to`] put in `dir`.
+pub fn verify_dir(dir: &Path) -> Result<(), PlonkScriptError> {
+    let params = read_params(&dir.join(PARAMS_FILE))?;
+    let vk = VerifyingKeyFile::read(&dir.join(VERIFYING_KEY_FILE))?;
+    let proof = ProofFile::read(&dir.join(PROOF_FILE))?;
+    verify(&params, &vk, &proof)
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, PlonkScriptError> {
+    let text = fs::read_to_string(path).map_err(|e| PlonkScriptError::io(path, e))?;
+    serde_json::from_str(&text).map_err(|e| artifact_error(path, e))
+}
+
+fn write_json<T: Serialize>(value: &T, path: &Path) -> Result<(), PlonkScriptError> {
+    let text = serde_json::to_string_pretty(value).map_err(|e| artifact_error(path, e))?;
+    fs::write(path, text).map_err(|e| PlonkScriptError::io(path, e))
+}
+
+fn invalid(file: &str, message: &str) -> PlonkScriptError {
+    PlonkScriptError::InvalidArtifact {
+        path: file.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn artifact_error(path: &Path, error: impl std::fmt::Display) -> PlonkScriptError {
+    PlonkScriptError::InvalidArtifact {
+        path: path.display().to_string(),
+        message: error.to_string(),
+    }
+}
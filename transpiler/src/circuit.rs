@@ -1,23 +1,107 @@
+use std::cell::RefCell;
 use std::marker::PhantomData;
+use std::sync::Arc;
 use std::{collections::HashMap, io};
 
 use halo2_proofs::{
     circuit::{floor_planner::V1, *},
-    pasta::group::ff::PrimeField,
+    halo2curves::ff::PrimeField,
     plonk::*,
     poly::Rotation,
 };
 
-use crate::system::LookupParameter;
+use crate::engine::{CONSTANTS_COLUMN_NAME, DEFAULT_INSTANCE_COLUMN_NAME};
 use crate::system::{cell_expression::ToField, CellExpression};
-use crate::{engine::DEFAULT_INSTANCE_COLUMN_NAME, CONTEXT};
+use crate::system::{LookupParameter, SimplifiedConstraitSystem};
+
+thread_local! {
+    // `Circuit::configure` takes no `self`, so the system of the circuit being
+    // configured is lent here for the duration of `MyCircuit::scope`
+    static CONFIGURING: RefCell<Option<Arc<SimplifiedConstraitSystem>>> = RefCell::new(None);
+}
+
+// puts the previously lent system back when `MyCircuit::scope` ends, also when
+// `f` panics and the panic is caught, so the next circuit on the thread does not
+// get a stale one
+struct RestoreConfiguring(Option<Arc<SimplifiedConstraitSystem>>);
+
+impl Drop for RestoreConfiguring {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        // the thread-local is gone when this runs during thread teardown
+        let _ = CONFIGURING.try_with(|c| *c.borrow_mut() = previous);
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct MyCircuit<F: PrimeField> {
-    // pub scs: SimplifiedConstraitSystem,
+    pub scs: Arc<SimplifiedConstraitSystem>,
     pub _marker: PhantomData<F>,
 }
 
+impl<F: PrimeField> MyCircuit<F> {
+    pub fn new(scs: SimplifiedConstraitSystem) -> Self {
+        MyCircuit {
+            scs: Arc::new(scs),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Runs `f` (typically `MockProver::run` or key generation) with this circuit's
+    /// constraint system available to `Circuit::configure` on the current thread.
+    pub fn scope<R>(&self, f: impl FnOnce(&Self) -> R) -> R {
+        let _restore = RestoreConfiguring(CONFIGURING.with(|c| c.replace(Some(self.scs.clone()))));
+        f(self)
+    }
+
+    /// PlonkScript name of a halo2 column of this circuit.
+    ///
+    /// Columns are numbered per type in the order `configure` creates them;
+    /// lookup tables are fixed columns, followed by the constants column, and
+    /// selectors only turn into fixed columns after all of them, so those are
+    /// not named.
+    pub fn column_name(&self, column_type: &Any, index: usize) -> Option<String> {
+        let has_default_instance = self
+            .scs
+            .instance_columns()
+            .first()
+            .is_some_and(|(name, _)| name == DEFAULT_INSTANCE_COLUMN_NAME);
+        if *column_type == Any::Instance && has_default_instance && index == 0 {
+            return Some(DEFAULT_INSTANCE_COLUMN_NAME.to_string());
+        }
+        let index = match column_type {
+            Any::Instance if has_default_instance => index - 1,
+            _ => index,
+        };
+        let mut columns = self
+            .scs
+            .columns
+            .iter()
+            .filter(|col| match column_type {
+                Any::Advice(_) => col.ctype == crate::system::ColumnType::Advice,
+                Any::Fixed => matches!(
+                    col.ctype,
+                    crate::system::ColumnType::Fixed | crate::system::ColumnType::TableLookup
+                ),
+                Any::Instance => col.ctype == crate::system::ColumnType::Instance,
+            })
+            .map(|col| col.name.clone())
+            .collect::<Vec<_>>();
+        if *column_type == Any::Fixed && self.scs.uses_constants() {
+            columns.push(CONSTANTS_COLUMN_NAME.to_string());
+        }
+        columns.into_iter().nth(index)
+    }
+
+    fn configuring() -> Arc<SimplifiedConstraitSystem> {
+        // every prover, key generation and MockProver run of the crate goes
+        // through `scope`, only a caller skipping it gets here without a system
+        CONFIGURING.with(|c| c.borrow().clone()).unwrap_or_else(|| {
+            unreachable!("MyCircuit::configure called outside of MyCircuit::scope")
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct CommonConfig<F: PrimeField> {
@@ -26,7 +110,7 @@ pub struct CommonConfig<F: PrimeField> {
     selectors: Vec<(String, Selector)>,
     instances: Vec<(String, Column<Instance>)>,
     lookups: Vec<(String, TableColumn)>,
-    acells: Vec<(String, AssignedCell<F, F>)>,
+    acells: HashMap<String, AssignedCell<F, F>>, // cell name, its assignment in any region
     _marker: PhantomData<F>,
 }
 
@@ -36,21 +120,29 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
     // type FloorPlanner = SimpleFloorPlanner;
 
     fn without_witnesses(&self) -> Self {
-        Self::default()
+        MyCircuit {
+            scs: self.scs.clone(),
+            _marker: PhantomData,
+        }
     }
 
     fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
         // let value = meta.advice_column();
         // CommonConfig::configure(meta, value)
+        let scs = Self::configuring();
 
         let mut advices = Vec::new();
         let mut fixeds = Vec::new();
         let mut selectors = Vec::new();
         let mut instances = Vec::new();
         let mut lookups = Vec::new();
-        let acells = Vec::new();
+        let acells = HashMap::new();
 
-        if unsafe { CONTEXT.signals.len() > 0 } {
+        if scs
+            .instance_columns()
+            .first()
+            .is_some_and(|(name, _)| name == DEFAULT_INSTANCE_COLUMN_NAME)
+        {
             instances.push((
                 DEFAULT_INSTANCE_COLUMN_NAME.to_string(),
                 meta.instance_column(),
@@ -58,7 +150,7 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
         }
 
         // build columns
-        let scols = unsafe { CONTEXT.columns.clone() };
+        let scols = scs.columns.clone();
         for col in scols {
             match col.ctype {
                 crate::system::ColumnType::Advice => advices.push((col.name, meta.advice_column())),
@@ -76,13 +168,23 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
             }
         }
 
-        // enable_equality
-        for c in advices.clone() {
+        // enable_equality, only on columns taking part in copy constraints
+        let in_equality = scs.columns_in_equality();
+        for c in advices.iter().filter(|c| in_equality.contains(&c.0)) {
             meta.enable_equality(c.1);
         }
-        for c in instances.clone() {
+        for c in instances.iter().filter(|c| in_equality.contains(&c.0)) {
             meta.enable_equality(c.1);
         }
+        for c in fixeds.iter().filter(|c| in_equality.contains(&c.0)) {
+            meta.enable_equality(c.1);
+        }
+
+        // created after the fixed columns of the script, see `column_name`
+        if scs.uses_constants() {
+            let constants = meta.fixed_column();
+            meta.enable_constant(constants);
+        }
 
         let config = CommonConfig {
             advices,
@@ -95,28 +197,57 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
         };
 
         // build gates
-        let sgates = unsafe { CONTEXT.gates.clone() };
-        for (gname, _, _, gate) in sgates {
-            let sgname = Box::leak(gname.into_boxed_str());
+        for (gname, gates) in scs.gate_groups() {
+            let sgname = Box::leak(gname.to_string().into_boxed_str());
             meta.create_gate(sgname, |meta| {
-                vec![convert_to_gate_expression(meta, config.clone(), gate)
-                    .expect(format!("cannot convert gate expression of {}", sgname).as_str())]
+                gates
+                    .into_iter()
+                    .map(|gate| {
+                        // `compile` rejects what cannot be converted, see `check_circuit`
+                        convert_to_gate_expression(meta, config.clone(), gate.clone())
+                            .unwrap_or_else(|e| unreachable!("gate {} was checked: {}", sgname, e))
+                    })
+                    .collect::<Vec<_>>()
             });
         }
 
         // build lookups
-        let slookups = unsafe { CONTEXT.lookups.clone() };
-        for LookupParameter { name, map } in slookups {
-            let slname = Box::leak(name.into_boxed_str());
-            meta.lookup(|meta| {
+        let slookups = scs.lookups.clone();
+        for lookup in slookups {
+            let advice = lookup.reads_advice();
+            let LookupParameter { name, map, .. } = lookup;
+            if advice {
+                // advice tables are expressions, gated by their selector
+                meta.lookup_any(&name, |meta| {
+                    map.into_iter()
+                        // `compile` rejects what cannot be converted, see `check_circuit`
+                        .map(|(exp, col)| {
+                            (
+                                convert_to_gate_expression(meta, config.clone(), exp)
+                                    .unwrap_or_else(|e| {
+                                        unreachable!("lookup {} was checked: {}", name, e)
+                                    }),
+                                config.query_table(meta, &col).unwrap_or_else(|e| {
+                                    unreachable!("lookup {} was checked: {}", name, e)
+                                }),
+                            )
+                        })
+                        .collect::<Vec<(Expression<F>, Expression<F>)>>()
+                });
+                continue;
+            }
+            meta.lookup(&name, |meta| {
                 let lookup_vec = map
                     .into_iter()
+                    // `compile` rejects what cannot be converted, see `check_circuit`
                     .map(|(exp, col)| {
                         (
-                            convert_to_gate_expression(meta, config.clone(), exp).expect(
-                                format!("cannot convert lookup expression of {}", slname).as_str(),
+                            convert_to_gate_expression(meta, config.clone(), exp).unwrap_or_else(
+                                |e| unreachable!("lookup {} was checked: {}", name, e),
                             ),
-                            config.get_table_lookup(&col.name).unwrap(),
+                            config.get_table_lookup(&col.name).unwrap_or_else(|e| {
+                                unreachable!("lookup {} was checked: {}", name, e)
+                            }),
                         )
                     })
                     .collect::<Vec<(Expression<F>, TableColumn)>>();
@@ -132,12 +263,15 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
         mut config: Self::Config,
         mut layouter: impl Layouter<F>,
     ) -> Result<(), Error> {
-        let sregions = unsafe { CONTEXT.regions.clone() };
+        let sregions = self.scs.regions.clone();
 
         for r in sregions {
+            // instance cells are outside of regions, they are copied once the region is laid out
+            let mut instance_copies = Vec::new();
             layouter.assign_region(
                 || r.name.clone(),
                 |mut region| {
+                    instance_copies.clear();
                     for ins in r.instructions.clone() {
                         match ins {
                             crate::system::Instruction::EnableSelector(c) => {
@@ -146,24 +280,34 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
                                     .enable(&mut region, c.index as usize)?;
                             }
                             crate::system::Instruction::AssignFixed(f, exp) => {
+                                let value = config.convert_to_value(exp)?;
                                 let acell = region.assign_fixed(
                                     || "fixed",
                                     config.get_fixed(&f.column.name)?,
                                     f.index as usize,
-                                    || config.convert_to_value(exp.clone()),
+                                    || value,
                                 )?;
-                                config.acells.push((f.name, acell));
+                                config.acells.insert(f.name, acell);
                             }
                             crate::system::Instruction::AssignAdvice(a, exp) => {
+                                let value = config.convert_to_value(exp)?;
                                 let acell = region.assign_advice(
                                     || "advice",
                                     config.get_advice(&a.column.name)?,
                                     a.index as usize,
-                                    || config.convert_to_value(exp.clone()),
+                                    || value,
+                                )?;
+                                config.acells.insert(a.name, acell);
+                            }
+                            crate::system::Instruction::AssignAdviceFromConstant(a, c) => {
+                                let acell = region.assign_advice_from_constant(
+                                    || "constant",
+                                    config.get_advice(&a.column.name)?,
+                                    a.index as usize,
+                                    to_constant::<F>(&c)?,
                                 )?;
-                                config.acells.push((a.name, acell));
+                                config.acells.insert(a.name, acell);
                             }
-                            crate::system::Instruction::AssignAdviceFromConstant(_, _) => todo!(),
                             crate::system::Instruction::AssignAdviceFromInstance(a, b) => {
                                 let acell = region.assign_advice_from_instance(
                                     || "instance",
@@ -172,16 +316,35 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
                                     config.get_advice(&a.column.name)?,
                                     a.index as usize,
                                 )?;
-                                config.acells.push((a.name, acell));
+                                config.acells.insert(a.name, acell);
                             }
                             crate::system::Instruction::ConstrainEqual(a, b) => {
-                                let acell = config.get_assigned_cell(a.name);
-                                let bcell = config.get_assigned_cell(b.name);
-                                region.constrain_equal(acell.cell(), bcell.cell())?;
+                                match (a.column.ctype, b.column.ctype) {
+                                    (_, crate::system::ColumnType::Instance) => {
+                                        let acell = config.get_assigned_cell(&a.name)?;
+                                        instance_copies.push((acell.cell(), b));
+                                    }
+                                    (crate::system::ColumnType::Instance, _) => {
+                                        let bcell = config.get_assigned_cell(&b.name)?;
+                                        instance_copies.push((bcell.cell(), a));
+                                    }
+                                    _ => {
+                                        let acell = config.get_assigned_cell(&a.name)?;
+                                        let bcell = config.get_assigned_cell(&b.name)?;
+                                        region.constrain_equal(acell.cell(), bcell.cell())?;
+                                    }
+                                }
                             }
-                            crate::system::Instruction::ConstrainConstant() => todo!(),
-                            crate::system::Instruction::AssignCell(_, _) => {
-                                todo!("illegal instruction")
+                            crate::system::Instruction::ConstrainConstant(a, c) => {
+                                let acell = config.get_assigned_cell(&a.name)?;
+                                region.constrain_constant(acell.cell(), to_constant::<F>(&c)?)?;
+                            }
+                            crate::system::Instruction::AssignCell(a, _) => {
+                                return Err(unsupported(&format!(
+                                    "lookup column {} filled inside region {}",
+                                    a.name, r.name
+                                ))
+                                .into())
                             }
                         };
                     }
@@ -189,9 +352,16 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
                     Ok(())
                 },
             )?;
+            for (cell, instance) in instance_copies {
+                layouter.constrain_instance(
+                    cell,
+                    config.get_instance(&instance.column.name)?,
+                    instance.index as usize,
+                )?;
+            }
         }
 
-        let stables = unsafe { CONTEXT.tables.clone() };
+        let stables = self.scs.tables.clone();
         let mut max_indexes = HashMap::<&str, usize>::new();
         for t in stables {
             layouter.assign_table(
@@ -213,14 +383,22 @@ impl<F: PrimeField> Circuit<F> for MyCircuit<F> {
                                     }
                                 };
 
+                                let value =
+                                    config.convert_to_value(CellExpression::Constant(b.clone()))?;
                                 table.assign_cell(
                                     || a.name.clone(),
                                     config.get_table_lookup(&a.name)?,
                                     idx,
-                                    || config.convert_to_value(CellExpression::Constant(b.clone())),
+                                    || value,
                                 )?;
                             }
-                            _ => todo!("illegal instruction"),
+                            _ => {
+                                return Err(unsupported(&format!(
+                                    "region instruction in lookup table {}",
+                                    t.name
+                                ))
+                                .into())
+                            }
                         };
                     }
 
@@ -277,11 +455,27 @@ impl<F: PrimeField> CommonConfig<F> {
             ))
     }
 
-    fn get_assigned_cell(&self, name: String) -> AssignedCell<F, F> {
-        if let Some(acell) = self.acells.iter().filter(|x| x.0 == name).nth(0) {
-            return acell.1.clone();
-        }
-        panic!("cannot find assigned cell of {}", name)
+    // an advice table gated by its selector, rows left out of it read as zero
+    fn query_table(
+        &self,
+        meta: &mut VirtualCells<F>,
+        table: &crate::system::Column,
+    ) -> Result<Expression<F>, io::Error> {
+        let selector = self.get_selector(&table.table_selector().name)?;
+        let advice = self.get_advice(&table.name)?;
+        Ok(meta.query_selector(selector) * meta.query_advice(advice, Rotation::cur()))
+    }
+
+    fn get_assigned_cell(&self, name: &str) -> Result<AssignedCell<F, F>, io::Error> {
+        self.acells.get(name).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "cell {} is not assigned in any region laid out so far",
+                    name
+                ),
+            )
+        })
     }
 
     fn query_column(
@@ -294,57 +488,90 @@ impl<F: PrimeField> CommonConfig<F> {
             crate::system::ColumnType::Selector => self
                 .get_selector(&column.name)
                 .map(|x| meta.query_selector(x)),
-            crate::system::ColumnType::Advice => self.get_advice(&column.name).map(|x| {
-                meta.query_advice(
-                    x,
-                    match cell.index {
-                        -1 => Rotation::prev(),
-                        0 => Rotation::cur(),
-                        1 => Rotation::next(),
-                        x @ 2..=5 => Rotation(x as i32),
-                        x => panic!("too big the rotation: {}", x),
-                    },
-                )
-            }),
-            crate::system::ColumnType::Fixed => {
-                self.get_fixed(&column.name).map(|x| meta.query_fixed(x))
+            crate::system::ColumnType::Advice => {
+                let rotation = i32::try_from(cell.index)
+                    .map(Rotation)
+                    .map_err(|_| unsupported(&format!("rotation {}", cell.index)))?;
+                self.get_advice(&column.name)
+                    .map(|x| meta.query_advice(x, rotation))
+            }
+            // the engine copies fixed cells used at other rows into advice
+            // cells, fixed columns are only queried at the current row
+            crate::system::ColumnType::Fixed => match cell.index {
+                0 => self
+                    .get_fixed(&column.name)
+                    .map(|x| meta.query_fixed(x, Rotation::cur())),
+                x => Err(unsupported(&format!(
+                    "rotation {} of fixed column {}",
+                    x, column.name
+                ))),
+            },
+            crate::system::ColumnType::Instance => {
+                let rotation = i32::try_from(cell.index)
+                    .map(Rotation)
+                    .map_err(|_| unsupported(&format!("rotation {}", cell.index)))?;
+                self.get_instance(&column.name)
+                    .map(|x| meta.query_instance(x, rotation))
             }
-            crate::system::ColumnType::Instance => todo!(),
             crate::system::ColumnType::ComplexSelector => self
                 .get_selector(&column.name)
                 .map(|x| meta.query_selector(x)),
-            crate::system::ColumnType::TableLookup => todo!(),
+            crate::system::ColumnType::TableLookup => Err(unsupported("lookup column in gate")),
         }
     }
 
-    fn convert_to_value(&self, exp: CellExpression) -> Value<F> {
-        match exp {
-            CellExpression::Calculated(c) => Value::known(c.to_field().unwrap()),
-            CellExpression::Constant(c) => Value::known(c.to_field().unwrap()),
+    fn convert_to_value(&self, exp: CellExpression) -> Result<Value<F>, io::Error> {
+        Ok(match exp {
+            CellExpression::Calculated(c) | CellExpression::Constant(c) => {
+                Value::known(c.to_field().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("cannot convert {} to field element", c),
+                    )
+                })?)
+            }
             CellExpression::CellValue(c) => match c.column.ctype {
-                crate::system::ColumnType::Selector => {
-                    self.get_assigned_cell(c.name).value().copied()
+                crate::system::ColumnType::Selector
+                | crate::system::ColumnType::Advice
+                | crate::system::ColumnType::Fixed
+                | crate::system::ColumnType::ComplexSelector => {
+                    self.get_assigned_cell(&c.name)?.value().copied()
                 }
-                crate::system::ColumnType::Advice => {
-                    self.get_assigned_cell(c.name).value().copied()
+                crate::system::ColumnType::Instance => {
+                    return Err(unsupported("instance cell in an assigned expression"))
                 }
-                crate::system::ColumnType::Fixed => self.get_assigned_cell(c.name).value().copied(),
-                crate::system::ColumnType::Instance => todo!(),
-                crate::system::ColumnType::ComplexSelector => {
-                    self.get_assigned_cell(c.name).value().copied()
+                crate::system::ColumnType::TableLookup => {
+                    return Err(unsupported("lookup cell in an assigned expression"))
                 }
-                crate::system::ColumnType::TableLookup => todo!(),
             },
-            CellExpression::Negated(n) => -self.convert_to_value(*n),
-            CellExpression::Product(a, b) => self.convert_to_value(*a) * self.convert_to_value(*b),
-            CellExpression::Sum(a, b) => self.convert_to_value(*a) + self.convert_to_value(*b),
+            CellExpression::Negated(n) => -self.convert_to_value(*n)?,
+            CellExpression::Product(a, b) => {
+                self.convert_to_value(*a)? * self.convert_to_value(*b)?
+            }
+            CellExpression::Sum(a, b) => self.convert_to_value(*a)? + self.convert_to_value(*b)?,
             CellExpression::Scaled(a, b) => {
-                self.convert_to_value(*a) * self.convert_to_value(CellExpression::Constant(b))
+                self.convert_to_value(*a)? * self.convert_to_value(CellExpression::Constant(b))?
             }
-        }
+        })
     }
 }
 
+fn to_constant<F: PrimeField>(c: &String) -> Result<F, io::Error> {
+    c.to_field().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("cannot convert {} to field element", c),
+        )
+    })
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported", what),
+    )
+}
+
 fn convert_to_gate_expression<F: PrimeField>(
     meta: &mut VirtualCells<F>,
     config: CommonConfig<F>,
@@ -366,9 +593,9 @@ fn convert_to_gate_expression<F: PrimeField>(
             crate::system::ColumnType::Selector => config.query_column(meta, c),
             crate::system::ColumnType::Advice => config.query_column(meta, c),
             crate::system::ColumnType::Fixed => config.query_column(meta, c),
-            crate::system::ColumnType::Instance => todo!(),
+            crate::system::ColumnType::Instance => config.query_column(meta, c),
             crate::system::ColumnType::ComplexSelector => config.query_column(meta, c),
-            crate::system::ColumnType::TableLookup => todo!(),
+            crate::system::ColumnType::TableLookup => Err(unsupported("lookup column in gate")),
         },
         CellExpression::Negated(n) => {
             convert_to_gate_expression(meta, config.clone(), *n).map(|x| -x)
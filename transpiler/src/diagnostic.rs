@@ -0,0 +1,255 @@
+use std::fmt;
+
+use halo2_proofs::dev::{FailureLocation, VerifyFailure};
+use rhai::{EvalAltResult, Position};
+use serde::Serialize;
+
+use crate::{
+    error::EngineError,
+    parser::{ParseError, Span},
+    system::SimplifiedConstraitSystem,
+    transpiler::SourceMap,
+};
+
+/// An error or failure pointing back into the PlonkScript source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub location: Option<SourceLocation>,
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceLocation {
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    /// number of characters to underline
+    pub length: usize,
+    /// the whole source line
+    pub text: String,
+}
+
+impl SourceLocation {
+    pub fn new(src: &str, span: Span) -> Self {
+        let (line, column) = span.location(src);
+        let text = src.lines().nth(line - 1).unwrap_or_default().to_string();
+        let rest = text.chars().count().saturating_sub(column - 1);
+        let length = src[span.start.min(src.len())..span.end.min(src.len())]
+            .chars()
+            .take_while(|c| *c != '\n')
+            .count()
+            .clamp(1, rest.max(1));
+        SourceLocation {
+            line,
+            column,
+            length,
+            text,
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Diagnostic {
+            message: message.into(),
+            location: None,
+            notes: vec![],
+        }
+    }
+
+    pub fn at(mut self, src: &str, span: Option<Span>) -> Self {
+        self.location = span.map(|span| SourceLocation::new(src, span));
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn from_parse_error(src: &str, error: &ParseError) -> Self {
+        Diagnostic::new(error.message.clone()).at(src, Some(error.span))
+    }
+
+    /// Maps an error raised while running the transpiled `script` back to `src`.
+    ///
+    /// Errors inside region functions are reported where they happened, with the
+    /// chain of calls that led there as notes.
+    pub fn from_script_error(
+        src: &str,
+        script: &str,
+        source_map: &SourceMap,
+        error: Box<EvalAltResult>,
+    ) -> Self {
+        let mut error = error;
+        let mut calls = vec![];
+        loop {
+            match *error {
+                EvalAltResult::ErrorInFunctionCall(name, _, inner, position) => {
+                    calls.push((name, position));
+                    error = inner;
+                }
+                EvalAltResult::ErrorInModule(_, inner, _) => error = inner,
+                _ => break,
+            }
+        }
+
+        let position = error.take_position();
+        let span = source_map.source_span(src, script, position);
+        let message = match EngineError::find(&error) {
+            Some(engine_error) => engine_error.message,
+            None => error.to_string(),
+        };
+        let mut diagnostic = Diagnostic::new(message).at(src, span);
+        for (name, position) in calls.into_iter().rev() {
+            let note = match source_map.source_span(src, script, position) {
+                Some(span) => {
+                    let (line, column) = span.location(src);
+                    format!("in `{}`, called at {}:{}", name, line, column)
+                }
+                None => format!("in `{}`", name),
+            };
+            diagnostic = diagnostic.with_note(note);
+        }
+        diagnostic
+    }
+
+    /// Maps a MockProver failure to the region, gate or lookup that caused it.
+    // the constraint, gate and region metadata of a failure has no accessors,
+    // their indices and names are read back from how each of them renders,
+    // e.g. "Constraint 0 in gate 1 ('gate_1')" and "Region 2 ('fib')"
+    pub(crate) fn from_verify_failure(
+        src: &str,
+        script: &str,
+        source_map: &SourceMap,
+        cs: &SimplifiedConstraitSystem,
+        failure: &VerifyFailure,
+    ) -> Self {
+        let region_of = |location: &FailureLocation| match location {
+            FailureLocation::InRegion { region, .. } => Some(region.to_string()),
+            FailureLocation::OutsideRegion { .. } => None,
+        };
+        let (constraint, gate, region, lookup) = match failure {
+            VerifyFailure::CellNotAssigned { gate, region, .. }
+            | VerifyFailure::InstanceCellNotAssigned { gate, region, .. } => {
+                (None, Some(gate.to_string()), Some(region.to_string()), None)
+            }
+            VerifyFailure::ConstraintNotSatisfied {
+                constraint,
+                location,
+                ..
+            } => (
+                Some(constraint.to_string()),
+                None,
+                region_of(location),
+                None,
+            ),
+            VerifyFailure::ConstraintPoisoned { constraint } => {
+                (Some(constraint.to_string()), None, None, None)
+            }
+            VerifyFailure::Lookup {
+                lookup_index,
+                location,
+                ..
+            } => (None, None, region_of(location), Some(*lookup_index)),
+            VerifyFailure::Shuffle { location, .. }
+            | VerifyFailure::Permutation { location, .. } => {
+                (None, None, region_of(location), None)
+            }
+        };
+
+        let span = |position: Position| source_map.source_span(src, script, position);
+        let region = region
+            .and_then(|region| index_after(&region, "Region "))
+            .and_then(|i| cs.regions.get(i));
+        // constraints of merged gates each keep where they were created
+        let index = constraint
+            .as_ref()
+            .and_then(|c| index_after(c, "Constraint "));
+        let gate = constraint
+            .and_then(|c| quoted_after(&c, "in gate "))
+            .or_else(|| gate.and_then(|g| quoted_after(&g, "Gate ")))
+            .and_then(|name| {
+                let position = index
+                    .and_then(|i| cs.gate_positions.get(&format!("{}.{}", name, i)))
+                    .or_else(|| cs.gate_positions.get(&name))
+                    .copied()?;
+                Some((position, name))
+            });
+        let lookup = lookup.and_then(|i| cs.lookups.get(i));
+
+        let message = failure.to_string();
+        let mut diagnostic = Diagnostic::new(message);
+        let mut notes = vec![];
+        if let Some((position, name)) = gate {
+            diagnostic = diagnostic.at(src, span(position));
+            notes.push(format!("`{}` was created by this statement", name));
+        } else if let Some(lookup) = lookup {
+            diagnostic = diagnostic.at(src, span(lookup.position));
+            notes.push(format!(
+                "lookup `{}` was defined by this statement",
+                lookup.name
+            ));
+        }
+        if let Some(region) = region {
+            match span(region.position).filter(|_| diagnostic.location.is_some()) {
+                Some(region_span) => {
+                    let (line, column) = region_span.location(src);
+                    notes.push(format!(
+                        "in region `{}` defined at {}:{}",
+                        region.name, line, column
+                    ));
+                }
+                // the default region comes from no statement, keep the gate's location
+                None if diagnostic.location.is_none() => {
+                    diagnostic = diagnostic.at(src, span(region.position))
+                }
+                None => {}
+            }
+        }
+        notes.into_iter().fold(diagnostic, Diagnostic::with_note)
+    }
+}
+
+// `Region 3 ('fib')` -> 3
+pub(crate) fn index_after(text: &str, prefix: &str) -> Option<usize> {
+    let rest = &text[text.find(prefix)? + prefix.len()..];
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+// `gate 0 ('gate_0')` -> gate_0, `Gate 0 ('gate_0')` with the prefix `Gate `
+pub(crate) fn quoted_after(text: &str, prefix: &str) -> Option<String> {
+    let rest = &text[text.find(prefix)? + prefix.len()..];
+    let start = rest.find("('")? + 2;
+    let end = start + rest[start..].find("')")?;
+    Some(rest[start..end].to_string())
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        if let Some(location) = &self.location {
+            let gutter = " ".repeat(location.line.to_string().len());
+            writeln!(f, "{}--> {}:{}", gutter, location.line, location.column)?;
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", location.line, location.text)?;
+            // keep tabs so the caret lines up with the source line
+            let indent: String = location
+                .text
+                .chars()
+                .take(location.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            writeln!(f, "{} | {}{}", gutter, indent, "^".repeat(location.length))?;
+        }
+        for note in &self.notes {
+            writeln!(f, "  = note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
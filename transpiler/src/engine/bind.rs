@@ -1,93 +1,317 @@
-use core::panic;
+use std::collections::HashMap;
 
 use rhai::Array;
 use rhai::EvalAltResult;
 
+use self::cell_expression::Degree;
 use self::cell_expression::ToBaseIndex;
 use self::cell_expression::ToString;
-use crate::engine::gate::check_gate_ce;
+
+use crate::engine::gate::{
+    check_declared_gate_ce, check_gate_ce, check_gate_columns, check_lookup_ce,
+};
+use crate::error::EngineError;
+use crate::session::Context;
 use crate::system::cell_expression::GetBaseIndex;
-use crate::system::cell_expression::ToValueString;
 use crate::system::*;
-use crate::CONTEXT;
 
 use super::custom_type::get_field_name;
 
-pub fn register_bind(engine: &mut rhai::Engine) {
-    let _ = &mut engine
-        .register_fn("assign_constraint", assign_constraint)
-        .register_fn("assign_constraint", assign_constraint_cell_ce)
-        .register_fn("assign_constraint", assign_constraint_string)
-        .register_fn("constrain_equal", constrain_equal)
-        .register_fn("assign_common", assign_common_string)
-        .register_fn("assign_common", assign_common_ce)
-        .register_fn("assign_common", assign_common_i64)
-        .register_fn("push", push_column_i64)
-        .register_fn("push", push_column_ce)
-        .register_fn("enable_selector", enable_selector)
-        .register_fn("lookup", lookup)
-        .register_fn("lookup", lookup_without_name)
-        //-
-        ;
+pub fn register_bind(engine: &mut rhai::Engine, context: &Context) {
+    register_with_context!(engine, context, "assign_constraint", assign_constraint, (a: &mut Cell, b: Cell));
+    register_with_context!(engine, context, "assign_constraint", assign_constraint_cell_ce, (a: &mut Cell, b: CellExpression));
+    register_with_context!(engine, context, "assign_constraint", assign_constraint_string, (a: &mut Cell, b: String));
+    register_with_context!(engine, context, "assign_constraint", assign_constraint_i64, (a: &mut Cell, b: i64));
+    register_with_context!(engine, context, "constrain_equal", constrain_equal, (a: &mut Cell, b: Cell));
+    register_with_context!(engine, context, "constrain_equal", constrain_constant_string, (a: &mut Cell, b: String));
+    register_with_context!(engine, context, "constrain_equal", constrain_constant_i64, (a: &mut Cell, b: i64));
+    register_with_context!(engine, context, "assign_common", assign_common_string, (a: &mut Cell, b: String));
+    register_with_context!(engine, context, "assign_common", assign_common_ce, (a: &mut Cell, b: CellExpression));
+    register_with_context!(engine, context, "assign_common", assign_common_i64, (a: &mut Cell, b: i64));
+    register_with_context!(engine, context, "push", push_column_i64, (a: &mut Column, b: i64));
+    register_with_context!(engine, context, "push", push_column_ce, (a: &mut Column, b: CellExpression));
+    register_with_context!(engine, context, "fill", fill_column_range, (a: &mut Column, b: std::ops::Range<i64>));
+    register_with_context!(engine, context, "fill", fill_column_range_inclusive, (a: &mut Column, b: std::ops::RangeInclusive<i64>));
+    register_with_context!(engine, context, "fill", fill_column_array, (a: &mut Column, b: Array));
+    register_with_context!(engine, context, "enable_selector", enable_selector, (a: &mut Cell));
+    register_with_context!(engine, context, "lookup", lookup, (name: String, ces: Array, cols: Array));
+    register_with_context!(engine, context, "lookup", lookup_without_name, (ces: Array, cols: Array));
+    register_with_context!(engine, context, "is_zero", is_zero, (a: Cell));
+    register_with_context!(engine, context, "define_gate", define_gate, (name: String, columns: Array, constraints: Array));
 }
 
 // a <== b
-fn assign_constraint(a: &mut Cell, b: Cell) -> Cell {
+fn assign_constraint(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: Cell,
+) -> Result<Cell, Box<EvalAltResult>> {
     // println!("assign_constraint({:#?}, {:#?})", a, b);
-    a.value = b.value.clone();
-    push_instruction_to_last_region(match (a.column.ctype, b.column.ctype) {
+    let instructions = match (a.column.ctype, b.column.ctype) {
         (ColumnType::Advice, ColumnType::Instance) => {
             vec![Instruction::AssignAdviceFromInstance(a.clone(), b.clone())]
         }
+        // an assigned cell, of any region, is copied to the public value
+        (ColumnType::Instance, ColumnType::Advice) if cs.cells.contains_key(&b.name) => {
+            vec![Instruction::ConstrainEqual(b.clone(), a.clone())]
+        }
         (ColumnType::Instance, ColumnType::Advice) => {
             vec![Instruction::AssignAdviceFromInstance(b.clone(), a.clone())]
         }
-        (ColumnType::Advice, ColumnType::Advice) => {
+        (ColumnType::Instance, ColumnType::Fixed) => {
+            check_assigned(cs, &b)?;
+            vec![Instruction::ConstrainEqual(b.clone(), a.clone())]
+        }
+        (ColumnType::Advice, ColumnType::Advice | ColumnType::Fixed) => {
+            check_assigned(cs, &b)?;
             vec![
                 Instruction::AssignAdvice(a.clone(), CellExpression::CellValue(b.clone())),
                 Instruction::ConstrainEqual(a.clone(), b.clone()),
             ]
         }
-        (_, _) => todo!(),
-    });
-    a.clone()
+        (ColumnType::Fixed, ColumnType::Fixed) => {
+            check_assigned(cs, &b)?;
+            vec![
+                Instruction::AssignFixed(a.clone(), CellExpression::CellValue(b.clone())),
+                Instruction::ConstrainEqual(a.clone(), b.clone()),
+            ]
+        }
+        (x, y) => {
+            return Err(EngineError::unsupported(format!(
+                "cannot constrain a {:?} cell to a {:?} cell",
+                x, y
+            )))
+        }
+    };
+    a.value = b.value.clone();
+    push_instruction_to_last_region(cs, instructions);
+    Ok(a.clone())
 }
 
 // a <== b (b is expresion, e.g. b1 + b2)
-fn assign_constraint_cell_ce(a: &mut Cell, b: CellExpression) -> Cell {
+fn assign_constraint_cell_ce(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: CellExpression,
+) -> Result<Cell, Box<EvalAltResult>> {
     // println!("assign_constraint({:?}, {:?})", a, b);
-    a.value = b.to_value_string();
-    push_instruction_to_last_region(vec![Instruction::AssignAdvice(a.clone(), b.clone())]);
+    a.value = cs.field.value_string(&b);
+    push_instruction_to_last_region(cs, vec![Instruction::AssignAdvice(a.clone(), b.clone())]);
 
-    // set gate
-    let (selector, index) = upsert_gate(
-        None, // TODO: gate name should come from code
-        CellExpression::Sum(
-            Box::new(CellExpression::Negated(Box::new(
-                a.clone().to_cell_expression(),
-            ))),
-            Box::new(b.clone()),
+    let target = a.clone().to_cell_expression(cs);
+    let exp = match quotient(cs, &b) {
+        // a <== n / d is checked as a * d - n, d * inverse - 1 is already constrained
+        Some((numerator, denominator)) => CellExpression::Sum(
+            Box::new(CellExpression::Product(
+                Box::new(target),
+                Box::new(CellExpression::CellValue(denominator)),
+            )),
+            Box::new(CellExpression::Negated(Box::new(numerator))),
         ),
-    )
-    .unwrap();
+        None => CellExpression::Sum(
+            Box::new(CellExpression::Negated(Box::new(target))),
+            Box::new(b),
+        ),
+    };
+    constrain_zero(cs, exp)?;
+
+    Ok(a.clone())
+}
+
+// numerator and denominator of an expression built by `/` out of a witnessed inverse
+fn quotient(
+    cs: &SimplifiedConstraitSystem,
+    exp: &CellExpression,
+) -> Option<(CellExpression, Cell)> {
+    let (numerator, inverse) = match exp {
+        CellExpression::Product(n, i) => match i.as_ref() {
+            CellExpression::CellValue(i) => (n.as_ref().clone(), i),
+            _ => return None,
+        },
+        CellExpression::Scaled(i, n) => match i.as_ref() {
+            CellExpression::CellValue(i) => (CellExpression::Constant(n.clone()), i),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let denominator = cs.inverses.get(&inverse.name)?;
+    Some((numerator, denominator.clone()))
+}
+
+// makes `exp` zero in the current row with a gate, returns the enabled selector cell
+pub(crate) fn constrain_zero(
+    cs: &mut SimplifiedConstraitSystem,
+    exp: CellExpression,
+) -> Result<Cell, Box<EvalAltResult>> {
+    // the selector adds one to the degree of the gate
+    let exp = match cs.max_degree() {
+        Some(max) if exp.degree() + 1 > max => {
+            let row = exp.get_base_index();
+            reduce_degree(cs, exp, max - 1, row)?
+        }
+        _ => exp,
+    };
+    let (selector, index) = upsert_gate(
+        cs, None, // TODO: gate name should come from code
+        exp,
+    )?;
 
     // enable selector
     let enable = Cell {
         column: selector.clone(),
-        name: get_field_name(&selector, index),
+        name: get_field_name(cs, &selector, index),
         value: Some("1".to_string()),
-        index: index,
+        index,
     };
-    if let Some(region) = unsafe { CONTEXT.regions.last_mut() } {
+    if let Some(region) = cs.regions.last_mut() {
         region
             .instructions
             .push(Instruction::EnableSelector(enable.clone()));
     }
+    Ok(enable)
+}
 
-    a.clone()
+// products of `exp` above degree `limit` are split, a factor is witnessed in
+// an intermediate cell, in the row `row` of the first advice column `deg_<n>`
+// with that row free, and constrained equal to the factor by a gate of its own
+fn reduce_degree(
+    cs: &mut SimplifiedConstraitSystem,
+    exp: CellExpression,
+    limit: usize,
+    row: i64,
+) -> Result<CellExpression, Box<EvalAltResult>> {
+    if exp.degree() <= limit {
+        return Ok(exp);
+    }
+    let mut reduce = |exp| reduce_degree(cs, exp, limit, row);
+    Ok(match exp {
+        CellExpression::Negated(n) => CellExpression::Negated(Box::new(reduce(*n)?)),
+        CellExpression::Sum(a, b) => {
+            CellExpression::Sum(Box::new(reduce(*a)?), Box::new(reduce(*b)?))
+        }
+        CellExpression::Scaled(a, b) => CellExpression::Scaled(Box::new(reduce(*a)?), b),
+        CellExpression::Product(a, b) => {
+            let (mut a, mut b) = (reduce(*a)?, reduce(*b)?);
+            // the factor of the higher degree goes first, a limit of 2 at least
+            // leaves a product of two cells
+            while a.degree() + b.degree() > limit {
+                if a.degree() >= b.degree() {
+                    a = witness_intermediate(cs, a, row)?;
+                } else {
+                    b = witness_intermediate(cs, b, row)?;
+                }
+            }
+            CellExpression::Product(Box::new(a), Box::new(b))
+        }
+        exp => exp,
+    })
+}
+
+fn witness_intermediate(
+    cs: &mut SimplifiedConstraitSystem,
+    exp: CellExpression,
+    row: i64,
+) -> Result<CellExpression, Box<EvalAltResult>> {
+    let value = cs
+        .field
+        .value_string(&exp)
+        .ok_or_else(|| EngineError::script(format!("value of {} is unknown", exp.to_string())))?;
+    let mut n = 0;
+    let column = loop {
+        let column = Column {
+            name: format!("deg_{}", n),
+            ctype: ColumnType::Advice,
+            stype: SpecialType::None,
+        };
+        match cs.columns.iter().find(|c| c.name == column.name) {
+            Some(c) if c.ctype != ColumnType::Advice => {
+                return Err(EngineError::script(format!(
+                    "column {} is taken, it cannot hold intermediate cells of max_degree",
+                    c.name
+                )))
+            }
+            Some(_) if cs.cells.contains_key(&get_field_name(cs, &column, row)) => n += 1,
+            Some(_) => break column,
+            None => {
+                cs.columns.push(column.clone());
+                break column;
+            }
+        }
+    };
+    let mut cell = column.get_field(cs, row);
+    cell.value = Some(value);
+    push_instruction_to_last_region(
+        cs,
+        vec![Instruction::AssignAdvice(cell.clone(), exp.clone())],
+    );
+    cs.cells.insert(cell.name.clone(), cell.clone());
+    let cell = CellExpression::CellValue(cell);
+    constrain_zero(
+        cs,
+        CellExpression::Sum(
+            Box::new(CellExpression::Negated(Box::new(cell.clone()))),
+            Box::new(exp),
+        ),
+    )?;
+    Ok(cell)
+}
+
+/// Witnesses the inverse of `a` (zero if `a` is zero) in the row of `a` of the
+/// advice column `<column>_inv`, created when first needed.
+///
+/// Nothing is constrained yet, see `operator_divide` and [`is_zero`].
+pub(crate) fn witness_inverse(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &Cell,
+) -> Result<Cell, Box<EvalAltResult>> {
+    let value = a
+        .value
+        .as_ref()
+        .ok_or_else(|| EngineError::script(format!("value of {} is unknown", a.name)))?;
+    if !cs.field.is_element(value) {
+        return Err(EngineError::script(format!("{} is not a number", value)));
+    }
+    let inverse = cs.field.inverse(value).unwrap_or_else(|| "0".to_string());
+
+    let column = auxiliary_advice_column(cs, &a.column, "inv")?;
+    let mut cell = column.get_field(cs, a.index);
+    cell.value = Some(inverse.clone());
+    push_instruction_to_last_region(
+        cs,
+        vec![Instruction::AssignAdvice(
+            cell.clone(),
+            CellExpression::Constant(inverse),
+        )],
+    );
+    cs.cells.insert(cell.name.clone(), cell.clone());
+    cs.inverses.insert(cell.name.clone(), a.clone());
+    Ok(cell)
+}
+
+// is_zero(a) is 1 when a is zero and 0 otherwise, with inv the witnessed
+// inverse it is 1 - a * inv, and a * (1 - a * inv) = 0 makes it honest
+fn is_zero(
+    cs: &mut SimplifiedConstraitSystem,
+    a: Cell,
+) -> Result<CellExpression, Box<EvalAltResult>> {
+    let inverse = witness_inverse(cs, &a)?;
+    let a = a.to_cell_expression(cs);
+    let result = CellExpression::Sum(
+        Box::new(CellExpression::Constant("1".to_string())),
+        Box::new(CellExpression::Negated(Box::new(CellExpression::Product(
+            Box::new(a.clone()),
+            Box::new(CellExpression::CellValue(inverse)),
+        )))),
+    );
+    constrain_zero(
+        cs,
+        CellExpression::Product(Box::new(a), Box::new(result.clone())),
+    )?;
+    Ok(result)
 }
 
 fn upsert_gate(
+    cs: &mut SimplifiedConstraitSystem,
     name: Option<String>,
     exp: CellExpression,
 ) -> Result<(Column, i64), Box<EvalAltResult>> {
@@ -96,26 +320,28 @@ fn upsert_gate(
 
     // println!("upsert_gate({:#?})", exp);
     let base_index = exp.get_base_index();
+    let exp = copy_fixed_cells(cs, exp, base_index)?;
     let exp = if base_index > 0 {
         exp.to_base_index(base_index)
     } else {
         exp
     };
-    check_gate_ce(&exp)?;
+    // `b + a` and `a + b`, or `2 * x` and `x * 2`, share their gate
+    let exp = cs.field.canonicalize(&exp);
+    check_gate_ce(cs.field, &exp)?;
     let exp_str = exp.to_string();
-    unsafe {
-        let gate = CONTEXT.gates.iter().find(|(_, n, _, _)| n == &exp_str);
-        match gate {
-            Some((_, _, col, _)) => {
-                return Ok((col.clone(), base_index));
-            }
-            None => {}
-        }
+    let gate = cs.gates.iter().find(|(_, n, _, _)| n == &exp_str);
+    if let Some((_, _, col, _)) = gate {
+        return Ok((col.clone(), base_index));
     }
 
     let name = match name {
         Some(n) => n,
-        None => format!("gate_{}", unsafe { CONTEXT.gates.len() }),
+        // declared gates may have taken a `gate_N` name
+        None => (cs.gates.len()..)
+            .map(|i| format!("gate_{}", i))
+            .find(|n| !cs.gates.iter().any(|g| &g.0 == n))
+            .unwrap_or_default(),
     };
 
     let selector = Column {
@@ -124,108 +350,416 @@ fn upsert_gate(
         stype: SpecialType::None,
     };
     let result = CellExpression::Product(
-        Box::new(CellExpression::CellValue(selector.clone().get_field(0))),
+        Box::new(CellExpression::CellValue(selector.get_field(cs, 0))),
         Box::new(exp),
     );
 
-    unsafe {
-        CONTEXT
-            .gates
-            .push((name, exp_str, selector.clone(), result));
-        CONTEXT.columns.push(selector.clone());
-    }
+    cs.gate_positions.insert(name.clone(), cs.position);
+    cs.gates.push((name, exp_str, selector.clone(), result));
+    cs.columns.push(selector.clone());
 
     Ok((selector, base_index))
 }
 
+// gate name(a, b) { ... }
+// every constraint is a polynomial of its own under the selector `name`, which
+// is returned so the script can enable the gate with `name[row] <-- enable`
+fn define_gate(
+    cs: &mut SimplifiedConstraitSystem,
+    name: String,
+    columns: Array,
+    constraints: Array,
+) -> Result<Column, Box<EvalAltResult>> {
+    if cs.columns.iter().any(|c| c.name == name) || cs.gates.iter().any(|g| g.0 == name) {
+        return Err(EngineError::script(format!(
+            "gate `{}` is already defined, or a column has its name",
+            name
+        )));
+    }
+    let columns = columns
+        .into_iter()
+        .map(|x| {
+            x.try_cast::<Column>().ok_or_else(|| {
+                EngineError::unsupported(format!("parameters of gate `{}` must be columns", name))
+            })
+        })
+        .collect::<Result<Vec<Column>, Box<EvalAltResult>>>()?;
+    let constraints = constraints
+        .into_iter()
+        .map(|x| {
+            let exp = if x.is::<Cell>() {
+                x.cast::<Cell>().to_cell_expression(cs)
+            } else {
+                x.try_cast::<CellExpression>().ok_or_else(|| {
+                    EngineError::unsupported(format!(
+                        "constraints of gate `{}` must be expressions of its columns",
+                        name
+                    ))
+                })?
+            };
+            check_gate_columns(&name, &exp, &columns)?;
+            check_declared_gate_ce(cs.field, &exp)?;
+            Ok(exp.without_values())
+        })
+        .collect::<Result<Vec<CellExpression>, Box<EvalAltResult>>>()?;
+
+    let selector = Column {
+        name: name.clone(),
+        ctype: ColumnType::Selector,
+        stype: SpecialType::None,
+    };
+    for exp in constraints {
+        let key = declared_gate_key(&name, &exp);
+        let exp = CellExpression::Product(
+            Box::new(CellExpression::CellValue(selector.get_field(cs, 0))),
+            Box::new(exp),
+        );
+        // only `<==` knows the cells to split a polynomial into
+        match cs.max_degree() {
+            Some(max) if exp.degree() > max => {
+                return Err(EngineError::gate(format!(
+                    "gate `{}` has degree {}, above max_degree {}, declared gates are not split",
+                    name,
+                    exp.degree(),
+                    max
+                )))
+            }
+            _ => {}
+        }
+        cs.gates.push((name.clone(), key, selector.clone(), exp));
+    }
+    cs.gate_positions.insert(name, cs.position);
+    cs.columns.push(selector.clone());
+    Ok(selector)
+}
+
+// halo2 queries fixed columns at the gate's own row only, fixed cells of other
+// rows are copied into the advice column `<column>_copy` and used from there
+fn copy_fixed_cells(
+    cs: &mut SimplifiedConstraitSystem,
+    exp: CellExpression,
+    base_index: i64,
+) -> Result<CellExpression, Box<EvalAltResult>> {
+    let mut copy = |exp| copy_fixed_cells(cs, exp, base_index);
+    Ok(match exp {
+        CellExpression::CellValue(c)
+            if c.column.ctype == ColumnType::Fixed && c.index != base_index =>
+        {
+            let column = auxiliary_advice_column(cs, &c.column, "copy")?;
+            let mut cell = column.get_field(cs, c.index);
+            cell.value = c.value.clone();
+            let value = c
+                .value
+                .clone()
+                .ok_or_else(|| EngineError::script(format!("value of {} is unknown", c.name)))?;
+            push_instruction_to_last_region(
+                cs,
+                vec![
+                    Instruction::AssignAdvice(cell.clone(), CellExpression::Constant(value)),
+                    Instruction::ConstrainEqual(cell.clone(), c),
+                ],
+            );
+            cs.cells.insert(cell.name.clone(), cell.clone());
+            CellExpression::CellValue(cell)
+        }
+        CellExpression::Negated(n) => CellExpression::Negated(Box::new(copy(*n)?)),
+        CellExpression::Product(a, b) => {
+            CellExpression::Product(Box::new(copy(*a)?), Box::new(copy(*b)?))
+        }
+        CellExpression::Sum(a, b) => CellExpression::Sum(Box::new(copy(*a)?), Box::new(copy(*b)?)),
+        CellExpression::Scaled(a, b) => CellExpression::Scaled(Box::new(copy(*a)?), b),
+        exp => exp,
+    })
+}
+
+// the advice column `<column>_<suffix>` holding cells derived from `column`,
+// created when first needed
+pub(crate) fn auxiliary_advice_column(
+    cs: &mut SimplifiedConstraitSystem,
+    column: &Column,
+    suffix: &str,
+) -> Result<Column, Box<EvalAltResult>> {
+    let name = format!("{}_{}", column.name, suffix);
+    match cs.columns.iter().find(|c| c.name == name) {
+        Some(c) if c.ctype == ColumnType::Advice => Ok(c.clone()),
+        Some(_) => Err(EngineError::script(format!(
+            "column {} is taken, it cannot hold cells derived from {}",
+            name, column.name
+        ))),
+        None => {
+            let column = Column {
+                name,
+                ctype: ColumnType::Advice,
+                stype: SpecialType::None,
+            };
+            cs.columns.push(column.clone());
+            Ok(column)
+        }
+    }
+}
+
 // a === b
-fn constrain_equal(a: &mut Cell, b: Cell) {
+// returns `a` unchanged, as the transpiled statement assigns the result back to it
+fn constrain_equal(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: Cell,
+) -> Result<Cell, Box<EvalAltResult>> {
     // println!("constrain_equal({:#?}, {:#?})", a, b);
-    push_instruction_to_last_region(vec![Instruction::ConstrainEqual(a.clone(), b.clone())]);
+    check_assigned(cs, a)?;
+    check_assigned(cs, &b)?;
+    if a.column.ctype == ColumnType::Instance && b.column.ctype == ColumnType::Instance {
+        return Err(EngineError::unsupported(format!(
+            "cannot constrain instance cells {} and {} equal, copy one of them into an advice cell first",
+            a.name, b.name
+        )));
+    }
+    push_instruction_to_last_region(cs, vec![Instruction::ConstrainEqual(a.clone(), b.clone())]);
+    Ok(a.clone())
+}
+
+// copy constraints take cells that are assigned by then, in any region, or
+// public values
+fn check_assigned(cs: &SimplifiedConstraitSystem, cell: &Cell) -> Result<(), Box<EvalAltResult>> {
+    match cell.column.ctype {
+        ColumnType::Instance => Ok(()),
+        ColumnType::Advice | ColumnType::Fixed if cs.cells.contains_key(&cell.name) => Ok(()),
+        ColumnType::Advice | ColumnType::Fixed => Err(EngineError::script(format!(
+            "cell {} is used in a copy constraint before it is assigned",
+            cell.name
+        ))),
+        o => Err(EngineError::unsupported(format!(
+            "{:?} cell {} cannot be copy constrained",
+            o, cell.name
+        ))),
+    }
 }
 
-fn assign_constraint_string(a: &mut Cell, b: String) -> Cell {
+// a === 5
+// the cell must have been assigned, it is copy constrained to the constants column
+fn constrain_constant_string(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: String,
+) -> Result<Cell, Box<EvalAltResult>> {
+    check_constant(cs.field, &b)?;
+    if !matches!(a.column.ctype, ColumnType::Advice | ColumnType::Fixed) {
+        return Err(EngineError::unsupported(format!(
+            "cannot constrain a {:?} cell to a constant",
+            a.column.ctype
+        )));
+    }
+    check_assigned(cs, a)?;
+    push_instruction_to_last_region(cs, vec![Instruction::ConstrainConstant(a.clone(), b)]);
+    Ok(a.clone())
+}
+
+fn constrain_constant_i64(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: i64,
+) -> Result<Cell, Box<EvalAltResult>> {
+    constrain_constant_string(cs, a, b.to_string())
+}
+
+// a <== 5
+// advice cells are assigned from the constants column, so the value is constrained
+fn assign_constraint_string(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: String,
+) -> Result<Cell, Box<EvalAltResult>> {
     // println!("assign_constraint({:?}, {:?})", a, b);
-    let cb = CellExpression::Constant(b);
-    a.value = cb.to_value_string();
-    push_instruction_to_last_region(vec![Instruction::AssignAdvice(a.clone(), cb)]);
-    a.clone()
+    check_constant(cs.field, &b)?;
+    let instruction = match a.column.ctype {
+        ColumnType::Advice => Instruction::AssignAdviceFromConstant(a.clone(), b.clone()),
+        // fixed cells are constants already
+        ColumnType::Fixed => {
+            Instruction::AssignFixed(a.clone(), CellExpression::Constant(b.clone()))
+        }
+        o => {
+            return Err(EngineError::unsupported(format!(
+                "cannot constrain a {:?} cell to a constant",
+                o
+            )))
+        }
+    };
+    a.value = cs.field.value_string(&CellExpression::Constant(b));
+    push_instruction_to_last_region(cs, vec![instruction]);
+    Ok(a.clone())
 }
 
-fn assign_common_string(a: &mut Cell, b: String) -> Result<Cell, Box<EvalAltResult>> {
+fn assign_constraint_i64(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: i64,
+) -> Result<Cell, Box<EvalAltResult>> {
+    assign_constraint_string(cs, a, b.to_string())
+}
+
+fn assign_common_string(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: String,
+) -> Result<Cell, Box<EvalAltResult>> {
     match a.column.ctype {
         ColumnType::Fixed => {
+            check_constant(cs.field, &b)?;
             let cb = CellExpression::Constant(b);
-            a.value = cb.to_value_string();
-            push_instruction_to_last_region(vec![Instruction::AssignFixed(a.clone(), cb)]);
+            a.value = cs.field.value_string(&cb);
+            push_instruction_to_last_region(cs, vec![Instruction::AssignFixed(a.clone(), cb)]);
             Ok(a.clone())
         }
         ColumnType::Instance => {
             let cb = CellExpression::Constant(b);
-            a.value = cb.to_value_string();
+            a.value = cs.field.value_string(&cb);
             Ok(a.clone())
             //warning
         }
         ColumnType::Advice => {
+            check_constant(cs.field, &b)?;
             let cb = CellExpression::Constant(b);
-            a.value = cb.to_value_string();
-            push_instruction_to_last_region(vec![Instruction::AssignAdvice(a.clone(), cb)]);
+            a.value = cs.field.value_string(&cb);
+            push_instruction_to_last_region(cs, vec![Instruction::AssignAdvice(a.clone(), cb)]);
             Ok(a.clone())
         }
-        o => Err(format!("unsupported column type {:?}", o).into()),
+        o => Err(EngineError::unsupported(format!(
+            "cannot assign to a {:?} cell",
+            o
+        ))),
     }
 }
 
-fn assign_common_ce(a: &mut Cell, b: CellExpression) -> Result<Cell, Box<EvalAltResult>> {
+// values end up in the circuit as field elements
+fn check_constant(field: FieldKind, value: &String) -> Result<(), Box<EvalAltResult>> {
+    if field.is_element(value) {
+        Ok(())
+    } else {
+        Err(EngineError::script(format!(
+            "{} is not a field element",
+            value
+        )))
+    }
+}
+
+fn assign_common_ce(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: CellExpression,
+) -> Result<Cell, Box<EvalAltResult>> {
     match a.column.ctype {
         ColumnType::Fixed => {
-            a.value = b.to_value_string();
-            push_instruction_to_last_region(vec![Instruction::AssignFixed(a.clone(), b)]);
+            a.value = cs.field.value_string(&b);
+            push_instruction_to_last_region(cs, vec![Instruction::AssignFixed(a.clone(), b)]);
             Ok(a.clone())
         }
         ColumnType::Advice => {
-            a.value = b.to_value_string();
-            push_instruction_to_last_region(vec![Instruction::AssignAdvice(a.clone(), b)]);
+            a.value = cs.field.value_string(&b);
+            push_instruction_to_last_region(cs, vec![Instruction::AssignAdvice(a.clone(), b)]);
             Ok(a.clone())
         }
-        o => todo!("{:?}", o),
+        o => Err(EngineError::unsupported(format!(
+            "cannot assign an expression to a {:?} cell",
+            o
+        ))),
     }
 }
 
-fn assign_common_i64(a: &mut Cell, b: i64) -> Result<Cell, Box<EvalAltResult>> {
-    assign_common_string(a, b.to_string())
+fn assign_common_i64(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Cell,
+    b: i64,
+) -> Result<Cell, Box<EvalAltResult>> {
+    assign_common_string(cs, a, b.to_string())
+}
+
+fn push_column_i64(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Column,
+    b: i64,
+) -> Result<(), Box<EvalAltResult>> {
+    push_column(cs, a, b.to_string())
+}
+
+fn push_column_ce(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Column,
+    b: CellExpression,
+) -> Result<(), Box<EvalAltResult>> {
+    let value = cs
+        .field
+        .value_string(&b)
+        .ok_or_else(|| EngineError::script(format!("value of {} is unknown", b.to_string())))?;
+    push_column(cs, a, value)
 }
 
-fn push_column_i64(a: &mut Column, b: i64) -> Result<(), Box<EvalAltResult>> {
-    push_column(a, b.to_string())
+// t.fill(0..256)
+fn fill_column_range(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Column,
+    b: std::ops::Range<i64>,
+) -> Result<(), Box<EvalAltResult>> {
+    b.map(|x| push_column_i64(cs, a, x)).collect()
 }
 
-fn push_column_ce(a: &mut Column, b: CellExpression) -> Result<(), Box<EvalAltResult>> {
-    push_column(a, b.to_value_string().unwrap())
+fn fill_column_range_inclusive(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Column,
+    b: std::ops::RangeInclusive<i64>,
+) -> Result<(), Box<EvalAltResult>> {
+    b.map(|x| push_column_i64(cs, a, x)).collect()
 }
 
-fn push_column(a: &mut Column, b: String) -> Result<(), Box<EvalAltResult>> {
+// t.fill([1, 2, "0x03"])
+fn fill_column_array(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Column,
+    b: Array,
+) -> Result<(), Box<EvalAltResult>> {
+    for x in b {
+        if x.is::<i64>() {
+            push_column_i64(cs, a, x.cast::<i64>())?;
+        } else if x.is::<Cell>() {
+            let ce = x.cast::<Cell>().to_cell_expression(cs);
+            push_column_ce(cs, a, ce)?;
+        } else if x.is::<CellExpression>() {
+            push_column_ce(cs, a, x.cast::<CellExpression>())?;
+        } else if x.is_string() {
+            let value = x.into_string()?;
+            check_constant(cs.field, &value)?;
+            push_column(cs, a, value)?;
+        } else {
+            return Err(EngineError::unsupported(format!(
+                "cannot fill lookup column {} with a {}",
+                a.name,
+                x.type_name()
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn push_column(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &mut Column,
+    b: String,
+) -> Result<(), Box<EvalAltResult>> {
     match a.ctype {
         ColumnType::TableLookup => {
             let ins = vec![Instruction::AssignCell(a.clone(), b)];
 
-            let table = unsafe {
-                let table = CONTEXT.tables.iter_mut().find(|n| n.name == a.name);
-                match table {
-                    Some(t) => t,
-                    None => {
-                        let ib = InstructionBundle {
-                            id: CONTEXT.tables.len() as i64,
-                            name: a.name.clone(),
-                            instructions: vec![],
-                        };
-                        CONTEXT.tables.push(ib);
-                        let table = CONTEXT.tables.iter_mut().find(|n| n.name == a.name);
-                        match table {
-                            Some(t) => t,
-                            None => panic!("abnormal! table not found after inserted"),
-                        }
-                    }
+            let position = cs.tables.iter().position(|n| n.name == a.name);
+            let table = match position {
+                Some(p) => &mut cs.tables[p],
+                None => {
+                    let ib = InstructionBundle {
+                        id: cs.tables.len() as i64,
+                        name: a.name.clone(),
+                        instructions: vec![],
+                        position: cs.position,
+                    };
+                    cs.tables.push(ib);
+                    cs.tables.last_mut().unwrap()
                 }
             };
             for i in ins {
@@ -234,22 +768,28 @@ fn push_column(a: &mut Column, b: String) -> Result<(), Box<EvalAltResult>> {
 
             Ok(())
         }
-        o => todo!("{:?}", o),
+        o => Err(EngineError::unsupported(format!(
+            "cannot push to a {:?} column, only lookup columns are filled with push",
+            o
+        ))),
     }
 }
 
-fn push_instruction_to_last_region(a: Vec<Instruction>) {
-    if let Some(region) = unsafe { CONTEXT.regions.last_mut() } {
+pub(crate) fn push_instruction_to_last_region(
+    cs: &mut SimplifiedConstraitSystem,
+    a: Vec<Instruction>,
+) {
+    if let Some(region) = cs.regions.last_mut() {
         for i in a {
             region.instructions.push(i);
         }
     }
 }
 
-fn enable_selector(a: &mut Cell) {
+fn enable_selector(cs: &mut SimplifiedConstraitSystem, a: &mut Cell) {
     // println!("enable_selector({:?})", a);
     a.value = Some("1".to_string());
-    if let Some(region) = unsafe { CONTEXT.regions.last_mut() } {
+    if let Some(region) = cs.regions.last_mut() {
         region
             .instructions
             .push(Instruction::EnableSelector(a.clone()));
@@ -257,21 +797,73 @@ fn enable_selector(a: &mut Cell) {
 }
 
 // fn lookup(ces: Vec<CellExpression>, cols: Vec<Column>) -> Result<(), Box<EvalAltResult>> {
-fn lookup_without_name(ces: Array, cols: Array) -> Result<(), Box<EvalAltResult>> {
-    lookup("default".to_string(), ces, cols)
+fn lookup_without_name(
+    cs: &mut SimplifiedConstraitSystem,
+    ces: Array,
+    cols: Array,
+) -> Result<(), Box<EvalAltResult>> {
+    let mut n = cs.lookups.len();
+    while cs.lookups.iter().any(|l| l.name == format!("lookup_{}", n)) {
+        n += 1;
+    }
+    lookup(cs, format!("lookup_{}", n), ces, cols)
 }
 
-fn lookup(name: String, ces: Array, cols: Array) -> Result<(), Box<EvalAltResult>> {
+fn lookup(
+    cs: &mut SimplifiedConstraitSystem,
+    name: String,
+    ces: Array,
+    cols: Array,
+) -> Result<(), Box<EvalAltResult>> {
+    if cs.lookups.iter().any(|l| l.name == name) {
+        return Err(EngineError::script(format!(
+            "lookup `{}` is already defined",
+            name
+        )));
+    }
     let ces = ces
         .into_iter()
-        .map(|x| x.try_cast::<CellExpression>().unwrap())
-        .collect::<Vec<CellExpression>>();
+        .map(|x| {
+            let ce = if x.is::<Cell>() {
+                x.cast::<Cell>().to_cell_expression(cs)
+            } else {
+                x.try_cast::<CellExpression>().ok_or_else(|| {
+                    EngineError::unsupported("lookup inputs must be cells or expressions")
+                })?
+            };
+            check_lookup_ce(cs.field, &ce)?;
+            Ok(ce)
+        })
+        .collect::<Result<Vec<CellExpression>, Box<EvalAltResult>>>()?;
     let cols = cols
         .into_iter()
-        .map(|x| x.try_cast::<Column>().unwrap())
-        .collect::<Vec<Column>>();
+        .map(|x| match x.try_cast::<Column>() {
+            Some(col) if col.ctype == ColumnType::TableLookup => Ok(col),
+            Some(col) if col.ctype == ColumnType::Fixed => Ok(fixed_table_column(cs, &col)),
+            Some(col) if col.ctype == ColumnType::Advice => Ok(advice_table_column(cs, &col)),
+            Some(col) => Err(EngineError::unsupported(format!(
+                "column {} is not a lookup column",
+                col.name
+            ))),
+            None => Err(EngineError::unsupported(
+                "lookup tables must be lookup, fixed or advice columns",
+            )),
+        })
+        .collect::<Result<Vec<Column>, Box<EvalAltResult>>>()?;
     if ces.len() != cols.len() {
-        return Err("ces and cols length not match".into());
+        return Err(EngineError::script(format!(
+            "lookup of {} expressions into {} columns",
+            ces.len(),
+            cols.len()
+        )));
+    }
+    if cols.iter().any(|c| c.ctype == ColumnType::Advice)
+        && cols.iter().any(|c| c.ctype != ColumnType::Advice)
+    {
+        return Err(EngineError::unsupported(format!(
+            "lookup `{}` mixes advice columns with lookup or fixed columns as tables",
+            name
+        )));
     }
 
     let mut map = Vec::<(CellExpression, Column)>::new();
@@ -279,8 +871,182 @@ fn lookup(name: String, ces: Array, cols: Array) -> Result<(), Box<EvalAltResult
         map.push((ce, col));
     }
 
-    unsafe {
-        CONTEXT.lookups.push(LookupParameter { name, map });
+    cs.lookups.push(LookupParameter {
+        name,
+        map,
+        position: cs.position,
+    });
+    Ok(())
+}
+
+// halo2 looks up table columns only, a fixed column used as a table is copied
+// into the table column `<column>_table` once the script has assigned it
+fn fixed_table_column(cs: &mut SimplifiedConstraitSystem, column: &Column) -> Column {
+    let table = Column {
+        name: format!("{}_table", column.name),
+        ctype: ColumnType::TableLookup,
+        stype: SpecialType::None,
+    };
+    if !cs.fixed_tables.contains_key(&table.name) {
+        cs.fixed_tables
+            .insert(table.name.clone(), column.name.clone());
+        cs.columns.push(table.clone());
+    }
+    table
+}
+
+// an advice column used as a table is copied into the advice column
+// `<column>_table` once the script has assigned it, halo2 looks it up as an
+// expression gated by its selector, see `Column::table_selector`
+fn advice_table_column(cs: &mut SimplifiedConstraitSystem, column: &Column) -> Column {
+    let table = Column {
+        name: format!("{}_table", column.name),
+        ctype: ColumnType::Advice,
+        stype: SpecialType::None,
+    };
+    if !cs.advice_tables.contains_key(&table.name) {
+        cs.advice_tables
+            .insert(table.name.clone(), column.name.clone());
+        cs.columns.push(table.clone());
+        cs.columns.push(table.table_selector());
+    }
+    table
+}
+
+// region the advice tables are copied to, after every region they copy from
+const ADVICE_TABLES_REGION: &str = "advice_tables";
+
+/// Fills the tables copied from fixed and advice columns and checks that the
+/// table columns of every lookup have the same number of rows.
+///
+/// It runs once the script is done, as tables may be filled after the lookup
+/// using them is declared.
+pub fn build_lookup_tables(cs: &mut SimplifiedConstraitSystem) -> Result<(), Box<EvalAltResult>> {
+    let mut fixed_tables = cs.fixed_tables.iter().collect::<Vec<_>>();
+    fixed_tables.sort();
+    let mut copies = vec![];
+    for (table, fixed) in fixed_tables {
+        // rows in the order they are assigned, a reassigned cell keeps its row
+        let mut values = Vec::<(&String, String)>::new();
+        for instruction in cs.regions.iter().flat_map(|r| &r.instructions) {
+            if let Instruction::AssignFixed(cell, exp) = instruction {
+                if cell.column.name != *fixed {
+                    continue;
+                }
+                let value = cs.field.value_string(exp).ok_or_else(|| {
+                    EngineError::script(format!("value of {} is unknown", cell.name))
+                })?;
+                match values.iter_mut().find(|(name, _)| *name == &cell.name) {
+                    Some(row) => row.1 = value,
+                    None => values.push((&cell.name, value)),
+                }
+            }
+        }
+        let column = Column {
+            name: table.clone(),
+            ctype: ColumnType::TableLookup,
+            stype: SpecialType::None,
+        };
+        copies.push((
+            column,
+            values.into_iter().map(|(_, v)| v).collect::<Vec<_>>(),
+        ));
+    }
+    for (mut column, values) in copies {
+        for value in values {
+            push_column(cs, &mut column, value)?;
+        }
+    }
+
+    let mut advice_tables = cs.advice_tables.clone().into_iter().collect::<Vec<_>>();
+    advice_tables.sort();
+    let mut advice_rows = HashMap::<String, usize>::new();
+    if !advice_tables.is_empty() {
+        let sources = advice_tables
+            .into_iter()
+            .map(|(table, advice)| {
+                // cells in the order they are assigned, a reassigned cell keeps its row
+                let mut cells = Vec::<Cell>::new();
+                for instruction in cs.regions.iter().flat_map(|r| &r.instructions) {
+                    let cell = match instruction {
+                        Instruction::AssignAdvice(cell, _)
+                        | Instruction::AssignAdviceFromConstant(cell, _)
+                        | Instruction::AssignAdviceFromInstance(cell, _) => cell,
+                        _ => continue,
+                    };
+                    if cell.column.name == advice && !cells.iter().any(|c| c.name == cell.name) {
+                        cells.push(cs.cells.get(&cell.name).unwrap_or(cell).clone());
+                    }
+                }
+                (table, cells)
+            })
+            .collect::<Vec<_>>();
+        super::define_region(cs, ADVICE_TABLES_REGION.to_string());
+        for (table, cells) in sources {
+            let column = Column {
+                name: table,
+                ctype: ColumnType::Advice,
+                stype: SpecialType::None,
+            };
+            let selector = column.table_selector();
+            for (row, source) in cells.iter().enumerate() {
+                let mut cell = column.get_field(cs, row as i64);
+                cell.value = source.value.clone();
+                let mut enabled = selector.get_field(cs, row as i64);
+                enabled.value = Some("1".to_string());
+                push_instruction_to_last_region(
+                    cs,
+                    vec![
+                        Instruction::AssignAdvice(
+                            cell.clone(),
+                            CellExpression::CellValue(source.clone()),
+                        ),
+                        Instruction::ConstrainEqual(cell.clone(), source.clone()),
+                        Instruction::EnableSelector(enabled),
+                    ],
+                );
+                cs.cells.insert(cell.name.clone(), cell);
+            }
+            advice_rows.insert(column.name, cells.len());
+        }
+    }
+
+    let rows = |column: &Column| match column.ctype {
+        ColumnType::Advice => advice_rows.get(&column.name).copied().unwrap_or(0),
+        _ => cs
+            .tables
+            .iter()
+            .find(|t| t.name == column.name)
+            .map_or(0, |t| t.instructions.len()),
+    };
+    for lookup in &cs.lookups {
+        let raise = |message: String| {
+            let mut error = EngineError::script(message);
+            error.set_position(lookup.position);
+            Err(error)
+        };
+        let lengths = lookup
+            .map
+            .iter()
+            .map(|(_, col)| (col, rows(col)))
+            .collect::<Vec<_>>();
+        if let Some((col, _)) = lengths.iter().find(|(_, n)| *n == 0) {
+            return raise(format!(
+                "table column {} of lookup `{}` is empty",
+                col.name, lookup.name
+            ));
+        }
+        if lengths.windows(2).any(|w| w[0].1 != w[1].1) {
+            return raise(format!(
+                "table columns of lookup `{}` have different lengths: {}",
+                lookup.name,
+                lengths
+                    .iter()
+                    .map(|(col, n)| format!("{} has {} rows", col.name, n))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
     }
     Ok(())
 }
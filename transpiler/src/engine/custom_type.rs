@@ -1,22 +1,30 @@
+use rhai::EvalAltResult;
+
+use crate::error::EngineError;
+use crate::session::Context;
 use crate::system::*;
-use crate::CONTEXT;
 
-pub fn register_custom_type(engine: &mut rhai::Engine) {
+pub fn register_custom_type(engine: &mut rhai::Engine, context: &Context) {
+    register_with_context!(engine, context, "next", next_row, (col: Column));
+
+    let (getter, setter) = (context.clone(), context.clone());
     let _ = &mut engine
         .register_type_with_name::<Column>("Column")
-        .register_indexer_get(Column::get_field)
-        .register_indexer_set(Column::set_field)
+        .register_indexer_get(move |col: &mut Column, index: i64| {
+            col.get_field(&getter.borrow(), index)
+        })
+        .register_indexer_set(move |col: &mut Column, index: i64, value: Cell| {
+            col.set_field(&mut setter.borrow_mut(), index, value)
+        })
         .register_type_with_name::<Cell>("Cell")
         .register_get("value", Cell::get_value);
 }
 
 impl Column {
-    pub fn get_field(&mut self, index: i64) -> Cell {
-        let name = get_field_name(&self, index);
-        unsafe {
-            if CONTEXT.cells.contains_key(&name) {
-                return CONTEXT.cells[&name].clone();
-            }
+    pub fn get_field(&self, cs: &SimplifiedConstraitSystem, index: i64) -> Cell {
+        let name = get_field_name(cs, self, index);
+        if let Some(cell) = cs.cells.get(&name) {
+            return cell.clone();
         }
 
         Cell {
@@ -26,28 +34,48 @@ impl Column {
             column: self.clone(),
         }
     }
-    pub fn set_field(&mut self, index: i64, value: Cell) {
-        let name = get_field_name(&self, index);
+    pub fn set_field(&mut self, cs: &mut SimplifiedConstraitSystem, index: i64, value: Cell) {
+        let name = get_field_name(cs, self, index);
         let cell = Cell {
             name: name.clone(),
             index,
             value: value.value,
             ..value
         };
-        unsafe {
-            let entry = CONTEXT.cells.entry(name).or_insert(cell.clone()); //.and_modify(||cell);
-            *entry = cell;
-        }
+        let entry = cs.cells.entry(name).or_insert(cell.clone()); //.and_modify(||cell);
+        *entry = cell;
     }
 }
 
+// a.next() is the row after the last cell of `a` assigned in the current region,
+// 0 when there is none, so a[a.next()] (written a[$]) is its next free cell.
+// Cells count once the indexer stores them, `a[$] <== x` asks for the row twice,
+// before and after `assign_constraint`, and gets the same one.
+fn next_row(cs: &mut SimplifiedConstraitSystem, col: Column) -> Result<i64, Box<EvalAltResult>> {
+    if !matches!(col.ctype, ColumnType::Advice | ColumnType::Fixed) {
+        return Err(EngineError::unsupported(format!(
+            "{:?} column {} has no next row, only advice and fixed columns do",
+            col.ctype, col.name
+        )));
+    }
+    Ok(cs
+        .cells
+        .values()
+        .filter(|c| c.column.name == col.name && c.name == get_field_name(cs, &col, c.index))
+        .map(|c| c.index + 1)
+        .max()
+        .unwrap_or(0))
+}
+
 impl Cell {
-    fn get_value(&mut self) -> String {
-        self.value.clone().unwrap()
+    fn get_value(&mut self) -> Result<String, Box<EvalAltResult>> {
+        self.value
+            .clone()
+            .ok_or_else(|| EngineError::script(format!("value of {} is unknown", self.name)))
     }
 }
 
-pub fn get_field_name(col: &Column, index: i64) -> String {
-    let region = unsafe { CONTEXT.regions.last().unwrap().clone() };
+pub fn get_field_name(cs: &SimplifiedConstraitSystem, col: &Column, index: i64) -> String {
+    let region = cs.regions.last().unwrap();
     format!("{}[{}]_{}_{}", col.name, index, region.name, region.id)
-}
\ No newline at end of file
+}
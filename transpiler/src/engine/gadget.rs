@@ -0,0 +1,201 @@
+use num_bigint::BigUint;
+use num_traits::One;
+use rhai::EvalAltResult;
+
+use crate::error::EngineError;
+use crate::session::Context;
+use crate::system::*;
+
+use super::bind::{
+    auxiliary_advice_column, constrain_zero, push_column, push_instruction_to_last_region,
+};
+
+/// bits of a limb when `range_check` is not given a limb width, its table
+/// has 16 rows, so it fits in circuits of `k` 5 and more
+pub const RANGE_CHECK_LIMB_BITS: i64 = 4;
+
+// the largest limb table, 2^16 rows
+const MAX_LIMB_BITS: i64 = 16;
+
+pub fn register_gadget(engine: &mut rhai::Engine, context: &Context) {
+    register_with_context!(engine, context, "range_check", range_check, (a: Cell, bits: i64));
+    register_with_context!(engine, context, "range_check", range_check_with_limbs, (a: Cell, bits: i64, limb_bits: i64));
+}
+
+fn range_check(
+    cs: &mut SimplifiedConstraitSystem,
+    a: Cell,
+    bits: i64,
+) -> Result<(), Box<EvalAltResult>> {
+    range_check_with_limbs(cs, a, bits, RANGE_CHECK_LIMB_BITS)
+}
+
+// range_check(a[0], bits, w) constrains a[0] < 2^bits
+//
+// a[0] is split into limbs of w bits, the j-th limb in the row of a[0] of the
+// advice column `a_limb_j`, and a gate recombines them. Every limb column is
+// looked up in the table `range_w` of 0..2^w, a column-wide lookup, as rows
+// without limbs hold 0. A top limb of r < w bits is looked up once more
+// shifted left by w - r bits, from the column `a_limb_j_shl_(w - r)`, which
+// only fits the table when the limb is below 2^r.
+fn range_check_with_limbs(
+    cs: &mut SimplifiedConstraitSystem,
+    a: Cell,
+    bits: i64,
+    limb_bits: i64,
+) -> Result<(), Box<EvalAltResult>> {
+    if a.column.ctype != ColumnType::Advice {
+        return Err(EngineError::unsupported(format!(
+            "range_check takes advice cells, {} is a {:?} cell",
+            a.name, a.column.ctype
+        )));
+    }
+    if !(1..=MAX_LIMB_BITS).contains(&limb_bits) {
+        return Err(EngineError::script(format!(
+            "limbs of range_check have 1 to {} bits, not {}",
+            MAX_LIMB_BITS, limb_bits
+        )));
+    }
+    // the recombined limbs must stay below the modulus of the field
+    if !(1..=254).contains(&bits) {
+        return Err(EngineError::script(format!(
+            "range_check takes 1 to 254 bits, not {}",
+            bits
+        )));
+    }
+    if !cs.cells.contains_key(&a.name) {
+        return Err(EngineError::script(format!(
+            "cell {} is range checked before it is assigned",
+            a.name
+        )));
+    }
+    let value = a
+        .value
+        .as_ref()
+        .and_then(|v| cs.field.canonical(v))
+        .and_then(|v| BigUint::parse_bytes(v.trim_start_matches("0x").as_bytes(), 16))
+        .ok_or_else(|| EngineError::script(format!("value of {} is unknown", a.name)))?;
+
+    let table = range_table(cs, limb_bits)?;
+    let limbs = (bits + limb_bits - 1) / limb_bits;
+    let mask = (BigUint::one() << limb_bits as usize) - BigUint::one();
+    let mut recombined = CellExpression::Negated(Box::new(CellExpression::CellValue(a.clone())));
+    for j in 0..limbs {
+        let shift = (j * limb_bits) as usize;
+        // a value out of range is left in the top limb, where the lookup fails
+        let limb_value = match j == limbs - 1 {
+            true => &value >> shift,
+            false => (&value >> shift) & &mask,
+        };
+        let limb = witness_limb(cs, &a, &format!("limb_{}", j), &limb_value)?;
+        look_up_column(cs, &limb.column, &table);
+        recombined = CellExpression::Sum(
+            Box::new(recombined),
+            Box::new(scaled(CellExpression::CellValue(limb.clone()), shift)),
+        );
+
+        let top_bits = bits - j * limb_bits;
+        if top_bits < limb_bits {
+            let shl = (limb_bits - top_bits) as usize;
+            let shifted = witness_limb(
+                cs,
+                &a,
+                &format!("limb_{}_shl_{}", j, shl),
+                &(&limb_value << shl),
+            )?;
+            look_up_column(cs, &shifted.column, &table);
+            constrain_zero(
+                cs,
+                CellExpression::Sum(
+                    Box::new(CellExpression::CellValue(shifted)),
+                    Box::new(CellExpression::Negated(Box::new(scaled(
+                        CellExpression::CellValue(limb),
+                        shl,
+                    )))),
+                ),
+            )?;
+        }
+    }
+    constrain_zero(cs, recombined)?;
+    Ok(())
+}
+
+// exp * 2^shift
+fn scaled(exp: CellExpression, shift: usize) -> CellExpression {
+    match shift {
+        0 => exp,
+        _ => CellExpression::Scaled(Box::new(exp), (BigUint::one() << shift).to_string()),
+    }
+}
+
+// the table column `range_<bits>` holding 0..2^bits, shared by every range check
+// with limbs of that width
+fn range_table(
+    cs: &mut SimplifiedConstraitSystem,
+    bits: i64,
+) -> Result<Column, Box<EvalAltResult>> {
+    let name = format!("range_{}", bits);
+    match cs.columns.iter().find(|c| c.name == name) {
+        Some(c)
+            if c.ctype == ColumnType::TableLookup
+                && cs
+                    .tables
+                    .iter()
+                    .any(|t| t.name == name && t.instructions.len() == 1 << bits) =>
+        {
+            Ok(c.clone())
+        }
+        Some(_) => Err(EngineError::script(format!(
+            "column {} is taken, it cannot hold the table of range_check",
+            name
+        ))),
+        None => {
+            let mut column = Column {
+                name,
+                ctype: ColumnType::TableLookup,
+                stype: SpecialType::None,
+            };
+            cs.columns.push(column.clone());
+            for i in 0..(1_i64 << bits) {
+                push_column(cs, &mut column, i.to_string())?;
+            }
+            Ok(column)
+        }
+    }
+}
+
+// assigns `value` to the cell in the row of `a` of the column `<a's column>_<suffix>`
+fn witness_limb(
+    cs: &mut SimplifiedConstraitSystem,
+    a: &Cell,
+    suffix: &str,
+    value: &BigUint,
+) -> Result<Cell, Box<EvalAltResult>> {
+    let column = auxiliary_advice_column(cs, &a.column, suffix)?;
+    let mut cell = column.get_field(cs, a.index);
+    let value = value.to_string();
+    cell.value = cs.field.value_string(&value);
+    push_instruction_to_last_region(
+        cs,
+        vec![Instruction::AssignAdvice(
+            cell.clone(),
+            CellExpression::Constant(value),
+        )],
+    );
+    cs.cells.insert(cell.name.clone(), cell.clone());
+    Ok(cell)
+}
+
+// limb columns are looked up once, whichever range checks use them
+fn look_up_column(cs: &mut SimplifiedConstraitSystem, column: &Column, table: &Column) {
+    let name = format!("range_check_{}", column.name);
+    if cs.lookups.iter().any(|l| l.name == name) {
+        return;
+    }
+    let input = CellExpression::CellValue(column.get_field(cs, 0));
+    cs.lookups.push(LookupParameter {
+        name,
+        map: vec![(input, table.clone())],
+        position: cs.position,
+    });
+}
@@ -1,55 +1,231 @@
-use std::io;
-
 use rhai::EvalAltResult;
 
+use crate::engine::DEFAULT_INSTANCE_COLUMN_NAME;
+use crate::error::EngineError;
 use crate::system::*;
 
-pub fn check_gate_ce(exp: &CellExpression) -> Result<(), Box<EvalAltResult>> {
+// where an expression is used decides which cells it may query
+#[derive(Clone, Copy, PartialEq)]
+enum Usage {
+    Gate,
+    // rows of a declared gate are chosen by the script, so instance cells
+    // are queried relative to them
+    DeclaredGate,
+    // lookups may also be gated by a selector
+    Lookup,
+}
+
+pub fn check_gate_ce(field: FieldKind, exp: &CellExpression) -> Result<(), Box<EvalAltResult>> {
+    check_ce(field, exp, Usage::Gate)
+}
+
+pub fn check_declared_gate_ce(
+    field: FieldKind,
+    exp: &CellExpression,
+) -> Result<(), Box<EvalAltResult>> {
+    check_ce(field, exp, Usage::DeclaredGate)
+}
+
+pub fn check_lookup_ce(field: FieldKind, exp: &CellExpression) -> Result<(), Box<EvalAltResult>> {
+    check_ce(field, exp, Usage::Lookup)
+}
+
+fn check_ce(
+    field: FieldKind,
+    exp: &CellExpression,
+    usage: Usage,
+) -> Result<(), Box<EvalAltResult>> {
     match exp {
-        CellExpression::Calculated(_) => create_error(
-            io::ErrorKind::Unsupported,
-            "Calculated cell (no operators than +/*/- is allowed in gate) cannot be converted to gate.",
-        ),
-        CellExpression::Constant(_) => Ok(()),
+        CellExpression::Calculated(_) => create_error("Calculated cell (no operators than +/*/- is allowed in gate) cannot be converted to gate."),
+        CellExpression::Constant(c) => check_constant(field, c),
         CellExpression::CellValue(c) => match c.column.ctype {
-            crate::system::ColumnType::Selector => create_error(
-                io::ErrorKind::Unsupported,
-                "Selector cannot be used in gate",
-            ),
-            crate::system::ColumnType::Advice => Ok(()),
+            crate::system::ColumnType::Selector | crate::system::ColumnType::ComplexSelector
+                if usage == Usage::Lookup =>
+            {
+                Ok(())
+            }
+            crate::system::ColumnType::Selector => create_error("Selector cannot be used in gate"),
+            crate::system::ColumnType::Advice => check_rotation(c),
             crate::system::ColumnType::Fixed => match c.index {
                 0 => Ok(()),
-                _ => create_error(
-                    io::ErrorKind::Unsupported,
-                    "Fixed column cannot have rotation in gate, refer to https://github.com/zcash/halo2/issues/585",
-                ),
+                i => create_error(&format!(
+                    "fixed column {} cannot be queried at rotation {}, only the current row is supported, refer to https://github.com/zcash/halo2/issues/585",
+                    c.column.name, i
+                )),
             },
-                
+            crate::system::ColumnType::Instance if usage == Usage::DeclaredGate => check_rotation(c),
             crate::system::ColumnType::Instance => create_error(
-                io::ErrorKind::Unsupported,
-                "Instance cannot be used in gate",
+                "Instance cannot be used in gate, query instance columns in a declared gate",
             ),
 
-            crate::system::ColumnType::ComplexSelector => create_error(
-                io::ErrorKind::Unsupported,
-                "Complex selector cannot be used in gate",
-            ),
+            crate::system::ColumnType::ComplexSelector => create_error("Complex selector cannot be used in gate"),
 
-            crate::system::ColumnType::TableLookup => create_error(
-                io::ErrorKind::Unsupported,
-                "Lookup cannot be used in gate",
-            ),
+            crate::system::ColumnType::TableLookup => create_error("Lookup cannot be used in gate"),
         },
-        CellExpression::Negated(n) => check_gate_ce(&*n),
-        CellExpression::Product(a, b) => check_gate_ce(&*a).and(check_gate_ce(&*b)),
-        CellExpression::Sum(a, b) => check_gate_ce(&*a).and(check_gate_ce(&*b)),
-        CellExpression::Scaled(a, _) => check_gate_ce(&*a),
+        CellExpression::Negated(n) => check_ce(field, n, usage),
+        CellExpression::Product(a, b) => check_ce(field, a, usage).and(check_ce(field, b, usage)),
+        CellExpression::Sum(a, b) => check_ce(field, a, usage).and(check_ce(field, b, usage)),
+        CellExpression::Scaled(a, b) => check_ce(field, a, usage).and(check_constant(field, b)),
+    }
+}
+
+// cells of a declared gate must come from the columns it is declared with
+pub fn check_gate_columns(
+    gate: &str,
+    exp: &CellExpression,
+    columns: &[Column],
+) -> Result<(), Box<EvalAltResult>> {
+    match exp {
+        CellExpression::CellValue(c) if !columns.iter().any(|col| col.name == c.column.name) => {
+            create_error(&format!(
+                "column {} is not a parameter of gate `{}`",
+                c.column.name, gate
+            ))
+        }
+        CellExpression::Negated(n) => check_gate_columns(gate, n, columns),
+        CellExpression::Product(a, b) | CellExpression::Sum(a, b) => {
+            check_gate_columns(gate, a, columns).and(check_gate_columns(gate, b, columns))
+        }
+        CellExpression::Scaled(a, _) => check_gate_columns(gate, a, columns),
+        _ => Ok(()),
+    }
+}
+
+// any rotation halo2 can express
+fn check_rotation(c: &Cell) -> Result<(), Box<EvalAltResult>> {
+    match c.index {
+        i if i32::try_from(i).is_ok() => Ok(()),
+        i => create_error(&format!(
+            "rotation {} of {:?} column {} is out of range",
+            i, c.column.ctype, c.column.name
+        )),
+    }
+}
+
+fn check_constant(field: FieldKind, c: &String) -> Result<(), Box<EvalAltResult>> {
+    if field.is_element(c) {
+        Ok(())
+    } else {
+        create_error(&format!("{} is not a field element", c))
+    }
+}
+
+fn create_error(err: &str) -> Result<(), Box<EvalAltResult>> {
+    Err(EngineError::gate(format!("gate check error: {}", err)))
+}
+
+/// Checks that `MyCircuit::configure` can turn every gate and lookup of `cs`
+/// into halo2 expressions, so that it never fails on what a script built.
+///
+/// Expressions are checked as they are created, this also covers the gates
+/// merged afterwards and the columns their cells come from.
+pub fn check_circuit(cs: &SimplifiedConstraitSystem) -> Result<(), Box<EvalAltResult>> {
+    let at = |result: Result<(), Box<EvalAltResult>>, position: Option<&rhai::Position>| {
+        result.map_err(|mut error| {
+            if let Some(position) = position {
+                error.set_position(*position);
+            }
+            error
+        })
+    };
+    for (name, polys) in cs.gate_groups() {
+        for (i, exp) in polys.into_iter().enumerate() {
+            let position = cs
+                .gate_positions
+                .get(&format!("{}.{}", name, i))
+                .or_else(|| cs.gate_positions.get(name));
+            at(check_configurable(cs, exp), position)?;
+        }
+    }
+    for lookup in &cs.lookups {
+        for (exp, table) in &lookup.map {
+            at(check_configurable(cs, exp), Some(&lookup.position))?;
+            // an advice table is read along with its selector
+            let declared = match table.ctype {
+                ColumnType::Advice => {
+                    is_declared(cs, table, &[ColumnType::Advice])
+                        && is_declared(cs, &table.table_selector(), &[ColumnType::ComplexSelector])
+                }
+                _ => is_declared(cs, table, &[ColumnType::TableLookup]),
+            };
+            if !declared {
+                at(
+                    create_error(&format!(
+                        "lookup `{}` looks up {:?} column {}, which is not a declared table column",
+                        lookup.name, table.ctype, table.name
+                    )),
+                    Some(&lookup.position),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// what `convert_to_gate_expression` of the circuit accepts
+fn check_configurable(
+    cs: &SimplifiedConstraitSystem,
+    exp: &CellExpression,
+) -> Result<(), Box<EvalAltResult>> {
+    match exp {
+        CellExpression::Calculated(c) => {
+            create_error(&format!("calculated value {} cannot be queried", c))
+        }
+        CellExpression::Constant(c) => check_constant(cs.field, c),
+        CellExpression::CellValue(c) => {
+            let types: &[ColumnType] = match c.column.ctype {
+                ColumnType::Selector | ColumnType::ComplexSelector => {
+                    &[ColumnType::Selector, ColumnType::ComplexSelector]
+                }
+                ColumnType::Advice => {
+                    check_rotation(c)?;
+                    &[ColumnType::Advice]
+                }
+                ColumnType::Fixed if c.index != 0 => {
+                    return create_error(&format!(
+                        "fixed column {} cannot be queried at rotation {}",
+                        c.column.name, c.index
+                    ))
+                }
+                ColumnType::Fixed => &[ColumnType::Fixed],
+                ColumnType::Instance => {
+                    check_rotation(c)?;
+                    &[ColumnType::Instance]
+                }
+                ColumnType::TableLookup => {
+                    return create_error(&format!(
+                        "table column {} cannot be queried",
+                        c.column.name
+                    ))
+                }
+            };
+            match is_declared(cs, &c.column, types) {
+                true => Ok(()),
+                false => create_error(&format!(
+                    "{:?} column {} of cell {} is not declared",
+                    c.column.ctype, c.column.name, c.name
+                )),
+            }
+        }
+        CellExpression::Negated(n) => check_configurable(cs, n),
+        CellExpression::Product(a, b) | CellExpression::Sum(a, b) => {
+            check_configurable(cs, a).and(check_configurable(cs, b))
+        }
+        CellExpression::Scaled(a, b) => check_configurable(cs, a).and(check_constant(cs.field, b)),
     }
 }
 
-fn create_error(kind: io::ErrorKind, err: &str) -> Result<(), Box<EvalAltResult>> {
-    Err(Box::new(EvalAltResult::ErrorSystem(
-        "gate check error".to_string(),
-        Box::new(io::Error::new(kind, err)),
-    )))
+// columns `configure` creates, the default instance column included when it is used
+fn is_declared(cs: &SimplifiedConstraitSystem, column: &Column, types: &[ColumnType]) -> bool {
+    let default_instance = column.name == DEFAULT_INSTANCE_COLUMN_NAME
+        && types.contains(&ColumnType::Instance)
+        && cs
+            .instance_columns()
+            .first()
+            .is_some_and(|(name, _)| name == DEFAULT_INSTANCE_COLUMN_NAME);
+    default_instance
+        || cs
+            .columns
+            .iter()
+            .any(|c| c.name == column.name && types.contains(&c.ctype))
 }
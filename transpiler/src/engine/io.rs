@@ -1,152 +1,256 @@
-use crate::system::cell_expression::ToValueString;
+use rhai::EvalAltResult;
+
+use crate::error::EngineError;
+use crate::session::Context;
+use crate::system::cell_expression::ToString;
 use crate::system::*;
-use crate::CONTEXT;
 
 pub const DEFAULT_INSTANCE_COLUMN_NAME: &str = "defins";
+/// name of the fixed column holding the constants cells are bound to
+pub const CONSTANTS_COLUMN_NAME: &str = "constants";
+/// `#` parameter choosing the field of the circuit
+pub const FIELD_PARAMETER: &str = "field";
+/// `#` parameter bounding the degree of the gates made by `<==`
+pub const MAX_DEGREE_PARAMETER: &str = "max_degree";
+
+pub fn register_io(engine: &mut rhai::Engine, context: &Context) {
+    register_with_context!(engine, context, "init_input", init_input, (v: &str));
+    register_with_context!(engine, context, "init_input", init_input_in, (v: &str, column: Column));
+    register_with_context!(engine, context, "init_output", init_output, (v: String));
+    register_with_context!(engine, context, "init_output", init_output_in, (v: String, column: Column));
+    register_with_context!(engine, context, "set_output", set_output, (name: String, cell: Cell));
+    register_with_context!(engine, context, "init_advice_column", init_advice_column, (v: String));
+    register_with_context!(engine, context, "init_selector_column", init_selector_column, (v: String));
+    register_with_context!(engine, context, "init_fixed_column", init_fixed_column, (v: String));
+    register_with_context!(engine, context, "init_table_column", init_table_column, (v: String));
+    register_with_context!(engine, context, "init_instance_column", init_instance_column, (v: String));
+    register_with_context!(engine, context, "enable_equality", enable_equality, (column: Column));
+    register_with_context!(engine, context, "set_parameter", set_parameter, (name: String, v: String));
+    register_with_context!(engine, context, "set_parameter", set_parameter_i64, (name: String, v: i64));
+    register_with_context!(engine, context, "raw", raw_ce, (c: CellExpression));
+    register_with_context!(engine, context, "inspect", inspect_ce, (obj: CellExpression));
 
-pub fn register_io(engine: &mut rhai::Engine) {
     let _ = &mut engine
-        .register_fn("init_input", init_input)
-        .register_fn("init_output", init_output)
-        .register_fn("set_output", set_output)
-        .register_fn("init_advice_column", init_advice_column)
-        .register_fn("init_selector_column", init_selector_column)
-        .register_fn("init_fixed_column", init_fixed_column)
-        .register_fn("init_table_column", init_table_column)
-        .register_fn("raw", raw_ce)
         .register_fn("raw", raw_c)
-        .register_fn("set_parameter", set_parameter)
-        .register_fn("set_parameter", set_parameter_i64)
         .register_fn("inspect", inspect)
-        .register_fn("inspect", inspect_ce)
         .register_fn("inspect", inspect_str);
 }
 
-fn get_lastest_instance_index() -> i64 {
-    let a = unsafe { CONTEXT.instance_count };
-    unsafe { CONTEXT.instance_count += 1 };
-    a
+// signals take the rows of their instance column in the order they are declared
+fn next_instance_row(cs: &mut SimplifiedConstraitSystem, column: &str) -> i64 {
+    cs.instance_count += 1;
+    cs.signals
+        .iter()
+        .filter(|x| x.column.name == column)
+        .count() as i64
 }
 
-fn init_input(v: &str) -> Cell {
-    // println!("init_input({})", v);
-    let cell = Cell {
-        name: v.to_string(),
-        index: get_lastest_instance_index(),
-        value: unsafe { Some(CONTEXT.inputs[v].clone()) },
-        column: Column {
-            name: DEFAULT_INSTANCE_COLUMN_NAME.to_string(),
-            ctype: ColumnType::Instance,
-            stype: SpecialType::Input,
-        },
-    };
-    unsafe {
-        CONTEXT.signals.push(cell.clone());
+fn default_instance_column() -> Column {
+    Column {
+        name: DEFAULT_INSTANCE_COLUMN_NAME.to_string(),
+        ctype: ColumnType::Instance,
+        stype: SpecialType::None,
     }
-    cell
 }
-fn init_output(v: String) -> Cell {
-    // println!("init_output({})", v);
+
+fn init_signal(
+    cs: &mut SimplifiedConstraitSystem,
+    name: &str,
+    column: Column,
+    stype: SpecialType,
+) -> Result<Cell, Box<EvalAltResult>> {
+    if column.ctype != ColumnType::Instance {
+        return Err(EngineError::unsupported(format!(
+            "signal {} cannot be placed in {:?} column {}, only in an instance column",
+            name, column.ctype, column.name
+        )));
+    }
+    let value = match stype {
+        // a missing value is reported once the public inputs are collected
+        SpecialType::Input => cs.inputs.get(name).cloned(),
+        _ => None,
+    };
     let cell = Cell {
-        name: v.to_string(),
-        index: get_lastest_instance_index(),
-        value: None,
-        column: Column {
-            name: DEFAULT_INSTANCE_COLUMN_NAME.to_string(),
-            ctype: ColumnType::Instance,
-            stype: SpecialType::Output,
-        },
+        name: name.to_string(),
+        index: next_instance_row(cs, &column.name),
+        value,
+        column: Column { stype, ..column },
     };
-    unsafe {
-        CONTEXT.signals.push(cell.clone());
-    }
-    cell
+    cs.signals.push(cell.clone());
+    Ok(cell)
 }
-fn set_output(name: String, cell: Cell) {
-    unsafe {
-        if let Some(pos) = CONTEXT.signals.iter().position(|x| x.name == name) {
-            CONTEXT.signals.splice(pos..(pos + 1), vec![cell]);
-        }
+
+fn init_input(cs: &mut SimplifiedConstraitSystem, v: &str) -> Result<Cell, Box<EvalAltResult>> {
+    init_signal(cs, v, default_instance_column(), SpecialType::Input)
+}
+
+fn init_input_in(
+    cs: &mut SimplifiedConstraitSystem,
+    v: &str,
+    column: Column,
+) -> Result<Cell, Box<EvalAltResult>> {
+    init_signal(cs, v, column, SpecialType::Input)
+}
+
+fn init_output(cs: &mut SimplifiedConstraitSystem, v: String) -> Result<Cell, Box<EvalAltResult>> {
+    init_signal(cs, &v, default_instance_column(), SpecialType::Output)
+}
+
+fn init_output_in(
+    cs: &mut SimplifiedConstraitSystem,
+    v: String,
+    column: Column,
+) -> Result<Cell, Box<EvalAltResult>> {
+    init_signal(cs, &v, column, SpecialType::Output)
+}
+
+fn set_output(cs: &mut SimplifiedConstraitSystem, name: String, cell: Cell) {
+    if let Some(pos) = cs.signals.iter().position(|x| x.name == name) {
+        cs.signals.splice(pos..(pos + 1), vec![cell]);
     }
 }
-fn init_advice_column(v: String) -> Column {
+fn init_advice_column(cs: &mut SimplifiedConstraitSystem, v: String) -> Column {
     // println!("init_advice_column({})", v);
     let col = Column {
         name: v.to_string(),
         ctype: ColumnType::Advice,
         stype: SpecialType::None,
     };
-    unsafe {
-        CONTEXT.columns.push(col.clone());
-    }
+    cs.columns.push(col.clone());
     col
 }
 
-fn init_selector_column(v: String) -> Column {
+fn init_selector_column(cs: &mut SimplifiedConstraitSystem, v: String) -> Column {
     // println!("init_selector_column({})", v);
     let col = Column {
         name: v.to_string(),
         ctype: ColumnType::ComplexSelector,
         stype: SpecialType::None,
     };
-    unsafe {
-        CONTEXT.columns.push(col.clone());
-    }
+    cs.columns.push(col.clone());
     col
 }
 
-fn init_fixed_column(v: String) -> Column {
+fn init_fixed_column(cs: &mut SimplifiedConstraitSystem, v: String) -> Column {
     // println!("init_fixed_column({})", v);
     let col = Column {
         name: v.to_string(),
         ctype: ColumnType::Fixed,
         stype: SpecialType::None,
     };
-    unsafe {
-        CONTEXT.columns.push(col.clone());
-    }
+    cs.columns.push(col.clone());
     col
 }
 
-fn init_table_column(v: String) -> Column {
+fn init_table_column(cs: &mut SimplifiedConstraitSystem, v: String) -> Column {
     // println!("init_table_column({})", v);
     let col = Column {
         name: v.to_string(),
         ctype: ColumnType::TableLookup,
         stype: SpecialType::None,
     };
-    unsafe {
-        CONTEXT.columns.push(col.clone());
-    }
+    cs.columns.push(col.clone());
     col
 }
 
-fn set_parameter_i64(name: String, v: i64) {
-    set_parameter(name, v.to_string())
+// signals without a column go to the default one, so its name is taken
+fn init_instance_column(
+    cs: &mut SimplifiedConstraitSystem,
+    v: String,
+) -> Result<Column, Box<EvalAltResult>> {
+    if v == DEFAULT_INSTANCE_COLUMN_NAME {
+        return Err(EngineError::script(format!(
+            "{} is the name of the default instance column",
+            v
+        )));
+    }
+    let col = Column {
+        name: v.to_string(),
+        ctype: ColumnType::Instance,
+        stype: SpecialType::None,
+    };
+    cs.columns.push(col.clone());
+    Ok(col)
+}
+
+// copy constraints enable equality on their own, this forces it
+fn enable_equality(
+    cs: &mut SimplifiedConstraitSystem,
+    column: Column,
+) -> Result<(), Box<EvalAltResult>> {
+    if !matches!(
+        column.ctype,
+        ColumnType::Advice | ColumnType::Fixed | ColumnType::Instance
+    ) {
+        return Err(EngineError::unsupported(format!(
+            "equality cannot be enabled on {:?} column {}",
+            column.ctype, column.name
+        )));
+    }
+    if !cs.equality_columns.contains(&column.name) {
+        cs.equality_columns.push(column.name);
+    }
+    Ok(())
 }
 
-fn set_parameter(name: String, v: String) {
-    unsafe {
-        CONTEXT.inputs.insert(name, v);
+fn set_parameter_i64(
+    cs: &mut SimplifiedConstraitSystem,
+    name: String,
+    v: i64,
+) -> Result<(), Box<EvalAltResult>> {
+    set_parameter(cs, name, v.to_string())
+}
+
+fn set_parameter(
+    cs: &mut SimplifiedConstraitSystem,
+    name: String,
+    v: String,
+) -> Result<(), Box<EvalAltResult>> {
+    let v = cs.overrides.get(&name).cloned().unwrap_or(v);
+    if name == FIELD_PARAMETER {
+        let field = v.parse::<FieldKind>().map_err(EngineError::unsupported)?;
+        // values computed so far were reduced in the previous field
+        if field != cs.field && !(cs.signals.is_empty() && cs.columns.is_empty()) {
+            return Err(EngineError::script(
+                "the field must be chosen before any signal or column is declared",
+            ));
+        }
+        cs.field = field;
+    }
+    // every circuit has degree 3 at least, the permutation argument needs it
+    if name == MAX_DEGREE_PARAMETER && !v.parse::<usize>().is_ok_and(|d| d >= 3) {
+        return Err(EngineError::script(format!(
+            "max_degree must be a number of 3 or more, not {}",
+            v
+        )));
     }
+    cs.inputs.insert(name, v);
+    Ok(())
 }
 
 fn inspect(obj: Cell) {
     println!("{:#?}", obj);
 }
 
-fn inspect_ce(obj: CellExpression) {
-    println!("{:#?}", obj.to_value_string());
+fn inspect_ce(cs: &mut SimplifiedConstraitSystem, obj: CellExpression) {
+    println!("{:#?}", cs.field.value_string(&obj));
 }
 
 fn inspect_str(obj: String) {
     println!("{:#?}", obj);
 }
 
-fn raw_ce(c: CellExpression) -> String {
-    c.to_value_string().unwrap()
+fn raw_ce(
+    cs: &mut SimplifiedConstraitSystem,
+    c: CellExpression,
+) -> Result<String, Box<EvalAltResult>> {
+    cs.field
+        .value_string(&c)
+        .ok_or_else(|| EngineError::script(format!("value of {} is unknown", c.to_string())))
 }
 
-fn raw_c(c: Cell) -> String {
-    c.to_value_string().unwrap()
+fn raw_c(c: Cell) -> Result<String, Box<EvalAltResult>> {
+    c.value
+        .clone()
+        .ok_or_else(|| EngineError::script(format!("value of {} is unknown", c.name)))
 }
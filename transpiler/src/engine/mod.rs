@@ -1,52 +1,83 @@
-use crate::{system::*, CONTEXT};
+use crate::{error::PlonkScriptError, session::Context, system::*};
 use std::collections::HashMap;
 
+// registers `$func` as `$name`, passing the session's constraint system as the first
+// argument of every call, with the position of the call recorded in it
+macro_rules! register_with_context {
+    ($eng: expr, $ctx: expr, $name: expr, $func: path, ($($arg: ident: $ty: ty),*)) => {{
+        let context = $ctx.clone();
+        $eng.register_fn($name, move |call: rhai::NativeCallContext, $($arg: $ty),*| {
+            let mut cs = context.borrow_mut();
+            cs.position = call.position();
+            $func(&mut cs, $($arg),*)
+        });
+    }};
+}
+
 pub mod bind;
 pub mod custom_type;
+pub mod gadget;
 pub mod gate;
 pub mod io;
 pub mod operator;
 pub mod resolver;
 
 use self::{
-    bind::register_bind, custom_type::register_custom_type, io::register_io,
-    operator::register_operator, resolver::register_module_resolver,
+    bind::register_bind, custom_type::register_custom_type, gadget::register_gadget,
+    io::register_io, operator::register_operator, resolver::register_module_resolver,
+};
+pub use io::{
+    CONSTANTS_COLUMN_NAME, DEFAULT_INSTANCE_COLUMN_NAME, FIELD_PARAMETER, MAX_DEGREE_PARAMETER,
 };
-pub use io::DEFAULT_INSTANCE_COLUMN_NAME;
 
 pub trait PlonkScriptEngine {
-    fn register_plonk_script(&mut self, modules: HashMap<String, String>);
+    fn register_plonk_script(
+        &mut self,
+        context: &Context,
+        modules: HashMap<String, String>,
+    ) -> Result<(), PlonkScriptError>;
 }
 
 impl PlonkScriptEngine for rhai::Engine {
     #[warn(unused_must_use)]
-    fn register_plonk_script(&mut self, modules: HashMap<String, String>) {
+    fn register_plonk_script(
+        &mut self,
+        context: &Context,
+        modules: HashMap<String, String>,
+    ) -> Result<(), PlonkScriptError> {
         // when expression is complex, may occur ExprTooDeep error
         self.set_max_expr_depths(320, 320);
 
-        let _ = &mut self
-        .register_fn("define_region", define_region)
-        // .register_indexer_set(TestStruct::set_field)
-        ;
-        register_io(self);
-        register_bind(self);
-        register_custom_type(self);
-        register_operator(self);
+        register_with_context!(self, context, "define_region", define_region, (v: String));
+        register_with_context!(self, context, "region_rows", region_rows, ());
+        register_io(self, context);
+        register_bind(self, context);
+        register_gadget(self, context);
+        register_custom_type(self, context);
+        register_operator(self, context);
 
-        register_module_resolver(self, modules);
+        register_module_resolver(self, modules)?;
 
-        define_region("default".to_string());
+        // the default region does not come from any statement
+        let mut cs = context.borrow_mut();
+        cs.position = rhai::Position::NONE;
+        define_region(&mut cs, "default".to_string());
+        Ok(())
     }
 }
 
-fn define_region(v: String) {
+fn define_region(cs: &mut SimplifiedConstraitSystem, v: String) {
     // println!("define_region({})", v);
-    unsafe {
-        CONTEXT.regions.push(InstructionBundle {
-            name: v,
-            id: CONTEXT.regions.len() as i64,
-            instructions: vec![],
-        });
-    }
-    ()
+    cs.regions.push(InstructionBundle {
+        name: v,
+        id: cs.regions.len() as i64,
+        instructions: vec![],
+        position: cs.position,
+    });
+}
+
+// rows of the current region, which is still the last one built after its
+// function returns
+fn region_rows(cs: &mut SimplifiedConstraitSystem) -> i64 {
+    cs.regions.last().map_or(0, |r| r.rows() as i64)
 }
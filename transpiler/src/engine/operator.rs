@@ -1,73 +1,110 @@
+use halo2_proofs::halo2curves::{
+    bn256::Fr,
+    ff::PrimeField,
+    pasta::{Fp, Fq},
+};
 use num_bigint::BigInt;
 use num_traits::cast::ToPrimitive;
+use rhai::EvalAltResult;
 use std::str::FromStr;
 
-use crate::system::{cell_expression::ToValueString, *};
+use crate::engine::bind::{constrain_zero, witness_inverse};
+use crate::error::EngineError;
+use crate::session::Context;
+use crate::system::{
+    cell_expression::{field_to_string, ToField, ToString, ToValueString},
+    *,
+};
 
 // operators
 // Cell/CellExpression/Column/String/i64
+// operands are resolved against the session (columns stand for their first cell)
+// before being combined. Two i64 are left to rhai, whose fast operators take
+// them before any overload, so `6 / 4` stays integer division
 macro_rules! engine_register_ops {
-    ($eng: expr, $op: tt, $func: ident, $a:ty, $b:ty) => {
-        $eng.register_fn(stringify!($op), $func::<$a, $b>);
-    };
+    ($eng: expr, $ctx: expr, $op: tt, $func: ident, $a:ty, $b:ty) => {{
+        let context = $ctx.clone();
+        $eng.register_fn(stringify!($op), move |a: $a, b: $b| {
+            let cs = context.borrow();
+            $func(a.to_cell_expression(&cs), b.to_cell_expression(&cs))
+        });
+    }};
+}
+
+// same, for operators that add cells or gates to the session
+macro_rules! engine_register_ops_with_context {
+    ($eng: expr, $ctx: expr, $op: tt, $func: ident, $a:ty, $b:ty) => {{
+        let context = $ctx.clone();
+        $eng.register_fn(stringify!($op), move |call: rhai::NativeCallContext, a: $a, b: $b| {
+            let mut cs = context.borrow_mut();
+            cs.position = call.position();
+            let (a, b) = (a.to_cell_expression(&cs), b.to_cell_expression(&cs));
+            $func(&mut cs, a, b)
+        });
+    }};
 }
 
 macro_rules! engine_register_ops_i64 {
-    ($eng: expr, $op: tt, $func: ident, $a:ty) => {
-        $eng.register_fn($op, $func::<$a>);
-    };
+    ($eng: expr, $ctx: expr, $op: tt, $func: ident, $a:ty) => {{
+        let context = $ctx.clone();
+        $eng.register_fn($op, move |a: $a, b: i64| {
+            $func(a.to_cell_expression(&context.borrow()), b)
+        });
+    }};
 }
 
 macro_rules! engine_register_ops_types {
-    ($eng: expr, $op: tt, $func: ident) => {
-        engine_register_ops!($eng, $op, $func, Cell, Cell);
-        engine_register_ops!($eng, $op, $func, Cell, CellExpression);
-        engine_register_ops!($eng, $op, $func, Cell, String);
-        engine_register_ops!($eng, $op, $func, Cell, Column);
-        engine_register_ops!($eng, $op, $func, Cell, i64);
-
-        engine_register_ops!($eng, $op, $func, CellExpression, Cell);
-        engine_register_ops!($eng, $op, $func, CellExpression, CellExpression);
-        engine_register_ops!($eng, $op, $func, CellExpression, String);
-        engine_register_ops!($eng, $op, $func, CellExpression, Column);
-        engine_register_ops!($eng, $op, $func, CellExpression, i64);
-
-        engine_register_ops!($eng, $op, $func, String, Cell);
-        engine_register_ops!($eng, $op, $func, String, CellExpression);
-        engine_register_ops!($eng, $op, $func, String, String);
-        engine_register_ops!($eng, $op, $func, String, Column);
-        engine_register_ops!($eng, $op, $func, String, i64);
-
-        engine_register_ops!($eng, $op, $func, Column, Cell);
-        engine_register_ops!($eng, $op, $func, Column, CellExpression);
-        engine_register_ops!($eng, $op, $func, Column, String);
-        engine_register_ops!($eng, $op, $func, Column, Column);
-        engine_register_ops!($eng, $op, $func, Column, i64);
-
-        engine_register_ops!($eng, $op, $func, i64, Cell);
-        engine_register_ops!($eng, $op, $func, i64, CellExpression);
-        engine_register_ops!($eng, $op, $func, i64, String);
-        engine_register_ops!($eng, $op, $func, i64, Column);
-        engine_register_ops!($eng, $op, $func, i64, i64);
+    ($eng: expr, $ctx: expr, $op: tt, $func: ident) => {
+        engine_register_ops_types!($eng, $ctx, $op, $func, engine_register_ops);
+    };
+    ($eng: expr, $ctx: expr, $op: tt, $func: ident, $register: ident) => {
+        $register!($eng, $ctx, $op, $func, Cell, Cell);
+        $register!($eng, $ctx, $op, $func, Cell, CellExpression);
+        $register!($eng, $ctx, $op, $func, Cell, String);
+        $register!($eng, $ctx, $op, $func, Cell, Column);
+        $register!($eng, $ctx, $op, $func, Cell, i64);
+
+        $register!($eng, $ctx, $op, $func, CellExpression, Cell);
+        $register!($eng, $ctx, $op, $func, CellExpression, CellExpression);
+        $register!($eng, $ctx, $op, $func, CellExpression, String);
+        $register!($eng, $ctx, $op, $func, CellExpression, Column);
+        $register!($eng, $ctx, $op, $func, CellExpression, i64);
+
+        $register!($eng, $ctx, $op, $func, String, Cell);
+        $register!($eng, $ctx, $op, $func, String, CellExpression);
+        $register!($eng, $ctx, $op, $func, String, String);
+        $register!($eng, $ctx, $op, $func, String, Column);
+        $register!($eng, $ctx, $op, $func, String, i64);
+
+        $register!($eng, $ctx, $op, $func, Column, Cell);
+        $register!($eng, $ctx, $op, $func, Column, CellExpression);
+        $register!($eng, $ctx, $op, $func, Column, String);
+        $register!($eng, $ctx, $op, $func, Column, Column);
+        $register!($eng, $ctx, $op, $func, Column, i64);
+
+        $register!($eng, $ctx, $op, $func, i64, Cell);
+        $register!($eng, $ctx, $op, $func, i64, CellExpression);
+        $register!($eng, $ctx, $op, $func, i64, String);
+        $register!($eng, $ctx, $op, $func, i64, Column);
     };
 }
 
 macro_rules! engine_register_ops_types_i64 {
-    ($eng: expr, $op: tt, $func: ident) => {
-        engine_register_ops_i64!($eng, $op, $func, Cell);
-        engine_register_ops_i64!($eng, $op, $func, CellExpression);
-        engine_register_ops_i64!($eng, $op, $func, String);
-        engine_register_ops_i64!($eng, $op, $func, Column);
-        engine_register_ops_i64!($eng, $op, $func, i64);
+    ($eng: expr, $ctx: expr, $op: tt, $func: ident) => {
+        engine_register_ops_i64!($eng, $ctx, $op, $func, Cell);
+        engine_register_ops_i64!($eng, $ctx, $op, $func, CellExpression);
+        engine_register_ops_i64!($eng, $ctx, $op, $func, String);
+        engine_register_ops_i64!($eng, $ctx, $op, $func, Column);
+        engine_register_ops_i64!($eng, $ctx, $op, $func, i64);
     };
 }
 
-pub fn register_operator(engine: &mut rhai::Engine) {
-    engine_register_ops_types!(engine, +, operator_plus);
-    engine_register_ops_types!(engine, -, operator_minus);
-    engine_register_ops_types!(engine, *, operator_mul);
-    engine_register_ops_types_i64!(engine, "**", operator_pow);
-    engine_register_ops_types!(engine, /, operator_divide);
+pub fn register_operator(engine: &mut rhai::Engine, context: &Context) {
+    engine_register_ops_types!(engine, context, +, operator_plus);
+    engine_register_ops_types!(engine, context, -, operator_minus);
+    engine_register_ops_types!(engine, context, *, operator_mul);
+    engine_register_ops_types_i64!(engine, context, "**", operator_pow);
+    engine_register_ops_types!(engine, context, /, operator_divide, engine_register_ops_with_context);
 
     // to calculated value
     engine.register_fn(">>", |t1: String, t2: i64| {
@@ -90,30 +127,24 @@ pub fn register_operator(engine: &mut rhai::Engine) {
     });
 }
 
-fn operator_plus<T1: ToCellExpression, T2: ToCellExpression>(a: T1, b: T2) -> CellExpression {
-    CellExpression::Sum(
-        Box::new(a.to_cell_expression()),
-        Box::new(b.to_cell_expression()),
-    )
+fn operator_plus(a: CellExpression, b: CellExpression) -> CellExpression {
+    CellExpression::Sum(Box::new(a), Box::new(b))
 }
 
-fn operator_minus<T1: ToCellExpression, T2: ToCellExpression>(a: T1, b: T2) -> CellExpression {
-    CellExpression::Sum(
-        Box::new(a.to_cell_expression()),
-        Box::new(CellExpression::Negated(Box::new(b.to_cell_expression()))),
-    )
+fn operator_minus(a: CellExpression, b: CellExpression) -> CellExpression {
+    CellExpression::Sum(Box::new(a), Box::new(CellExpression::Negated(Box::new(b))))
 }
 
-fn operator_mul<T1: ToCellExpression, T2: ToCellExpression>(a: T1, b: T2) -> CellExpression {
-    match ((a.to_cell_expression()), (b.to_cell_expression())) {
+fn operator_mul(a: CellExpression, b: CellExpression) -> CellExpression {
+    match (a, b) {
         (CellExpression::Constant(a), b) => CellExpression::Scaled(Box::new(b), a),
         (b, CellExpression::Constant(a)) => CellExpression::Scaled(Box::new(b), a),
         (a, b) => CellExpression::Product(Box::new(a), Box::new(b)),
     }
 }
 
-fn operator_pow<T1: ToCellExpression>(a: T1, b: i64) -> CellExpression {
-    let origin_exp = a.to_cell_expression();
+fn operator_pow(a: CellExpression, b: i64) -> CellExpression {
+    let origin_exp = a;
     let mut exp = origin_exp.clone();
     for _ in 1..b {
         exp = CellExpression::Product(Box::new(exp), Box::new(origin_exp.clone()));
@@ -121,11 +152,85 @@ fn operator_pow<T1: ToCellExpression>(a: T1, b: i64) -> CellExpression {
     exp
 }
 
-fn operator_divide<T1: ToCellExpression, T2: ToCellExpression>(a: T1, b: T2) -> CellExpression {
-    CellExpression::Constant(
-        (BigInt::from_str(&a.to_cell_expression().to_value_string().unwrap()).unwrap()
-            / BigInt::from_str(&b.to_cell_expression().to_value_string().unwrap()).unwrap()).to_string(),
-    )
+// the quotient in the field: computed when both values are constants, a times
+// the inverse of b otherwise. Dividing by a cell witnesses its inverse
+// and constrains b * inverse - 1 = 0, so the quotient can be used in gates.
+fn operator_divide(
+    cs: &mut SimplifiedConstraitSystem,
+    a: CellExpression,
+    b: CellExpression,
+) -> Result<CellExpression, Box<EvalAltResult>> {
+    match b {
+        CellExpression::CellValue(b) => {
+            if cs.field.value_string(&b).is_some_and(|v| cs.field.inverse(&v).is_none()) {
+                return Err(EngineError::script("division by zero"));
+            }
+            let inverse = witness_inverse(cs, &b)?;
+            constrain_zero(
+                cs,
+                CellExpression::Sum(
+                    Box::new(CellExpression::Product(
+                        Box::new(CellExpression::CellValue(b)),
+                        Box::new(CellExpression::CellValue(inverse.clone())),
+                    )),
+                    Box::new(CellExpression::Negated(Box::new(CellExpression::Constant(
+                        "1".to_string(),
+                    )))),
+                ),
+            )?;
+            Ok(operator_mul(a, CellExpression::CellValue(inverse)))
+        }
+        CellExpression::Constant(_) | CellExpression::Calculated(_) => match a {
+            CellExpression::Constant(_) | CellExpression::Calculated(_) => Ok(
+                CellExpression::Constant(divide_in(cs.field, &a, &b)?),
+            ),
+            a => {
+                let one = CellExpression::Constant("1".to_string());
+                let inverse = divide_in(cs.field, &one, &b)?;
+                Ok(operator_mul(a, CellExpression::Constant(inverse)))
+            }
+        },
+        b => Err(EngineError::unsupported(format!(
+            "cannot divide by {}, assign it to a cell first",
+            b.to_string()
+        ))),
+    }
+}
+
+fn divide_in(
+    field: FieldKind,
+    a: &CellExpression,
+    b: &CellExpression,
+) -> Result<String, Box<EvalAltResult>> {
+    match field {
+        FieldKind::Pallas => divide::<Fp>(a, b),
+        FieldKind::Vesta => divide::<Fq>(a, b),
+        FieldKind::Bn254 => divide::<Fr>(a, b),
+    }
+}
+
+fn divide<F: PrimeField>(a: &CellExpression, b: &CellExpression) -> Result<String, Box<EvalAltResult>> {
+    let (a, b) = (known_field::<F>(a)?, known_field::<F>(b)?);
+    let inverse = Option::<F>::from(b.invert()).ok_or_else(|| EngineError::script("division by zero"))?;
+    Ok(field_to_string(&(a * inverse)))
+}
+
+fn known_field<F: PrimeField>(exp: &CellExpression) -> Result<F, Box<EvalAltResult>> {
+    let value = exp
+        .to_value_string::<F>()
+        .ok_or_else(|| EngineError::script(format!("value of {} is unknown", exp.to_string())))?;
+    value
+        .to_field()
+        .ok_or_else(|| EngineError::script(format!("{} is not a number", value)))
+}
+
+// decimal, or hex with a `0x` prefix as field elements are printed
+fn parse_bigint(s: &str) -> Result<BigInt, Box<EvalAltResult>> {
+    match s.strip_prefix("0x") {
+        Some(hex) => BigInt::parse_bytes(hex.as_bytes(), 16),
+        None => BigInt::from_str(s).ok(),
+    }
+    .ok_or_else(|| EngineError::script(format!("{} is not a number", s)))
 }
 
 #[derive(Debug, Clone)]
@@ -137,21 +242,24 @@ pub enum OperationEnum {
     ExclusiveOr,
 }
 
-fn perform_operation(big_num_str1: &str, big_num_str2: &str, operation: OperationEnum) -> String {
-    let big_num1 = BigInt::from_str(big_num_str1).unwrap();
-    let big_num2 = BigInt::from_str(big_num_str2).unwrap();
+fn perform_operation(
+    big_num_str1: &str,
+    big_num_str2: &str,
+    operation: OperationEnum,
+) -> Result<String, Box<EvalAltResult>> {
+    let big_num1 = parse_bigint(big_num_str1)?;
+    let big_num2 = parse_bigint(big_num_str2)?;
+    let shift = || {
+        big_num2
+            .to_usize()
+            .ok_or_else(|| EngineError::script(format!("cannot shift by {}", big_num2)))
+    };
 
-    match operation {
-        OperationEnum::RightShift => {
-            let shift = big_num2.to_usize().unwrap();
-            (big_num1 >> shift).to_string()
-        }
-        OperationEnum::LeftShift => {
-            let shift = big_num2.to_usize().unwrap();
-            (big_num1 << shift).to_string()
-        }
-        OperationEnum::BinaryAnd => (big_num1 & big_num2).to_string(),
-        OperationEnum::BinaryOr => (big_num1 | big_num2).to_string(),
-        OperationEnum::ExclusiveOr => (big_num1 ^ big_num2).to_string(),
-    }
+    Ok(match operation {
+        OperationEnum::RightShift => (&big_num1 >> shift()?).to_string(),
+        OperationEnum::LeftShift => (&big_num1 << shift()?).to_string(),
+        OperationEnum::BinaryAnd => (&big_num1 & &big_num2).to_string(),
+        OperationEnum::BinaryOr => (&big_num1 | &big_num2).to_string(),
+        OperationEnum::ExclusiveOr => (&big_num1 ^ &big_num2).to_string(),
+    })
 }
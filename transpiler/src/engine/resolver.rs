@@ -1,18 +1,34 @@
 use rhai::{module_resolvers::StaticModuleResolver, Engine, Module, Scope};
 use std::collections::HashMap;
 
-use crate::transpiler::transpile;
+use crate::error::PlonkScriptError;
+use crate::transpiler::transpile_with_source_map;
 
-pub fn register_module_resolver(engine: &mut Engine, modules: HashMap<String, String>) {
+pub fn register_module_resolver(
+    engine: &mut Engine,
+    modules: HashMap<String, String>,
+) -> Result<(), PlonkScriptError> {
     let mut resolver = StaticModuleResolver::new();
-    
+
     for (name, code) in modules {
-        let script = transpile(code);
-        let ast = engine.compile(script.as_str()).unwrap();
-        let module = Module::eval_ast_as_new(Scope::new(), &ast, &engine).unwrap();
+        let module = load_module(engine, &code).map_err(|error| PlonkScriptError::ModuleError {
+            module: name.clone(),
+            error: Box::new(error),
+        })?;
         resolver.insert(&name, module);
-        println!("Loaded module: {}", name);
+        eprintln!("Loaded module: {}", name);
     }
 
     engine.set_module_resolver(resolver);
+    Ok(())
+}
+
+fn load_module(engine: &Engine, code: &str) -> Result<Module, PlonkScriptError> {
+    let (script, source_map) = transpile_with_source_map(code)
+        .map_err(|e| PlonkScriptError::from_parse_error(code, &e))?;
+    engine
+        .compile(script.as_str())
+        .map_err(Into::into)
+        .and_then(|ast| Module::eval_ast_as_new(Scope::new(), &ast, engine))
+        .map_err(|e| PlonkScriptError::from_script_error(code, &script, &source_map, e))
 }
@@ -0,0 +1,183 @@
+use std::{fmt, path::Path};
+
+use rhai::{Dynamic, EvalAltResult, Position};
+
+use crate::{diagnostic::Diagnostic, failure::Failure, parser::ParseError, transpiler::SourceMap};
+
+/// Everything that can go wrong between PlonkScript source and a verified circuit.
+#[derive(Debug)]
+pub enum PlonkScriptError {
+    /// The source is not valid PlonkScript.
+    ParseError(Diagnostic),
+    /// A library module failed to load.
+    ModuleError {
+        module: String,
+        error: Box<PlonkScriptError>,
+    },
+    /// The script failed while running.
+    ScriptError(Diagnostic),
+    /// A constraint cannot be turned into a gate or lookup.
+    GateError(Diagnostic),
+    /// The script asked for something PlonkScript does not support.
+    UnsupportedOperation(Diagnostic),
+    /// A public signal was never given a value.
+    MissingInput { name: String },
+    /// An input or parameter has a value that cannot be used.
+    InvalidInput { name: String, value: String },
+    /// halo2 rejected the circuit while laying it out.
+    ProverError(halo2_proofs::plonk::Error),
+    /// The circuit was built, but its constraints do not hold.
+    ConstraintUnsatisfied { failures: Vec<Failure> },
+    /// A file cannot be read or written.
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// A params, verifying key, proof or inputs file is malformed or does not
+    /// belong with the others.
+    InvalidArtifact { path: String, message: String },
+    /// The proof does not verify.
+    ProofRejected(halo2_proofs::plonk::Error),
+}
+
+impl PlonkScriptError {
+    pub fn io(path: &Path, source: std::io::Error) -> Self {
+        PlonkScriptError::Io {
+            path: path.display().to_string(),
+            source,
+        }
+    }
+
+    pub fn from_parse_error(src: &str, error: &ParseError) -> Self {
+        PlonkScriptError::ParseError(Diagnostic::from_parse_error(src, error))
+    }
+
+    /// Classifies an error raised while running the transpiled `script` of `src`.
+    pub fn from_script_error(
+        src: &str,
+        script: &str,
+        source_map: &SourceMap,
+        error: Box<EvalAltResult>,
+    ) -> Self {
+        let kind = EngineError::find(&error).map(|e| e.kind);
+        let diagnostic = Diagnostic::from_script_error(src, script, source_map, error);
+        match kind {
+            Some(EngineErrorKind::Gate) => PlonkScriptError::GateError(diagnostic),
+            Some(EngineErrorKind::Unsupported) => {
+                PlonkScriptError::UnsupportedOperation(diagnostic)
+            }
+            _ => PlonkScriptError::ScriptError(diagnostic),
+        }
+    }
+}
+
+impl fmt::Display for PlonkScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlonkScriptError::ParseError(d)
+            | PlonkScriptError::ScriptError(d)
+            | PlonkScriptError::GateError(d)
+            | PlonkScriptError::UnsupportedOperation(d) => write!(f, "{}", d),
+            PlonkScriptError::ModuleError { module, error } => {
+                writeln!(f, "in module `{}`:", module)?;
+                write!(f, "{}", error)
+            }
+            PlonkScriptError::MissingInput { name } => {
+                writeln!(f, "error: no value for signal `{}`", name)
+            }
+            PlonkScriptError::InvalidInput { name, value } => {
+                writeln!(f, "error: `{}` is not a valid value for `{}`", value, name)
+            }
+            PlonkScriptError::ProverError(e) => writeln!(f, "error: prove failed: {}", e),
+            PlonkScriptError::Io { path, source } => writeln!(f, "error: {}: {}", path, source),
+            PlonkScriptError::InvalidArtifact { path, message } => {
+                writeln!(f, "error: {}: {}", path, message)
+            }
+            PlonkScriptError::ProofRejected(e) => {
+                writeln!(f, "error: the proof does not verify: {}", e)
+            }
+            PlonkScriptError::ConstraintUnsatisfied { failures } => {
+                for (i, failure) in failures.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", failure)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlonkScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlonkScriptError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<halo2_proofs::plonk::Error> for PlonkScriptError {
+    fn from(error: halo2_proofs::plonk::Error) -> Self {
+        PlonkScriptError::ProverError(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineErrorKind {
+    Gate,
+    Unsupported,
+    Script,
+}
+
+/// Error raised by a function registered on the rhai engine.
+///
+/// It travels inside `EvalAltResult::ErrorRuntime`, whose position rhai fills in
+/// with the call that failed.
+#[derive(Debug, Clone)]
+pub struct EngineError {
+    pub kind: EngineErrorKind,
+    pub message: String,
+}
+
+impl EngineError {
+    pub fn gate(message: impl Into<String>) -> Box<EvalAltResult> {
+        Self::raise(EngineErrorKind::Gate, message)
+    }
+
+    pub fn unsupported(message: impl Into<String>) -> Box<EvalAltResult> {
+        Self::raise(EngineErrorKind::Unsupported, message)
+    }
+
+    pub fn script(message: impl Into<String>) -> Box<EvalAltResult> {
+        Self::raise(EngineErrorKind::Script, message)
+    }
+
+    fn raise(kind: EngineErrorKind, message: impl Into<String>) -> Box<EvalAltResult> {
+        let error = EngineError {
+            kind,
+            message: message.into(),
+        };
+        Box::new(EvalAltResult::ErrorRuntime(
+            Dynamic::from(error),
+            Position::NONE,
+        ))
+    }
+
+    /// The engine error at the bottom of a chain of function calls, if any.
+    pub fn find(error: &EvalAltResult) -> Option<EngineError> {
+        match error {
+            EvalAltResult::ErrorInFunctionCall(_, _, inner, _)
+            | EvalAltResult::ErrorInModule(_, inner, _) => Self::find(inner),
+            EvalAltResult::ErrorRuntime(value, _) => value.clone().try_cast::<EngineError>(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+
+use halo2_proofs::{
+    dev::{metadata, FailureLocation, VerifyFailure},
+    halo2curves::ff::PrimeField,
+    plonk::Any,
+};
+use serde::Serialize;
+
+use crate::{
+    circuit::MyCircuit,
+    diagnostic::{index_after, quoted_after, Diagnostic},
+    transpiler::SourceMap,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FailureKind {
+    /// A gate evaluated to a non-zero value.
+    ConstraintNotSatisfied,
+    /// A gate is active on a row reserved for blinding.
+    ConstraintPoisoned,
+    /// A gate reads a cell that was never assigned.
+    CellNotAssigned,
+    /// An input row is missing from its lookup table.
+    Lookup,
+    /// Two cells constrained to be equal hold different values.
+    Permutation,
+}
+
+/// A MockProver failure, in terms of the PlonkScript program.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Failure {
+    pub kind: FailureKind,
+    /// name of the gate, e.g. `gate_2`
+    pub gate: Option<String>,
+    /// name of the lookup
+    pub lookup: Option<String>,
+    /// name of the region the failing row belongs to
+    pub region: Option<String>,
+    /// offset inside `region`, or the absolute row when outside any region
+    pub row: Option<usize>,
+    /// name of the column of the offending cell
+    pub column: Option<String>,
+    /// value of every cell the failing constraint reads, e.g. (`a@-1`, `0x2`)
+    pub cell_values: Vec<(String, String)>,
+    pub diagnostic: Diagnostic,
+}
+
+impl Failure {
+    pub fn from_verify_failure<F: PrimeField>(
+        src: &str,
+        script: &str,
+        source_map: &SourceMap,
+        circuit: &MyCircuit<F>,
+        failure: &VerifyFailure,
+    ) -> Self {
+        let cs = &circuit.scs;
+        let mut result = Failure {
+            kind: FailureKind::ConstraintNotSatisfied,
+            gate: None,
+            lookup: None,
+            region: None,
+            row: None,
+            column: None,
+            cell_values: vec![],
+            diagnostic: Diagnostic::from_verify_failure(src, script, source_map, cs, failure),
+        };
+
+        match failure {
+            VerifyFailure::CellNotAssigned {
+                gate,
+                region,
+                column,
+                offset,
+                ..
+            } => {
+                result.kind = FailureKind::CellNotAssigned;
+                result.gate = quoted_after(&gate.to_string(), "Gate ");
+                result.region = region_name(region);
+                result.row = usize::try_from(*offset).ok();
+                result.column = circuit.column_name(column.column_type(), column.index());
+            }
+            VerifyFailure::InstanceCellNotAssigned {
+                gate,
+                region,
+                column,
+                row,
+                ..
+            } => {
+                result.kind = FailureKind::CellNotAssigned;
+                result.gate = quoted_after(&gate.to_string(), "Gate ");
+                result.region = region_name(region);
+                result.row = Some(*row);
+                result.column = circuit.column_name(&Any::Instance, column.index());
+            }
+            VerifyFailure::ConstraintNotSatisfied {
+                constraint,
+                location,
+                cell_values,
+            } => {
+                result.gate = quoted_after(&constraint.to_string(), "in gate ");
+                result.set_location(location);
+                result.cell_values = cell_values
+                    .iter()
+                    .map(|(cell, value)| (cell_name(circuit, &cell.to_string()), value.clone()))
+                    .collect();
+            }
+            VerifyFailure::ConstraintPoisoned { constraint } => {
+                result.kind = FailureKind::ConstraintPoisoned;
+                result.gate = quoted_after(&constraint.to_string(), "in gate ");
+            }
+            VerifyFailure::Lookup { name, location, .. } => {
+                result.kind = FailureKind::Lookup;
+                result.lookup = Some(name.to_string());
+                result.set_location(location);
+            }
+            // `MyCircuit` declares no shuffle arguments
+            VerifyFailure::Shuffle { .. } => {}
+            VerifyFailure::Permutation { column, location } => {
+                result.kind = FailureKind::Permutation;
+                result.column = circuit.column_name(&column.column_type(), column.index());
+                result.set_location(location);
+            }
+        }
+        result
+    }
+
+    fn set_location(&mut self, location: &FailureLocation) {
+        match location {
+            FailureLocation::InRegion { region, offset } => {
+                self.region = region_name(region);
+                self.row = Some(*offset);
+            }
+            FailureLocation::OutsideRegion { row } => self.row = Some(*row),
+        }
+    }
+}
+
+// `metadata::Region` has no accessors, `Region 2 ('fib')` -> fib
+fn region_name(region: &metadata::Region) -> Option<String> {
+    quoted_after(&region.to_string(), "Region ")
+}
+
+// `metadata::VirtualCell` has no accessors either,
+// `Column('Advice', 1)@-1` -> `a@-1`, kept as is when the column is unknown
+pub(crate) fn cell_name<F: PrimeField>(circuit: &MyCircuit<F>, cell: &str) -> String {
+    let Some((column, rotation)) = cell.split_once('@') else {
+        return cell.to_string();
+    };
+    match parse_column(column).and_then(|(t, i)| circuit.column_name(&t, i)) {
+        Some(name) => format!("{}@{}", name, rotation),
+        None => cell.to_string(),
+    }
+}
+
+// `Column('Advice', 1)` -> (Advice, 1), an advice column of a later phase
+// reads `Column('Advice { phase: Phase(1) }', 1)`
+fn parse_column(text: &str) -> Option<(Any, usize)> {
+    let (column_type, index) = text.strip_prefix("Column('")?.rsplit_once("', ")?;
+    let column_type = match column_type.split(|c: char| !c.is_alphanumeric()).next()? {
+        "Advice" => Any::advice(),
+        "Fixed" => Any::Fixed,
+        "Instance" => Any::Instance,
+        _ => return None,
+    };
+    Some((column_type, index_after(index, "")?))
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.diagnostic)
+    }
+}
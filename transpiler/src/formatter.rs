@@ -0,0 +1,110 @@
+use crate::parser::{
+    lexer::{tokenize, Token, TokenKind},
+    parse, ParseError,
+};
+
+const INDENT: &str = "    ";
+
+/// Formats PlonkScript source.
+///
+/// Only whitespace changes: lines are re-indented by nesting depth, a statement
+/// continued on the next line is indented once more, constraint operators get a
+/// space on each side, trailing spaces go and blank lines are collapsed to one.
+/// Comments and strings are kept as they are.
+pub fn format(code: &str) -> Result<String, ParseError> {
+    parse(code)?;
+    let tokens = tokenize(code)?;
+
+    let mut out = String::with_capacity(code.len());
+    let mut depth = 0usize;
+    // last token that is neither whitespace nor a comment
+    let mut previous: Option<Token> = None;
+    // the current line is a `#` parameter, which ends without `;`
+    let mut parameter_line = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let text = token.span.text(code);
+        let next = tokens.get(i + 1);
+        match token.kind {
+            TokenKind::Whitespace => {
+                let newlines = text.matches('\n').count();
+                if out.is_empty() || next.is_none() {
+                    continue;
+                }
+                if newlines == 0 {
+                    let around_op = next.is_some_and(|t| is_constraint_op(t.kind))
+                        || tokens
+                            .get(i.wrapping_sub(1))
+                            .is_some_and(|t| is_constraint_op(t.kind));
+                    out.push_str(if around_op { " " } else { text });
+                    continue;
+                }
+
+                out.push_str(&"\n".repeat(newlines.min(2)));
+                let closers = tokens[i + 1..]
+                    .iter()
+                    .take_while(|t| is_closer(t.kind))
+                    .count();
+                let continued = previous.is_some_and(|t| !ends_statement(t.kind))
+                    && !parameter_line
+                    && closers == 0;
+                let level = depth.saturating_sub(closers) + continued as usize;
+                out.push_str(&INDENT.repeat(level));
+                parameter_line = next.is_some_and(|t| t.kind == TokenKind::Hash);
+            }
+            kind => {
+                if is_constraint_op(kind) && !out.is_empty() && !out.ends_with(char::is_whitespace)
+                {
+                    out.push(' ');
+                }
+                if out.is_empty() {
+                    parameter_line = kind == TokenKind::Hash;
+                }
+                out.push_str(text);
+                if is_constraint_op(kind) && next.is_some_and(|t| t.kind != TokenKind::Whitespace) {
+                    out.push(' ');
+                }
+
+                match kind {
+                    TokenKind::LBrace | TokenKind::LParen | TokenKind::LBracket => depth += 1,
+                    k if is_closer(k) => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                if kind != TokenKind::Comment {
+                    previous = Some(*token);
+                }
+            }
+        }
+    }
+
+    let mut out = out.trim_end().to_string();
+    out.push('\n');
+    Ok(out)
+}
+
+fn is_constraint_op(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::ConstrainAssign | TokenKind::Assign | TokenKind::ConstrainEqual
+    )
+}
+
+fn is_closer(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::RBrace | TokenKind::RParen | TokenKind::RBracket
+    )
+}
+
+// the line after one of these starts a new statement, or is indented by depth
+fn ends_statement(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Semicolon
+            | TokenKind::Comma
+            | TokenKind::LBrace
+            | TokenKind::RBrace
+            | TokenKind::LParen
+            | TokenKind::LBracket
+    )
+}
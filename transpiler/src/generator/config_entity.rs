@@ -27,7 +27,7 @@ fn get_config_structs(circuit_name: &str, _cs: &SimplifiedConstraitSystem) -> To
             selectors: Vec<(String, Selector)>,
             instances: Vec<(String, Column<Instance>)>,
             lookups: Vec<(String, TableColumn)>,
-            acells: Vec<(String, AssignedCell<F, F>)>,
+            acells: HashMap<String, AssignedCell<F, F>>,
             _marker: PhantomData<F>,
         }
     }
@@ -89,11 +89,13 @@ fn get_config_impl() -> TokenStream {
                     ))
             }
 
-            fn get_assigned_cell(&self, name: &str) -> AssignedCell<F, F> {
-                if let Some(acell) = self.acells.iter().filter(|x| x.0 == name).nth(0) {
-                    return acell.1.clone();
-                }
-                panic!("cannot find assigned cell of {}", name)
+            fn get_assigned_cell(&self, name: &str) -> Result<AssignedCell<F, F>, io::Error> {
+                self.acells.get(name).cloned().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("cell {} is not assigned in any region laid out so far", name),
+                    )
+                })
             }
 
             pub fn query_column(
@@ -107,26 +109,40 @@ fn get_config_impl() -> TokenStream {
                     ColumnType::Selector => self
                         .get_selector(&col_name)
                         .map(|x| meta.query_selector(x)),
-                    ColumnType::Advice => self.get_advice(&col_name).map(|x| {
-                        meta.query_advice(
-                            x,
-                            match idx {
-                                -1 => Rotation::prev(),
-                                0 => Rotation::cur(),
-                                1 => Rotation::next(),
-                                x @ 2..=5 => Rotation(x as i32),
-                                x => panic!("too big the rotation: {}", x),
-                            },
-                        )
-                    }),
-                    ColumnType::Fixed => {
-                        self.get_fixed(&col_name).map(|x| meta.query_fixed(x))
+                    ColumnType::Advice => {
+                        let rotation = i32::try_from(idx).map(Rotation).map_err(|_| {
+                            io::Error::new(
+                                io::ErrorKind::Unsupported,
+                                format!("rotation {} is not supported", idx),
+                            )
+                        })?;
+                        self.get_advice(&col_name)
+                            .map(|x| meta.query_advice(x, rotation))
+                    }
+                    ColumnType::Fixed => match idx {
+                        0 => self.get_fixed(&col_name).map(|x| meta.query_fixed(x, Rotation::cur())),
+                        x => Err(io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            format!("rotation {} of fixed column {} is not supported", x, col_name),
+                        )),
+                    },
+                    ColumnType::Instance => {
+                        let rotation = i32::try_from(idx).map(Rotation).map_err(|_| {
+                            io::Error::new(
+                                io::ErrorKind::Unsupported,
+                                format!("rotation {} is not supported", idx),
+                            )
+                        })?;
+                        self.get_instance(&col_name)
+                            .map(|x| meta.query_instance(x, rotation))
                     }
-                    ColumnType::Instance => todo!(),
                     ColumnType::ComplexSelector => self
                         .get_selector(&col_name)
                         .map(|x| meta.query_selector(x)),
-                    ColumnType::TableLookup => todo!(),
+                    ColumnType::TableLookup => Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!("table column {} cannot be queried", col_name),
+                    )),
                 }
             }
         }
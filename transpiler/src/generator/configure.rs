@@ -1,25 +1,32 @@
-use crate::generator::util::convert_to_gate_expression;
+use crate::error::PlonkScriptError;
+use crate::generator::util::{convert_to_gate_expression, unsupported};
 use crate::{engine::DEFAULT_INSTANCE_COLUMN_NAME, system::SimplifiedConstraitSystem};
 use proc_macro2::TokenStream;
 use quote::quote;
 
-pub fn get_circuit_configure(cs: &SimplifiedConstraitSystem) -> TokenStream {
+pub fn get_circuit_configure(
+    cs: &SimplifiedConstraitSystem,
+) -> Result<TokenStream, PlonkScriptError> {
     let instance_push = get_circuit_instances_push(cs);
 
     let type_pushes = get_circuit_type_pushes(cs);
 
-    let gate_creates = get_circuit_gate_creates(cs);
+    let gate_creates = get_circuit_gate_creates(cs)?;
 
-    let lookup_creates = get_circuit_lookup_creates(cs);
+    let lookup_creates = get_circuit_lookup_creates(cs)?;
 
-    quote! {
+    let equality = get_circuit_equality(cs);
+
+    let constants = get_circuit_constants(cs);
+
+    Ok(quote! {
         fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
-            let mut advices = Vec::new();
-            let mut fixeds = Vec::new();
-            let mut selectors = Vec::new();
-            let mut instances = Vec::new();
-            let mut lookups = Vec::new();
-            let acells = Vec::new();
+            let mut advices: Vec<(String, Column<Advice>)> = Vec::new();
+            let mut fixeds: Vec<(String, Column<Fixed>)> = Vec::new();
+            let mut selectors: Vec<(String, Selector)> = Vec::new();
+            let mut instances: Vec<(String, Column<Instance>)> = Vec::new();
+            let mut lookups: Vec<(String, TableColumn)> = Vec::new();
+            let acells = HashMap::new();
 
             #instance_push
 
@@ -27,12 +34,10 @@ pub fn get_circuit_configure(cs: &SimplifiedConstraitSystem) -> TokenStream {
             #(#type_pushes;)*
 
             /// enable_equality
-            for c in advices.clone() {
-                meta.enable_equality(c.1);
-            }
-            for c in instances.clone() {
-                meta.enable_equality(c.1);
-            }
+            #equality
+
+            /// constants
+            #constants
 
             let config = CommonConfig {
                 advices,
@@ -52,12 +57,51 @@ pub fn get_circuit_configure(cs: &SimplifiedConstraitSystem) -> TokenStream {
 
             config
         }
+    })
+}
+
+fn get_circuit_equality(cs: &SimplifiedConstraitSystem) -> TokenStream {
+    let names = cs.columns_in_equality();
+    if names.is_empty() {
+        return quote! {};
+    }
+    quote! {
+        let in_equality = [#(#names),*];
+        for c in advices.clone() {
+            if in_equality.contains(&c.0.as_str()) {
+                meta.enable_equality(c.1);
+            }
+        }
+        for c in instances.clone() {
+            if in_equality.contains(&c.0.as_str()) {
+                meta.enable_equality(c.1);
+            }
+        }
+        for c in fixeds.clone() {
+            if in_equality.contains(&c.0.as_str()) {
+                meta.enable_equality(c.1);
+            }
+        }
+    }
+}
+
+fn get_circuit_constants(cs: &SimplifiedConstraitSystem) -> TokenStream {
+    if !cs.uses_constants() {
+        return quote! {};
+    }
+    quote! {
+        let constants = meta.fixed_column();
+        meta.enable_constant(constants);
     }
 }
 
 fn get_circuit_instances_push(cs: &SimplifiedConstraitSystem) -> TokenStream {
     let default_instance_column_name = DEFAULT_INSTANCE_COLUMN_NAME;
-    if cs.signals.len() > 0 {
+    let has_default_instance = cs
+        .instance_columns()
+        .first()
+        .is_some_and(|(name, _)| name == DEFAULT_INSTANCE_COLUMN_NAME);
+    if has_default_instance {
         quote! {
             instances.push((
                 #default_instance_column_name.to_string(),
@@ -98,35 +142,64 @@ fn get_circuit_type_pushes(cs: &SimplifiedConstraitSystem) -> Vec<TokenStream> {
         .collect()
 }
 
-fn get_circuit_gate_creates(cs: &SimplifiedConstraitSystem) -> Vec<TokenStream> {
-    cs.gates
-        .iter()
-        .map(|(gname, _, _, gate)| {
-            let sgname = gname.as_str();
-
-            let ge = convert_to_gate_expression(gate)
-                .expect(format!("cannot convert gate expression of {}", sgname).as_str());
-            quote! { meta.create_gate(#sgname, |meta| { vec![#ge] }); }
+fn get_circuit_gate_creates(
+    cs: &SimplifiedConstraitSystem,
+) -> Result<Vec<TokenStream>, PlonkScriptError> {
+    cs.gate_groups()
+        .into_iter()
+        .map(|(sgname, gates)| {
+            let ges = gates
+                .into_iter()
+                .map(|gate| {
+                    convert_to_gate_expression(gate)
+                        .map_err(|e| unsupported(format!("gate {}: {}", sgname, e)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(quote! { meta.create_gate(#sgname, |meta| { vec![#(#ges),*] }); })
         })
         .collect()
 }
 
-fn get_circuit_lookup_creates(cs: &SimplifiedConstraitSystem) -> Vec<TokenStream> {
+fn get_circuit_lookup_creates(
+    cs: &SimplifiedConstraitSystem,
+) -> Result<Vec<TokenStream>, PlonkScriptError> {
     cs.lookups
         .iter()
         .map(|lookup| {
             let name = lookup.name.as_str();
-            let map = lookup.map.iter().map(|(exp, col)| {
-                let colname = col.name.as_str();
-                let ge = convert_to_gate_expression(exp)
-                    .expect(format!("cannot convert lookup expression of {}", name).as_str());
-                quote! { (#ge, config.get_table_lookup(&#colname).unwrap()) }
-            });
-            quote! {
-                meta.lookup(|meta| {
-                    vec![#(#map),*]
+            let map = lookup
+                .map
+                .iter()
+                .map(|(exp, col)| {
+                    let colname = col.name.as_str();
+                    let ge = convert_to_gate_expression(exp)
+                        .map_err(|e| unsupported(format!("lookup {}: {}", name, e)))?;
+                    if lookup.reads_advice() {
+                        // advice tables are expressions, gated by their selector
+                        let selector = col.table_selector().name;
+                        return Ok(quote! {
+                            (
+                                #ge,
+                                config.query_column(meta, ColumnType::ComplexSelector, #selector, 0).unwrap()
+                                    * config.query_column(meta, ColumnType::Advice, #colname, 0).unwrap()
+                            )
+                        });
+                    }
+                    Ok(quote! { (#ge, config.get_table_lookup(&#colname).unwrap()) })
+                })
+                .collect::<Result<Vec<_>, PlonkScriptError>>()?;
+            if lookup.reads_advice() {
+                return Ok(quote! {
+                    meta.lookup_any(#name, |meta| {
+                        vec![#(#map),*]
+                    });
                 });
             }
+            Ok(quote! {
+                meta.lookup(#name, |meta| {
+                    vec![#(#map),*]
+                });
+            })
         })
         .collect()
 }
@@ -12,7 +12,7 @@ pub fn get_header() -> TokenStream {
 
         use halo2_proofs::{
             circuit::{floor_planner::V1, *},
-            pasta::group::ff::PrimeField,
+            halo2curves::ff::PrimeField,
             plonk::*,
             poly::Rotation,
         };
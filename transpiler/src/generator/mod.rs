@@ -1,3 +1,4 @@
+use crate::error::PlonkScriptError;
 use crate::generator::configure::get_circuit_configure;
 use crate::generator::synthesize::get_circuit_synthesize;
 use crate::system::SimplifiedConstraitSystem;
@@ -15,12 +16,12 @@ mod synthesize;
 mod test;
 mod util;
 
-pub fn generate_rust_code(cs: &SimplifiedConstraitSystem) -> String {
+pub fn generate_rust_code(cs: &SimplifiedConstraitSystem) -> Result<String, PlonkScriptError> {
     let header = get_header();
     let circuit_name = "MyCircuit";
-    let impls = get_circuit_impl(circuit_name, cs);
+    let impls = get_circuit_impl(circuit_name, cs)?;
     let config = get_config_entity(circuit_name, cs);
-    let test = get_test(circuit_name, cs);
+    let test = get_test(circuit_name, cs)?;
     let output = quote! {
         #header
         #impls
@@ -30,15 +31,17 @@ pub fn generate_rust_code(cs: &SimplifiedConstraitSystem) -> String {
 
     // println!("{}", output);
     let syntax_tree = syn::parse2(output).unwrap();
-    let formatted = prettyplease::unparse(&syntax_tree);
-    formatted
+    Ok(prettyplease::unparse(&syntax_tree))
 }
 
-fn get_circuit_impl(circuit_name: &str, cs: &SimplifiedConstraitSystem) -> TokenStream {
+fn get_circuit_impl(
+    circuit_name: &str,
+    cs: &SimplifiedConstraitSystem,
+) -> Result<TokenStream, PlonkScriptError> {
     let circuit_name = format_ident!("{}", circuit_name);
-    let configure = get_circuit_configure(cs);
-    let synthesize = get_circuit_synthesize(cs);
-    quote! {
+    let configure = get_circuit_configure(cs)?;
+    let synthesize = get_circuit_synthesize(cs)?;
+    Ok(quote! {
 
         impl<F: PrimeField> Circuit<F> for #circuit_name<F> {
             type Config = CommonConfig<F>;
@@ -51,5 +54,5 @@ fn get_circuit_impl(circuit_name: &str, cs: &SimplifiedConstraitSystem) -> Token
             #configure
             #synthesize
         }
-    }
+    })
 }
@@ -1,17 +1,20 @@
 use std::collections::HashMap;
 
-use crate::generator::util::convert_to_value;
-use crate::system::CellExpression;
+use crate::error::PlonkScriptError;
+use crate::generator::util::{convert_to_value, to_field, unsupported};
 use crate::system::SimplifiedConstraitSystem;
+use crate::system::{CellExpression, ColumnType};
 use proc_macro2::TokenStream;
 use quote::quote;
 
-pub fn get_circuit_synthesize(cs: &SimplifiedConstraitSystem) -> TokenStream {
-    let regions = get_circuit_synthesize_regions(cs);
+pub fn get_circuit_synthesize(
+    cs: &SimplifiedConstraitSystem,
+) -> Result<TokenStream, PlonkScriptError> {
+    let regions = get_circuit_synthesize_regions(cs)?;
 
-    let tables = get_circuit_synthesize_tables(cs);
+    let tables = get_circuit_synthesize_tables(cs)?;
 
-    quote! {
+    Ok(quote! {
         fn synthesize(
             &self,
             mut config: Self::Config,
@@ -24,14 +27,16 @@ pub fn get_circuit_synthesize(cs: &SimplifiedConstraitSystem) -> TokenStream {
 
             Ok(())
         }
-    }
+    })
 }
 
-fn get_circuit_synthesize_regions(cs: &SimplifiedConstraitSystem) -> Vec<TokenStream> {
+fn get_circuit_synthesize_regions(
+    cs: &SimplifiedConstraitSystem,
+) -> Result<Vec<TokenStream>, PlonkScriptError> {
     cs.regions
         .iter()
         .map(|region| {
-            let ins = region.instructions.iter().map(|ins| match ins {
+            let ins = region.instructions.iter().map(|ins| Ok(match ins {
                 crate::system::Instruction::EnableSelector(c) => {
                     let colname = c.column.name.as_str();
                     let idx = c.index as usize;
@@ -44,30 +49,32 @@ fn get_circuit_synthesize_regions(cs: &SimplifiedConstraitSystem) -> Vec<TokenSt
                     let colname = f.column.name.as_str();
                     let cellname = f.name.as_str();
                     let idx = f.index as usize;
-                    let exp = convert_to_value(exp);
+                    let exp = convert_to_value(exp)?;
                     quote! {
+                        let value = #exp;
                         let acell = region.assign_fixed(
                             || "fixed",
                             config.get_fixed(&#colname)?,
                             #idx,
-                            || #exp,
+                            || value,
                         )?;
-                        config.acells.push((#cellname.to_string(), acell));
+                        config.acells.insert(#cellname.to_string(), acell);
                     }
                 }
                 crate::system::Instruction::AssignAdvice(a, exp) => {
                     let colname = a.column.name.as_str();
                     let cellname = a.name.as_str();
                     let idx = a.index as usize;
-                    let exp = convert_to_value(exp);
+                    let exp = convert_to_value(exp)?;
                     quote! {
+                        let value = #exp;
                         let acell = region.assign_advice(
                             || "advice",
                             config.get_advice(&#colname)?,
                             #idx ,
-                            || #exp,
+                            || value,
                         )?;
-                        config.acells.push((#cellname.to_string(), acell));
+                        config.acells.insert(#cellname.to_string(), acell);
                     }
                 }
                 crate::system::Instruction::AssignAdviceFromInstance(a, b) => {
@@ -84,75 +91,150 @@ fn get_circuit_synthesize_regions(cs: &SimplifiedConstraitSystem) -> Vec<TokenSt
                             config.get_advice(&#colname_a)?,
                             #idx_a ,
                         )?;
-                        config.acells.push((#cellname.to_string(), acell));
+                        config.acells.insert(#cellname.to_string(), acell);
                     }
                 }
                 crate::system::Instruction::ConstrainEqual(a, b) => {
-                    let cellname_a = a.name.as_str();
-                    let cellname_b = b.name.as_str();
+                    match (a.column.ctype, b.column.ctype) {
+                        (_, ColumnType::Instance) | (ColumnType::Instance, _) => {
+                            let (cell, instance) = match a.column.ctype {
+                                ColumnType::Instance => (b, a),
+                                _ => (a, b),
+                            };
+                            let cellname = cell.name.as_str();
+                            let colname = instance.column.name.as_str();
+                            let idx = instance.index as usize;
+                            quote! {
+                                let acell = config.get_assigned_cell(#cellname)?;
+                                instance_copies.push((acell.cell(), #colname, #idx));
+                            }
+                        }
+                        _ => {
+                            let cellname_a = a.name.as_str();
+                            let cellname_b = b.name.as_str();
+                            quote! {
+                                let acell = config.get_assigned_cell(#cellname_a)?;
+                                let bcell = config.get_assigned_cell(#cellname_b)?;
+                                region.constrain_equal(acell.cell(), bcell.cell())?;
+                            }
+                        }
+                    }
+                }
+                crate::system::Instruction::AssignCell(a, _) => {
+                    return Err(unsupported(format!(
+                        "lookup column {} filled inside region {}",
+                        a.name, region.name
+                    )))
+                }
+                crate::system::Instruction::AssignAdviceFromConstant(a, c) => {
+                    let colname = a.column.name.as_str();
+                    let cellname = a.name.as_str();
+                    let idx = a.index as usize;
+                    let c = to_field(c)?;
                     quote! {
-                        let acell = config.get_assigned_cell(#cellname_a);
-                        let bcell = config.get_assigned_cell(#cellname_b);
-                        region.constrain_equal(acell.cell(), bcell.cell())?;
+                        let acell = region.assign_advice_from_constant(
+                            || "constant",
+                            config.get_advice(&#colname)?,
+                            #idx,
+                            #c,
+                        )?;
+                        config.acells.insert(#cellname.to_string(), acell);
                     }
                 }
-                crate::system::Instruction::AssignCell(_, _) => todo!("illegal instruction"),
-                crate::system::Instruction::AssignAdviceFromConstant(_, _) => todo!(),
-                crate::system::Instruction::ConstrainConstant() => todo!(),
-            });
+                crate::system::Instruction::ConstrainConstant(a, c) => {
+                    let cellname = a.name.as_str();
+                    let c = to_field(c)?;
+                    quote! {
+                        let acell = config.get_assigned_cell(#cellname)?;
+                        region.constrain_constant(acell.cell(), #c)?;
+                    }
+                }
+            }));
+            let ins = ins.collect::<Result<Vec<_>, PlonkScriptError>>()?;
 
             let region_name = region.name.clone();
-            quote! {
+            let copies_instance = region.instructions.iter().any(|ins| {
+                matches!(ins, crate::system::Instruction::ConstrainEqual(a, b)
+                    if a.column.ctype == ColumnType::Instance || b.column.ctype == ColumnType::Instance)
+            });
+            if !copies_instance {
+                return Ok(quote! {
+                    layouter.assign_region(
+                        || #region_name,
+                        |mut region| {
+                            #(#ins;)*
+                            Ok(())
+                        }
+                    )?
+                });
+            }
+            // instance cells are outside of regions, they are copied once the region is laid out
+            Ok(quote! {
+                let mut instance_copies = Vec::new();
                 layouter.assign_region(
                     || #region_name,
                     |mut region| {
+                        instance_copies.clear();
                         #(#ins;)*
                         Ok(())
                     }
-                )?
-            }
+                )?;
+                for (cell, column, row) in instance_copies {
+                    layouter.constrain_instance(cell, config.get_instance(column)?, row)?;
+                }
+            })
         })
         .collect()
 }
 
-fn get_circuit_synthesize_tables(cs: &SimplifiedConstraitSystem) -> Vec<TokenStream> {
+fn get_circuit_synthesize_tables(
+    cs: &SimplifiedConstraitSystem,
+) -> Result<Vec<TokenStream>, PlonkScriptError> {
     let mut max_indexes = HashMap::<&str, usize>::new();
     cs.tables
         .iter()
         .map(|t| {
-            let ins = t.instructions.iter().map(|ins| match ins {
-                crate::system::Instruction::AssignCell(a, b) => {
-                    let idx = max_indexes.get_mut(a.name.as_str());
-                    let idx = match idx {
-                        Some(i) => {
-                            *i += 1;
-                            *i
-                        }
-                        None => {
-                            let name = Box::leak(a.name.clone().into_boxed_str());
-                            max_indexes.insert(name, 0);
-                            0
-                        }
-                    };
+            let ins = t.instructions.iter().map(|ins| {
+                Ok(match ins {
+                    crate::system::Instruction::AssignCell(a, b) => {
+                        let idx = max_indexes.get_mut(a.name.as_str());
+                        let idx = match idx {
+                            Some(i) => {
+                                *i += 1;
+                                *i
+                            }
+                            None => {
+                                let name = Box::leak(a.name.clone().into_boxed_str());
+                                max_indexes.insert(name, 0);
+                                0
+                            }
+                        };
 
-                    let cell_name = a.name.clone();
-                    let exp = convert_to_value(&CellExpression::Constant(b.clone()));
-                    quote! {
-                    table.assign_cell(
-                        || #cell_name,
-                        config.get_table_lookup(#cell_name)?,
-                        #idx,
-                        || #exp,
-                    )?;
+                        let cell_name = a.name.clone();
+                        let exp = convert_to_value(&CellExpression::Constant(b.clone()))?;
+                        quote! {
+                        table.assign_cell(
+                            || #cell_name,
+                            config.get_table_lookup(#cell_name)?,
+                            #idx,
+                            || #exp,
+                        )?;
 
+                        }
                     }
-                }
-                _ => todo!("illegal instruction"),
+                    _ => {
+                        return Err(unsupported(format!(
+                            "region instruction in lookup table {}",
+                            t.name
+                        )))
+                    }
+                })
             });
+            let ins = ins.collect::<Result<Vec<_>, PlonkScriptError>>()?;
 
             let table_name = t.name.clone();
 
-            quote! {
+            Ok(quote! {
                 layouter.assign_table(
                     || #table_name,
                     |mut table| {
@@ -160,7 +242,7 @@ fn get_circuit_synthesize_tables(cs: &SimplifiedConstraitSystem) -> Vec<TokenStr
                         Ok(())
                     },
                 )?;
-            }
+            })
         })
         .collect()
 }
@@ -1,43 +1,59 @@
+use crate::error::PlonkScriptError;
 use crate::generator::util::ToQuoteField;
-use crate::system::SimplifiedConstraitSystem;
+use crate::system::{FieldKind, SimplifiedConstraitSystem};
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 
-pub fn get_test(circuit_name: &str, cs: &SimplifiedConstraitSystem) -> TokenStream {
+pub fn get_test(
+    circuit_name: &str,
+    cs: &SimplifiedConstraitSystem,
+) -> Result<TokenStream, PlonkScriptError> {
     let circuit_name = format_ident!("{}", circuit_name);
-    let k = cs
-        .inputs
-        .get("k")
-        .or(Some(&"8".to_string()))
-        .unwrap()
-        .parse::<u32>()
-        .unwrap();
+    // the same k the script is run with
+    let k = cs.k()?;
 
-    let public_input = cs
-        .signals
-        .clone()
-        .into_iter()
-        .map(|x| match x.value {
-            Some(x) => x
-                .to_quote_field()
-                .expect(format!("Decoding failed: {x}").as_str()),
-            None => panic!("No value for signal [{}]", x.name),
-        })
-        .collect::<Vec<TokenStream>>();
+    let instance =
+        cs.instance_columns()
+            .into_iter()
+            .map(|(_, signals)| {
+                let values =
+                    signals
+                        .into_iter()
+                        .map(|x| match &x.value {
+                            Some(value) => value.to_quote_field().ok_or_else(|| {
+                                PlonkScriptError::InvalidInput {
+                                    name: x.name.clone(),
+                                    value: value.clone(),
+                                }
+                            }),
+                            None => Err(PlonkScriptError::MissingInput {
+                                name: x.name.clone(),
+                            }),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                Ok(quote! { vec![#(#values),*] })
+            })
+            .collect::<Result<Vec<TokenStream>, PlonkScriptError>>()?;
 
-    quote! {
+    let field = match cs.field {
+        FieldKind::Pallas => quote! { pasta::Fp },
+        FieldKind::Vesta => quote! { pasta::Fq },
+        FieldKind::Bn254 => quote! { bn256::Fr },
+    };
+
+    Ok(quote! {
         #[cfg(test)]
         mod tests {
             use super::*;
-            use halo2_proofs::{dev::MockProver, pasta::Fp as F};
+            use halo2_proofs::{dev::MockProver, halo2curves::#field as F};
 
             #[test]
             fn test_simple() {
-                let circuit = #circuit_name {
+                let circuit = #circuit_name::<F> {
                     _marker: std::marker::PhantomData,
                 };
 
-                let prover = MockProver::run(#k, &circuit, vec![vec![#(#public_input),*]]).unwrap();
+                let prover = MockProver::run(#k, &circuit, vec![#(#instance),*]).unwrap();
                 if cfg!(debug_assertions) {
                     let d = format!("{:#?}", prover);
                     let mut file = std::fs::File::create("visualization.rust").unwrap();
@@ -47,5 +63,5 @@ pub fn get_test(circuit_name: &str, cs: &SimplifiedConstraitSystem) -> TokenStre
                 prover.assert_satisfied();
             }
         }
-    }
+    })
 }
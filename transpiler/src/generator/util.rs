@@ -1,6 +1,10 @@
-use std::io;
+use std::{io, str::FromStr};
 
+use crate::diagnostic::Diagnostic;
+use crate::error::PlonkScriptError;
 use crate::system::CellExpression;
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
 use proc_macro2::TokenStream;
 use quote::quote;
 
@@ -39,11 +43,16 @@ pub fn convert_to_gate_expression(exp: &CellExpression) -> Result<TokenStream, i
                 crate::system::ColumnType::Fixed => {
                     Ok(quote! { config.query_column(meta, #col_type, #col_name, #idx).unwrap() })
                 }
-                crate::system::ColumnType::Instance => todo!(),
+                crate::system::ColumnType::Instance => {
+                    Ok(quote! { config.query_column(meta, #col_type, #col_name, #idx).unwrap() })
+                }
                 crate::system::ColumnType::ComplexSelector => {
                     Ok(quote! { config.query_column(meta, #col_type, #col_name, #idx).unwrap() })
                 }
-                crate::system::ColumnType::TableLookup => todo!(),
+                crate::system::ColumnType::TableLookup => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("table column {} cannot be queried", col_name),
+                )),
             }
         }
         CellExpression::Negated(n) => convert_to_gate_expression(n).map(|x| quote! { (-#x) }),
@@ -67,73 +76,90 @@ pub trait ToQuoteField {
 }
 
 impl ToQuoteField for String {
+    // small values stay readable, others go through their decimal form, which
+    // `from_str_vartime` reduces into the field like `ToField` does
     fn to_quote_field(&self) -> Option<TokenStream> {
-        match self {
-            s if s.starts_with("0x") => {
-                // let mut bytes = F::Repr::default();
-                // let mut view = bytes.as_mut();
-
-                let mut view = Vec::<u8>::new();
-                hex::decode_to_slice(&s[2..], &mut view).ok();
-                view.reverse();
-                // let fixview = view.as_ref::<u8>();
-                Some(quote! { F::from_repr_vartime([#(#view,)*]) })
+        let s = self.trim();
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let value = match s.strip_prefix("0x") {
+            Some(hex) => BigUint::parse_bytes(hex.as_bytes(), 16)?,
+            None => BigUint::from_str(s).ok()?,
+        };
+        let value = match value.to_u64() {
+            Some(n) => quote! { F::from(#n) },
+            None => {
+                let decimal = value.to_string();
+                quote! { F::from_str_vartime(#decimal).unwrap() }
             }
-            s => {
-                let s = s.parse::<u64>().unwrap();
-                Some(quote! {F::from(#s)})
-            }
-        }
+        };
+        Some(if negative {
+            quote! { -#value }
+        } else {
+            value
+        })
     }
 }
 
-pub fn convert_to_value(exp: &CellExpression) -> TokenStream {
-    match exp {
-        CellExpression::Calculated(c) => {
-            let val = c.to_quote_field();
-            quote! { Value::known(#val)}
-        }
-        CellExpression::Constant(c) => {
-            let val = c.to_quote_field();
+/// Code computing the value of `exp` inside a region, where `?` returns the
+/// error of a cell not assigned yet.
+pub fn convert_to_value(exp: &CellExpression) -> Result<TokenStream, PlonkScriptError> {
+    Ok(match exp {
+        CellExpression::Calculated(c) | CellExpression::Constant(c) => {
+            let val = to_field(c)?;
             quote! { Value::known(#val)}
         }
         CellExpression::CellValue(c) => {
             let name = c.name.as_str();
             match c.column.ctype {
-                crate::system::ColumnType::Selector => {
-                    quote! {config.get_assigned_cell(#name).value().copied()}
+                crate::system::ColumnType::Selector
+                | crate::system::ColumnType::Advice
+                | crate::system::ColumnType::Fixed
+                | crate::system::ColumnType::ComplexSelector => {
+                    quote! { config.get_assigned_cell(#name)?.value().copied() }
                 }
-                crate::system::ColumnType::Advice => {
-                    quote! {config.get_assigned_cell(#name).value().copied()}
+                crate::system::ColumnType::Instance | crate::system::ColumnType::TableLookup => {
+                    return Err(unsupported(format!(
+                        "{:?} cell {} in an assigned expression",
+                        c.column.ctype, c.name
+                    )))
                 }
-                crate::system::ColumnType::Fixed => {
-                    quote! {config.get_assigned_cell(#name).value().copied()}
-                }
-                crate::system::ColumnType::Instance => quote! {todo!()},
-                crate::system::ColumnType::ComplexSelector => {
-                    quote! { config.get_assigned_cell(#name).value().copied() }
-                }
-                crate::system::ColumnType::TableLookup => quote! {todo!()},
             }
         }
         CellExpression::Negated(n) => {
-            let val = convert_to_value(n);
+            let val = convert_to_value(n)?;
             quote! { -#val }
         }
         CellExpression::Product(a, b) => {
-            let val1 = convert_to_value(a);
-            let val2 = convert_to_value(b);
+            let val1 = convert_to_value(a)?;
+            let val2 = convert_to_value(b)?;
             quote! { #val1 * #val2 }
         }
         CellExpression::Sum(a, b) => {
-            let val1 = convert_to_value(a);
-            let val2 = convert_to_value(b);
+            let val1 = convert_to_value(a)?;
+            let val2 = convert_to_value(b)?;
             quote! { #val1 + #val2 }
         }
         CellExpression::Scaled(a, b) => {
-            let val1 = convert_to_value(a);
-            let val2 = convert_to_value(&CellExpression::Constant(b.clone()));
+            let val1 = convert_to_value(a)?;
+            let val2 = convert_to_value(&CellExpression::Constant(b.clone()))?;
             quote! { #val1 * #val2 }
         }
-    }
+    })
+}
+
+/// Code of the field element `c`.
+pub fn to_field(c: &String) -> Result<TokenStream, PlonkScriptError> {
+    c.to_quote_field()
+        .ok_or_else(|| unsupported(format!("{} as a field element", c)))
+}
+
+/// What a script built but the exported circuit cannot express.
+pub fn unsupported(what: impl std::fmt::Display) -> PlonkScriptError {
+    PlonkScriptError::UnsupportedOperation(Diagnostic::new(format!(
+        "cannot export the circuit, {} is not supported",
+        what
+    )))
 }
@@ -0,0 +1,86 @@
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::error::PlonkScriptError;
+
+/// Largest integer a JSON or JavaScript number holds exactly.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+/// Value of a parameter or public input given from outside the script.
+///
+/// Numbers are accepted as long as they are exact integers, larger values must
+/// be given as decimal or `0x` prefixed strings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputValue(pub String);
+
+impl From<InputValue> for String {
+    fn from(value: InputValue) -> Self {
+        value.0
+    }
+}
+
+impl<'de> Deserialize<'de> for InputValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct InputVisitor;
+
+        impl<'de> Visitor<'de> for InputVisitor {
+            type Value = InputValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an integer or a string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<InputValue, E> {
+                Ok(InputValue(v.trim().to_string()))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<InputValue, E> {
+                Ok(InputValue(v.to_string()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<InputValue, E> {
+                Ok(InputValue(v.to_string()))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<InputValue, E> {
+                if v.fract() == 0.0 && v.abs() <= MAX_SAFE_INTEGER {
+                    Ok(InputValue((v as i64).to_string()))
+                } else {
+                    Err(E::custom(format!(
+                        "{} is not an exact integer, give it as a string",
+                        v
+                    )))
+                }
+            }
+        }
+
+        deserializer.deserialize_any(InputVisitor)
+    }
+}
+
+/// Collects input values into the map taken by [`crate::try_run_with_inputs`].
+pub fn from_values(values: HashMap<String, InputValue>) -> HashMap<String, String> {
+    values
+        .into_iter()
+        .map(|(name, value)| (name, value.into()))
+        .collect()
+}
+
+/// Parses a JSON object of inputs, e.g. `{ "k": 6, "in1": "0x2a" }`.
+pub fn parse_inputs(json: &str) -> Result<HashMap<String, String>, serde_json::Error> {
+    serde_json::from_str(json).map(from_values)
+}
+
+/// [`parse_inputs`] on the content of a file.
+pub fn read_inputs(path: &Path) -> Result<HashMap<String, String>, PlonkScriptError> {
+    let invalid = |message: String| PlonkScriptError::InvalidArtifact {
+        path: path.display().to_string(),
+        message,
+    };
+    let json = fs::read_to_string(path).map_err(|e| PlonkScriptError::io(path, e))?;
+    parse_inputs(&json).map_err(|e| invalid(e.to_string()))
+}
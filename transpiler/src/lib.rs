@@ -1,34 +1,39 @@
-use std::{collections::HashMap, ptr::addr_of};
+use std::{collections::HashMap, marker::PhantomData, path::Path, sync::Arc};
 
 use circuit::MyCircuit;
-use rhai::{Engine, EvalAltResult};
-use system::{cell_expression::ToField, SimplifiedConstraitSystem};
-use transpiler::transpile;
-
-use crate::engine::PlonkScriptEngine;
-use once_cell::sync::Lazy;
+use error::PlonkScriptError;
+use failure::Failure;
+use halo2_proofs::{
+    dev::MockProver,
+    halo2curves::{
+        bn256::Fr,
+        ff::{FromUniformBytes, PrimeField},
+        pasta::{EpAffine, EqAffine, Fp, Fq},
+    },
+};
+use prover::{Backend, Ipa, Kzg, ProveResult};
+use session::Session;
+use system::{FieldKind, SimplifiedConstraitSystem};
+use system::cell_expression::ToField;
+use transpiler::{transpile_with_source_map, SourceMap};
 
+pub mod artifact;
 pub mod circuit;
+pub mod diagnostic;
 pub mod engine;
+pub mod error;
+pub mod failure;
+pub mod formatter;
 pub mod generator;
+pub mod inputs;
+pub mod parser;
+pub mod prover;
+pub mod session;
 pub mod system;
 pub mod transpiler;
 #[cfg(test)]
 mod tests;
 
-static mut CONTEXT: SimplifiedConstraitSystem = SimplifiedConstraitSystem {
-    // ..Default::default()
-    signals: Vec::new(),
-    columns: Vec::new(),
-    regions: Vec::new(),
-    tables: Vec::new(),
-    gates: Vec::new(),
-    inputs: Lazy::new(|| HashMap::new()),
-    cells: Lazy::new(|| HashMap::new()),
-    lookups: Vec::new(),
-    instance_count: 0,
-};
-
 pub struct TryRunResult {
     pub prover_result: String,
     pub transpiled_script: String,
@@ -46,107 +51,237 @@ pub fn try_run(
     code: String, 
     modules: HashMap<String, String>,
     include_details: Option<IncludeDetails>
-) -> Result<TryRunResult, Box<EvalAltResult>> {
-    unsafe {
-        CONTEXT = SimplifiedConstraitSystem {
-            // ..Default::default()
-            signals: Vec::new(),
-            columns: Vec::new(),
-            regions: Vec::new(),
-            tables: Vec::new(),
-            gates: Vec::new(),
-            inputs: Lazy::new(|| HashMap::new()),
-            cells: Lazy::new(|| HashMap::new()),
-            lookups: Vec::new(),
-            instance_count: 0,
-        };
-    }
-
-    let mut engine = Engine::new();
-
-    engine.register_plonk_script(modules);
-
-    let transpiled_script = transpile(code);
-    if cfg!(debug_assertions) {
-        let mut file = std::fs::File::create("debug.rhai").unwrap();
-        std::io::Write::write_all(&mut file, transpiled_script.as_bytes()).unwrap();
-    }
+) -> Result<TryRunResult, PlonkScriptError> {
+    try_run_with_inputs(code, modules, HashMap::new(), include_details)
+}
 
-    if let Err(error) = engine.run(transpiled_script.as_str()) {
-        println!("Script Error: {:#?}", error);
-        return Err(error);
-    }
+/// [`try_run`] with `inputs` overriding the script's `#` parameters, `k`
+/// included, and supplying or overriding the values of its public inputs.
+///
+/// See [`inputs::parse_inputs`] to read them from JSON.
+pub fn try_run_with_inputs(
+    code: String,
+    modules: HashMap<String, String>,
+    inputs: HashMap<String, String>,
+    include_details: Option<IncludeDetails>,
+) -> Result<TryRunResult, PlonkScriptError> {
+    let compiled = compile(&code, modules, inputs)?;
 
     let transpiled_script = if matches!(include_details, Some(IncludeDetails::TranspiledScript | IncludeDetails::All)) {
-        transpiled_script.clone()
+        compiled.script.clone()
     } else {
         String::new()
     };
 
     let context_debug = if matches!(include_details, Some(IncludeDetails::ContextDebug | IncludeDetails::All)) {
-        unsafe { format!("{:#?}", CONTEXT) }
+        format!("{:#?}", compiled.scs)
     } else {
         String::new()
     };
 
-    if cfg!(debug_assertions) {
-        let mut file = std::fs::File::create("context.rust").unwrap();
-        std::io::Write::write_all(&mut file, context_debug.as_bytes()).unwrap();
-    }
-
-    if cfg!(debug_assertions) && false {
-        let d = unsafe { generator::generate_rust_code(&*addr_of!(CONTEXT)) };
-        let mut file = std::fs::File::create("../export_halo2_project/src/lib.rs").unwrap();
-        std::io::Write::write_all(&mut file, d.as_bytes()).unwrap();
-    }
-
-    let k = unsafe { CONTEXT.inputs.get("k").or(Some(&"8".to_string())) }
-        .unwrap()
-        .parse::<u32>()
-        .unwrap();
-
-    let public_input = unsafe { CONTEXT.signals.clone() }
-        .into_iter()
-        .map(|x| match x.value {
-            Some(x) => x
-                .to_field()
-                .expect(format!("Decoding failed: {x}").as_str()),
-            None => panic!("No value for signal [{}]", x.name),
-        })
-        .collect();
-
-    let ret = run_prover(k, public_input);
+    let prover_result = match compiled.scs.field {
+        FieldKind::Pallas => format!("{:#?}", check_satisfied::<Fp>(&code, &compiled)?),
+        FieldKind::Vesta => format!("{:#?}", check_satisfied::<Fq>(&code, &compiled)?),
+        FieldKind::Bn254 => format!("{:#?}", check_satisfied::<Fr>(&code, &compiled)?),
+    };
 
-    ret.map(|prover_result| TryRunResult {
+    Ok(TryRunResult {
         prover_result,
         transpiled_script,
         context_debug,
-    }).map_err(|e| {
-        Box::new(EvalAltResult::ErrorSystem(
-            "Prove failed".to_string(),
-            Box::new(e),
-        ))
     })
 }
 
-fn run_prover(
+/// Runs the script and creates a real proof of it, see [`prover::prove`].
+///
+/// The circuit is checked with the MockProver first, so unsatisfied constraints
+/// are reported the same way as by [`try_run`].
+pub fn try_prove(
+    code: String,
+    modules: HashMap<String, String>,
+    inputs: HashMap<String, String>,
+) -> Result<ProveResult, PlonkScriptError> {
+    try_prove_with_params(code, modules, inputs, None)
+}
+
+/// [`try_prove`] with the params of the commitment scheme read from `params`,
+/// as written by [`artifact::write_params`], e.g. the params of a KZG setup
+/// ceremony.
+///
+/// Without them the setup is run on this machine, see
+/// [`ProveResult::local_setup`].
+pub fn try_prove_with_params(
+    code: String,
+    modules: HashMap<String, String>,
+    inputs: HashMap<String, String>,
+    params: Option<&Path>,
+) -> Result<ProveResult, PlonkScriptError> {
+    let compiled = compile(&code, modules, inputs)?;
+    match compiled.scs.field {
+        FieldKind::Pallas => prove_with::<Ipa<EqAffine>>(&code, &compiled, params),
+        FieldKind::Vesta => prove_with::<Ipa<EpAffine>>(&code, &compiled, params),
+        FieldKind::Bn254 => prove_with::<Kzg>(&code, &compiled, params),
+    }
+}
+
+/// Runs the script and checks that its circuit can be built, without proving it.
+pub fn check(
+    code: &str,
+    modules: HashMap<String, String>,
+    inputs: HashMap<String, String>,
+) -> Result<SimplifiedConstraitSystem, PlonkScriptError> {
+    let scs = compile(code, modules, inputs)?.scs;
+    Ok(Arc::try_unwrap(scs).unwrap_or_else(|scs| (*scs).clone()))
+}
+
+// a script that ran to the end, with the circuit and public inputs it describes
+struct Compiled {
+    script: String,
+    source_map: SourceMap,
+    scs: Arc<SimplifiedConstraitSystem>,
     k: u32,
-    public_input: Vec<halo2_proofs::pasta::Fp>,
-) -> Result<String, halo2_proofs::plonk::Error> {
-    let circuit = MyCircuit {
-        _marker: std::marker::PhantomData,
+}
+
+impl Compiled {
+    fn circuit<F: PrimeField>(&self) -> MyCircuit<F> {
+        MyCircuit {
+            scs: self.scs.clone(),
+            _marker: PhantomData,
+        }
+    }
+
+    // values of the instance columns, one vector each with the rows of its signals
+    fn instance<F: PrimeField>(&self) -> Result<Vec<Vec<F>>, PlonkScriptError> {
+        self.scs
+            .instance_columns()
+            .into_iter()
+            .map(|(_, signals)| {
+                signals
+                    .into_iter()
+                    .map(|x| match &x.value {
+                        Some(value) => value.to_field().ok_or_else(|| PlonkScriptError::InvalidInput {
+                            name: x.name.clone(),
+                            value: value.clone(),
+                        }),
+                        None => Err(PlonkScriptError::MissingInput {
+                            name: x.name.clone(),
+                        }),
+                    })
+                    .collect::<Result<Vec<F>, _>>()
+            })
+            .collect()
+    }
+}
+
+fn compile(
+    code: &str,
+    modules: HashMap<String, String>,
+    inputs: HashMap<String, String>,
+) -> Result<Compiled, PlonkScriptError> {
+    let session = Session::new(modules)?;
+    session.set_inputs(inputs)?;
+
+    let (script, source_map) = transpile_with_source_map(code)
+        .map_err(|e| PlonkScriptError::from_parse_error(code, &e))?;
+    session
+        .run(script.as_str())
+        .map_err(|e| PlonkScriptError::from_script_error(code, &script, &source_map, e))?;
+
+    let mut scs = session.into_system();
+    engine::bind::build_lookup_tables(&mut scs)
+        .map_err(|e| PlonkScriptError::from_script_error(code, &script, &source_map, e))?;
+    scs.merge_gates();
+    // `configure` runs inside halo2, where it cannot return an error
+    engine::gate::check_circuit(&scs)
+        .map_err(|e| PlonkScriptError::from_script_error(code, &script, &source_map, e))?;
+
+    let k = scs.k()?;
+
+    let compiled = Compiled {
+        script,
+        source_map,
+        scs: Arc::new(scs),
+        k,
     };
+    // report missing and invalid public inputs before anything is proven
+    match compiled.scs.field {
+        FieldKind::Pallas => compiled.instance::<Fp>().map(|_| ())?,
+        FieldKind::Vesta => compiled.instance::<Fq>().map(|_| ())?,
+        FieldKind::Bn254 => compiled.instance::<Fr>().map(|_| ())?,
+    }
+    Ok(compiled)
+}
 
-    let presult = halo2_proofs::dev::MockProver::run(k, &circuit, vec![public_input.clone()]);
+fn check_satisfied<F>(code: &str, compiled: &Compiled) -> Result<MockProver<F>, PlonkScriptError>
+where
+    F: PrimeField + FromUniformBytes<64> + Ord,
+{
+    let circuit = compiled.circuit::<F>();
+    let prover = run_prover(&circuit, compiled.k, compiled.instance()?)?;
 
-    presult.map(|prover| {
-        if cfg!(debug_assertions) {
-            let d = format!("{:#?}", prover);
-            let mut file = std::fs::File::create("visualization.rust").unwrap();
-            std::io::Write::write_all(&mut file, d.as_bytes()).unwrap();
-        }
+    if let Err(failures) = prover.verify() {
+        let failures = failures
+            .iter()
+            .map(|f| {
+                Failure::from_verify_failure(
+                    code,
+                    &compiled.script,
+                    &compiled.source_map,
+                    &circuit,
+                    f,
+                )
+            })
+            .collect();
+        return Err(PlonkScriptError::ConstraintUnsatisfied { failures });
+    }
+    Ok(prover)
+}
 
-        prover.assert_satisfied();
-        format!("{:#?}", prover)
-    })
+// proves with the commitment scheme `B` of the circuit's field
+fn prove_with<B: Backend>(
+    code: &str,
+    compiled: &Compiled,
+    params: Option<&Path>,
+) -> Result<ProveResult, PlonkScriptError> {
+    let params = params
+        .map(|path| {
+            let params = B::read_params(&artifact::read_params(path)?)
+                .map_err(|e| invalid_params(path, &e.to_string()))?;
+            match B::params_k(&params) {
+                k if k < compiled.k => Err(invalid_params(
+                    path,
+                    &format!(
+                        "the params are for k = {}, the circuit needs k = {}",
+                        k, compiled.k
+                    ),
+                )),
+                _ => Ok(params),
+            }
+        })
+        .transpose()?;
+    check_satisfied::<B::Scalar>(code, compiled)?;
+    Ok(prover::prove::<B>(
+        &compiled.circuit(),
+        compiled.k,
+        &compiled.instance()?,
+        params,
+    )?)
+}
+
+fn invalid_params(path: &Path, message: &str) -> PlonkScriptError {
+    PlonkScriptError::InvalidArtifact {
+        path: path.display().to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn run_prover<F>(
+    circuit: &MyCircuit<F>,
+    k: u32,
+    instance: Vec<Vec<F>>,
+) -> Result<MockProver<F>, halo2_proofs::plonk::Error>
+where
+    F: PrimeField + FromUniformBytes<64> + Ord,
+{
+    circuit.scope(|circuit| MockProver::run(k, circuit, instance))
 }
@@ -1,44 +1,380 @@
-use std::fs;
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::json;
+use transpiler::{
+    artifact, check, error::PlonkScriptError, formatter, generator, inputs::read_inputs,
+    transpiler::transpile, try_prove_with_params, try_run_with_inputs,
+};
+
+// exit codes, besides 0 for success
+const EXIT_UNSATISFIED: u8 = 1; // constraints do not hold, proof rejected or file not formatted
+const EXIT_ERROR: u8 = 2;
+
+const DEFAULT_LIB_DIR: &str = "plonk/lib";
+
+#[derive(Parser)]
+#[command(
+    name = "plonkscript",
+    version,
+    about = "Run, prove and verify PlonkScript circuits"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the script and check its constraints with the MockProver
+    Run(ScriptArgs),
+    /// Run the script and build its circuit, without checking the constraints
+    Check(ScriptArgs),
+    /// Print the columns, rows, gates and lookups of the circuit and the smallest k it fits in
+    Stats(ScriptArgs),
+    /// Print the rhai script the source is lowered to
+    Transpile {
+        script: PathBuf,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Generate a standalone halo2 circuit in Rust
+    Export {
+        #[command(flatten)]
+        script: ScriptArgs,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Create a real proof and write the params, verifying key and proof files
+    Prove {
+        #[command(flatten)]
+        script: ScriptArgs,
+        /// directory to write the files to
+        #[arg(short, long, default_value = "proof")]
+        out: PathBuf,
+        /// params of the commitment scheme, e.g. from a KZG setup ceremony; without
+        /// them a bn254 setup is run locally, which is only fit for testing
+        #[arg(long, value_name = "FILE")]
+        params: Option<PathBuf>,
+    },
+    /// Verify the proof in a directory written by `prove`
+    Verify {
+        #[arg(default_value = "proof")]
+        dir: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+    },
+    /// Format scripts in place
+    Fmt {
+        #[arg(required = true)]
+        scripts: Vec<PathBuf>,
+        /// only report the files that are not formatted
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(Args)]
+struct ScriptArgs {
+    script: PathBuf,
+    /// directory of library modules, may be repeated (default: ./plonk/lib)
+    #[arg(short = 'L', long = "lib")]
+    libs: Vec<PathBuf>,
+    /// JSON object of `#` parameters and public inputs, e.g. `{ "k": 10, "in1": "0x2a" }`
+    #[arg(long = "inputs", value_name = "FILE")]
+    inputs_file: Option<PathBuf>,
+    /// override a `#` parameter or public input, e.g. `--input k=10`, wins over `--inputs`
+    #[arg(short, long = "input", value_name = "NAME=VALUE", value_parser = parse_input)]
+    inputs: Vec<(String, String)>,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+fn parse_input(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("expected NAME=VALUE, got `{}`", s)),
+    }
+}
+
+pub fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let format = match &cli.command {
+        Command::Run(args) | Command::Check(args) | Command::Stats(args) => args.format,
+        Command::Export { script, .. } | Command::Prove { script, .. } => script.format,
+        Command::Verify { format, .. } => *format,
+        _ => Format::Text,
+    };
 
-use rhai::EvalAltResult;
-use transpiler::try_run;
+    match execute(cli.command, format) {
+        Ok(code) => code,
+        Err(error) => {
+            report_error(&error, format);
+            ExitCode::from(match error {
+                PlonkScriptError::ConstraintUnsatisfied { .. }
+                | PlonkScriptError::ProofRejected(_) => EXIT_UNSATISFIED,
+                _ => EXIT_ERROR,
+            })
+        }
+    }
+}
 
-fn resolve_lib_modules() -> HashMap<String, String> {
-    let current_dir = std::env::current_dir().unwrap();
-    let dir_name = "plonk/lib";
-    let lib_dir = current_dir.join(dir_name);
+fn execute(command: Command, format: Format) -> Result<ExitCode, PlonkScriptError> {
+    match command {
+        Command::Run(args) => {
+            let code = read_script(&args.script)?;
+            try_run_with_inputs(code, args.modules()?, args.inputs()?, None)?;
+            print(
+                format,
+                "constraints satisfied",
+                json!({ "status": "satisfied" }),
+            );
+        }
+        Command::Check(args) => {
+            let code = read_script(&args.script)?;
+            let cs = check(&code, args.modules()?, args.inputs()?)?;
+            print(
+                format,
+                &format!(
+                    "ok: {} columns, {} gates, {} lookups, {} regions\n\
+                     degree {}, the quotient is computed on {} times the rows",
+                    cs.columns.len(),
+                    cs.gate_groups().len(),
+                    cs.lookups.len(),
+                    cs.regions.len(),
+                    cs.degree(),
+                    cs.extended_domain_factor()
+                ),
+                json!({
+                    "status": "ok",
+                    "columns": cs.columns.len(),
+                    "gates": cs.gate_groups().len(),
+                    "lookups": cs.lookups.len(),
+                    "regions": cs.regions.len(),
+                    "degree": cs.degree(),
+                    "extended_domain_factor": cs.extended_domain_factor(),
+                    "gate_degrees": cs.gate_degrees().into_iter().collect::<HashMap<_, _>>(),
+                }),
+            );
+        }
+        Command::Stats(args) => {
+            let code = read_script(&args.script)?;
+            let stats = check(&code, args.modules()?, args.inputs()?)?.stats();
+            let regions = stats
+                .region_rows
+                .iter()
+                .map(|(name, rows)| format!("  {}: {} rows", name, rows))
+                .collect::<Vec<_>>()
+                .join("\n");
+            print(
+                format,
+                &format!(
+                    "columns: {} advice, {} fixed, {} instance, {} selector, {} table\n\
+                     rows: {}, {} more for blinding, fits in k = {}\n\
+                     {}\n\
+                     gates: {} of degree {} at most\n\
+                     lookups: {}\n\
+                     permutation columns: {}",
+                    stats.advice_columns,
+                    stats.fixed_columns,
+                    stats.instance_columns,
+                    stats.selector_columns,
+                    stats.table_columns,
+                    stats.rows,
+                    stats.blinding_rows,
+                    stats.min_k,
+                    regions,
+                    stats.gates,
+                    stats.degree,
+                    stats.lookups,
+                    stats.permutation_columns
+                ),
+                json!(stats),
+            );
+        }
+        Command::Transpile { script, output } => {
+            let code = read_script(&script)?;
+            let script_text = transpile(code.clone())
+                .map_err(|e| PlonkScriptError::from_parse_error(&code, &e))?;
+            write_output(output.as_deref(), &script_text)?;
+        }
+        Command::Export { script, output } => {
+            let code = read_script(&script.script)?;
+            let cs = check(&code, script.modules()?, script.inputs()?)?;
+            write_output(output.as_deref(), &generator::generate_rust_code(&cs)?)?;
+        }
+        Command::Prove {
+            script,
+            out,
+            params,
+        } => {
+            let code = read_script(&script.script)?;
+            let result = try_prove_with_params(
+                code,
+                script.modules()?,
+                script.inputs()?,
+                params.as_deref(),
+            )?;
+            if result.local_setup {
+                eprintln!(
+                    "warning: the params come from a trusted setup run on this machine, \
+                     whoever keeps its secret can forge proofs; use them for testing only \
+                     or pass `--params` from a setup ceremony"
+                );
+            }
+            result.write_to(&out)?;
+            let t = result.timings;
+            print(
+                format,
+                &format!(
+                    "proved over {} with k = {}, {} bytes, written to {}\n\
+                     setup {:?}, keygen_vk {:?}, keygen_pk {:?}, prove {:?}, verify {:?}, total {:?}",
+                    result.circuit.field,
+                    result.k,
+                    result.proof.len(),
+                    out.display(),
+                    t.setup,
+                    t.keygen_vk,
+                    t.keygen_pk,
+                    t.prove,
+                    t.verify,
+                    t.total()
+                ),
+                json!({
+                    "status": "proved",
+                    "field": result.circuit.field,
+                    "k": result.k,
+                    "proof_size": result.proof.len(),
+                    "verifying_key": result.verifying_key_digest(),
+                    "local_setup": result.local_setup,
+                    "out": out.display().to_string(),
+                    "timings_ms": {
+                        "setup": t.setup.as_secs_f64() * 1000.0,
+                        "keygen_vk": t.keygen_vk.as_secs_f64() * 1000.0,
+                        "keygen_pk": t.keygen_pk.as_secs_f64() * 1000.0,
+                        "prove": t.prove.as_secs_f64() * 1000.0,
+                        "verify": t.verify.as_secs_f64() * 1000.0,
+                        "total": t.total().as_secs_f64() * 1000.0,
+                    },
+                }),
+            );
+        }
+        Command::Verify { dir, .. } => {
+            artifact::verify_dir(&dir)?;
+            print(format, "proof verified", json!({ "status": "verified" }));
+        }
+        Command::Fmt { scripts, check } => {
+            let mut unformatted = false;
+            for path in scripts {
+                let code = read_script(&path)?;
+                let formatted = formatter::format(&code)
+                    .map_err(|e| PlonkScriptError::from_parse_error(&code, &e))?;
+                if formatted == code {
+                    continue;
+                }
+                if check {
+                    println!("{}", path.display());
+                    unformatted = true;
+                } else {
+                    fs::write(&path, formatted).map_err(|e| PlonkScriptError::io(&path, e))?;
+                }
+            }
+            if unformatted {
+                return Ok(ExitCode::from(EXIT_UNSATISFIED));
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-    if !lib_dir.exists() {
-        panic!("The '{dir_name}' directory does not exist in the current directory.");
+impl ScriptArgs {
+    fn inputs(&self) -> Result<HashMap<String, String>, PlonkScriptError> {
+        let mut inputs = match &self.inputs_file {
+            Some(path) => read_inputs(path)?,
+            None => HashMap::new(),
+        };
+        inputs.extend(self.inputs.iter().cloned());
+        Ok(inputs)
     }
-    
-    let mut modules = HashMap::new();
-    for entry in fs::read_dir(lib_dir).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
 
-        if path.extension().and_then(|s| s.to_str()) == Some("plonk") {
-            let code = fs::read_to_string(path.clone()).expect("cannot read library");
-            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
-            modules.insert(name, code);
+    // every `.plonk` file of the library directories, by file name; the first
+    // directory wins when a name appears twice
+    fn modules(&self) -> Result<HashMap<String, String>, PlonkScriptError> {
+        let default = [PathBuf::from(DEFAULT_LIB_DIR)];
+        let dirs = match self.libs.as_slice() {
+            [] if default[0].is_dir() => &default[..],
+            dirs => dirs,
+        };
+
+        let mut modules = HashMap::new();
+        for dir in dirs {
+            let entries = fs::read_dir(dir).map_err(|e| PlonkScriptError::io(dir, e))?;
+            for entry in entries {
+                let path = entry.map_err(|e| PlonkScriptError::io(dir, e))?.path();
+                if path.extension().and_then(|s| s.to_str()) != Some("plonk") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                if !modules.contains_key(name) {
+                    modules.insert(name.to_string(), read_script(&path)?);
+                }
+            }
         }
+        Ok(modules)
     }
-    modules
 }
 
-#[allow(unreachable_code)]
-pub fn main() -> Result<(), Box<EvalAltResult>> {
-    let modules = resolve_lib_modules();
-    let code = fs::read_to_string("plonk/src/simple_demo.plonk").expect("read file failed");
-    let output = try_run(code, modules, Some(transpiler::IncludeDetails::All));
-    match output {
-        Ok(_) => {
-            println!("Done");
+fn read_script(path: &Path) -> Result<String, PlonkScriptError> {
+    fs::read_to_string(path).map_err(|e| PlonkScriptError::io(path, e))
+}
+
+fn write_output(path: Option<&Path>, text: &str) -> Result<(), PlonkScriptError> {
+    match path {
+        Some(path) => fs::write(path, text).map_err(|e| PlonkScriptError::io(path, e)),
+        None => {
+            print!("{}", text);
+            Ok(())
         }
-        Err(e) => {
-            println!("Script Error: {:#?}", e);
+    }
+}
+
+fn print(format: Format, text: &str, value: serde_json::Value) {
+    match format {
+        Format::Text => println!("{}", text),
+        Format::Json => println!("{}", value),
+    }
+}
+
+fn report_error(error: &PlonkScriptError, format: Format) {
+    match format {
+        Format::Text => eprint!("{}", error),
+        Format::Json => {
+            let failures = match error {
+                PlonkScriptError::ConstraintUnsatisfied { failures } => json!(failures),
+                _ => json!([]),
+            };
+            let status = match error {
+                PlonkScriptError::ConstraintUnsatisfied { .. } => "unsatisfied",
+                PlonkScriptError::ProofRejected(_) => "rejected",
+                _ => "error",
+            };
+            println!(
+                "{}",
+                json!({ "status": status, "message": error.to_string(), "failures": failures })
+            );
         }
     }
-    Ok(())
 }
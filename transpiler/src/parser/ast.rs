@@ -0,0 +1,162 @@
+use super::Span;
+
+/// A parsed PlonkScript source file.
+///
+/// Anything that is not PlonkScript syntax (plain rhai code such as `let`, `for`,
+/// `fn`) is kept as [`Statement::Host`], with spans pointing back into the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    /// `# k: 4`
+    Parameter(Parameter),
+    /// `pub input in1;`
+    Signal(Signal),
+    /// `col advice a;`
+    Column(ColumnDeclaration),
+    /// `region fib(a, b) { ... }`
+    Region(Region),
+    /// `gate add(a, b, c) { a[0] + b[0] - c[0]; }`
+    Gate(Gate),
+    /// `a[0] <== in1;`, `b[1] <-- a[0] * 2;`, `a[0] === b[0];`
+    Constraint(Constraint),
+    /// `s[0] <-- enable;`
+    EnableSelector(EnableSelector),
+    /// Rhai code, passed through untouched apart from nested blocks.
+    Host(HostStatement),
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Parameter(x) => x.span,
+            Statement::Signal(x) => x.span,
+            Statement::Column(x) => x.span,
+            Statement::Region(x) => x.span,
+            Statement::Gate(x) => x.span,
+            Statement::Constraint(x) => x.span,
+            Statement::EnableSelector(x) => x.span,
+            Statement::Host(x) => x.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A raw rhai expression, referenced by its span in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expr {
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: Ident,
+    pub value: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalKind {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signal {
+    pub kind: SignalKind,
+    pub name: Ident,
+    /// instance column the signal is placed in, the default one when `None`
+    pub column: Option<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Advice,
+    Fixed,
+    Instance,
+    Selector,
+    Lookup,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnDeclaration {
+    pub kind: ColumnKind,
+    pub name: Ident,
+    /// `@equality`, the column takes part in copy constraints even when the
+    /// script copies none of its cells
+    pub equality: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: Ident,
+    pub parameters: Vec<Ident>,
+    pub body: Block,
+    pub span: Span,
+}
+
+/// A custom gate: every constraint is an expression over the parameter columns
+/// that must be zero on the rows the gate is enabled on, the indices of its
+/// cells are rotations relative to that row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gate {
+    pub name: Ident,
+    pub parameters: Vec<Ident>,
+    pub constraints: Vec<Expr>,
+    /// `{ ... }`, including both braces
+    pub body: Span,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintOp {
+    /// `<==`, assign a value and constrain the cell to it
+    ConstrainAssign,
+    /// `<--`, assign a value without constraining it
+    Assign,
+    /// `===`, constrain two cells to be equal
+    ConstrainEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub target: Expr,
+    pub op: ConstraintOp,
+    pub value: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnableSelector {
+    pub target: Expr,
+    pub span: Span,
+}
+
+/// `{ ... }`, the span includes both braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPart {
+    Code(Span),
+    Block(Block),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostStatement {
+    pub parts: Vec<HostPart>,
+    pub span: Span,
+}
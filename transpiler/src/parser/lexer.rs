@@ -0,0 +1,200 @@
+use super::{ParseError, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident,
+    Number,
+    String,
+    Char,
+    Comment,
+    Whitespace,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Semicolon,
+    Comma,
+    Colon,
+    Hash,
+    ConstrainAssign, // <==
+    Assign,          // <--
+    ConstrainEqual,  // ===
+    Punct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn is_trivia(&self) -> bool {
+        matches!(self.kind, TokenKind::Comment | TokenKind::Whitespace)
+    }
+}
+
+// longest first, so that `<==` wins over `<=` and `===` over `==`
+const PUNCTS: [&str; 31] = [
+    "<==", "<--", "===", "..=", "::", "..", "=>", "->", "==", "!=", "<=", ">=", "<<", ">>", "&&",
+    "||", "**", "+=", "-=", "*=", "/=", "%=", "?.", "?[", "??", "|", "&", "^", "!", "@", "$",
+];
+
+pub fn tokenize(src: &str) -> Result<Vec<Token>, ParseError> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+        let kind = match c {
+            b' ' | b'\t' | b'\r' | b'\n' => {
+                while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                TokenKind::Whitespace
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'/') => {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                TokenKind::Comment
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                // block comments nest, as in rhai
+                let mut depth = 0;
+                loop {
+                    match (bytes.get(pos), bytes.get(pos + 1)) {
+                        (Some(b'/'), Some(b'*')) => {
+                            depth += 1;
+                            pos += 2;
+                        }
+                        (Some(b'*'), Some(b'/')) => {
+                            depth -= 1;
+                            pos += 2;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        (Some(_), _) => pos += 1,
+                        (None, _) => {
+                            return Err(ParseError::new(
+                                "unterminated block comment",
+                                Span::new(start, pos),
+                            ))
+                        }
+                    }
+                }
+                TokenKind::Comment
+            }
+            b'"' | b'`' | b'\'' => {
+                pos = skip_quoted(bytes, pos).ok_or_else(|| {
+                    ParseError::new("unterminated literal", Span::new(start, src.len()))
+                })?;
+                if c == b'\'' {
+                    TokenKind::Char
+                } else {
+                    TokenKind::String
+                }
+            }
+            b'0'..=b'9' => {
+                pos = skip_number(bytes, pos);
+                TokenKind::Number
+            }
+            c if c == b'_' || c.is_ascii_alphabetic() => {
+                while pos < bytes.len()
+                    && (bytes[pos] == b'_' || bytes[pos].is_ascii_alphanumeric())
+                {
+                    pos += 1;
+                }
+                TokenKind::Ident
+            }
+            b'(' => single(&mut pos, TokenKind::LParen),
+            b')' => single(&mut pos, TokenKind::RParen),
+            b'[' => single(&mut pos, TokenKind::LBracket),
+            b']' => single(&mut pos, TokenKind::RBracket),
+            b'{' => single(&mut pos, TokenKind::LBrace),
+            b'}' => single(&mut pos, TokenKind::RBrace),
+            b';' => single(&mut pos, TokenKind::Semicolon),
+            b',' => single(&mut pos, TokenKind::Comma),
+            b'#' => single(&mut pos, TokenKind::Hash),
+            b':' if bytes.get(pos + 1) != Some(&b':') => single(&mut pos, TokenKind::Colon),
+            _ => {
+                let rest = &src[pos..];
+                match PUNCTS.iter().find(|p| rest.starts_with(*p)) {
+                    Some(&"<==") => {
+                        pos += 3;
+                        TokenKind::ConstrainAssign
+                    }
+                    Some(&"<--") => {
+                        pos += 3;
+                        TokenKind::Assign
+                    }
+                    Some(&"===") => {
+                        pos += 3;
+                        TokenKind::ConstrainEqual
+                    }
+                    Some(p) => {
+                        pos += p.len();
+                        TokenKind::Punct
+                    }
+                    None => {
+                        pos += rest.chars().next().map_or(1, char::len_utf8);
+                        TokenKind::Punct
+                    }
+                }
+            }
+        };
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, pos),
+        });
+    }
+
+    Ok(tokens)
+}
+
+fn single(pos: &mut usize, kind: TokenKind) -> TokenKind {
+    *pos += 1;
+    kind
+}
+
+// returns the position right after the closing quote
+fn skip_quoted(bytes: &[u8], start: usize) -> Option<usize> {
+    let quote = bytes[start];
+    let mut pos = start + 1;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\\' if quote != b'`' => pos += 2,
+            c if c == quote => return Some(pos + 1),
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+fn skip_number(bytes: &[u8], start: usize) -> usize {
+    let mut pos = start;
+    if bytes[pos] == b'0' && matches!(bytes.get(pos + 1), Some(b'x' | b'o' | b'b')) {
+        pos += 2;
+        while pos < bytes.len() && (bytes[pos] == b'_' || bytes[pos].is_ascii_hexdigit()) {
+            pos += 1;
+        }
+        return pos;
+    }
+
+    while pos < bytes.len() && (bytes[pos] == b'_' || bytes[pos].is_ascii_digit()) {
+        pos += 1;
+    }
+    // `1.5` is a float, `0..r` is a range
+    if bytes.get(pos) == Some(&b'.') && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit) {
+        pos += 1;
+        while pos < bytes.len() && (bytes[pos] == b'_' || bytes[pos].is_ascii_digit()) {
+            pos += 1;
+        }
+    }
+    pos
+}
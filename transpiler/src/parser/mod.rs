@@ -0,0 +1,566 @@
+pub mod ast;
+pub mod lexer;
+
+use std::fmt;
+
+use self::{
+    ast::*,
+    lexer::{tokenize, Token, TokenKind},
+};
+
+/// Byte range in the source, `end` is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn text<'a>(&self, src: &'a str) -> &'a str {
+        &src[self.start..self.end]
+    }
+
+    /// 1-based line and column of the start of the span.
+    pub fn location(&self, src: &str) -> (usize, usize) {
+        let before = &src[..self.start.min(src.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        (line, column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        ParseError {
+            message: message.into(),
+            span,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    pub(crate) fn locate(self, src: &str) -> Self {
+        let (line, column) = self.span.location(src);
+        ParseError {
+            line,
+            column,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (line {}, column {})",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(src: &str) -> Result<Program, ParseError> {
+    let tokens = tokenize(src)
+        .map_err(|e| e.locate(src))?
+        .into_iter()
+        .filter(|t| !t.is_trivia())
+        .collect();
+    let mut parser = Parser {
+        src,
+        tokens,
+        pos: 0,
+        last_end: 0,
+    };
+    let statements = parser.statements(false).map_err(|e| e.locate(src))?;
+    Ok(Program { statements })
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    // end of the last consumed token
+    last_end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_nth(&self, n: usize) -> Option<Token> {
+        self.tokens.get(self.pos + n).copied()
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.peek_nth(0)
+    }
+
+    fn is(&self, n: usize, kind: TokenKind) -> bool {
+        self.peek_nth(n).is_some_and(|t| t.kind == kind)
+    }
+
+    fn is_ident(&self, n: usize, name: &str) -> bool {
+        self.peek_nth(n)
+            .is_some_and(|t| t.kind == TokenKind::Ident && t.span.text(self.src) == name)
+    }
+
+    fn is_punct(&self, n: usize, text: &str) -> bool {
+        self.peek_nth(n)
+            .is_some_and(|t| t.kind == TokenKind::Punct && t.span.text(self.src) == text)
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.tokens[self.pos];
+        self.pos += 1;
+        self.last_end = token.span.end;
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<Token, ParseError> {
+        match self.peek() {
+            Some(t) if t.kind == kind => Ok(self.bump()),
+            Some(t) => Err(ParseError::new(
+                format!("expected {}, found `{}`", what, t.span.text(self.src)),
+                t.span,
+            )),
+            None => Err(self.eof_error(what)),
+        }
+    }
+
+    fn ident(&mut self) -> Result<Ident, ParseError> {
+        let token = self.expect(TokenKind::Ident, "identifier")?;
+        Ok(Ident {
+            name: token.span.text(self.src).to_string(),
+            span: token.span,
+        })
+    }
+
+    fn eof_error(&self, what: &str) -> ParseError {
+        let end = self.src.len();
+        ParseError::new(
+            format!("expected {}, found end of file", what),
+            Span::new(end, end),
+        )
+    }
+
+    fn statements(&mut self, in_block: bool) -> Result<Vec<Statement>, ParseError> {
+        let mut statements = vec![];
+        loop {
+            match self.peek() {
+                None if in_block => return Err(self.eof_error("`}`")),
+                None => break,
+                Some(t) if t.kind == TokenKind::RBrace => {
+                    if in_block {
+                        break;
+                    }
+                    return Err(ParseError::new("unexpected `}`", t.span));
+                }
+                Some(_) => statements.push(self.statement()?),
+            }
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        if self.is(0, TokenKind::Hash)
+            && self.at_line_start()
+            && self.is(1, TokenKind::Ident)
+            && self.is(2, TokenKind::Colon)
+        {
+            return self.parameter();
+        }
+
+        let is_declaration = self.is(1, TokenKind::Ident)
+            && self.is(2, TokenKind::Ident)
+            && self.is(3, TokenKind::Semicolon);
+        // pub input in1 @ x; or col advice a @equality;
+        let is_annotated_declaration = self.is(1, TokenKind::Ident)
+            && self.is(2, TokenKind::Ident)
+            && self.is_punct(3, "@")
+            && self.is(4, TokenKind::Ident)
+            && self.is(5, TokenKind::Semicolon);
+        if (is_declaration || is_annotated_declaration) && self.is_ident(0, "pub") {
+            return self.signal();
+        }
+        if (is_declaration || is_annotated_declaration) && self.is_ident(0, "col") {
+            return self.column();
+        }
+        if self.is_ident(0, "region")
+            && self.is(1, TokenKind::Ident)
+            && self.is(2, TokenKind::LParen)
+        {
+            return self.region();
+        }
+        if self.is_ident(0, "gate") && self.is(1, TokenKind::Ident) && self.is(2, TokenKind::LParen)
+        {
+            return self.gate();
+        }
+
+        self.host_or_constraint()
+    }
+
+    fn at_line_start(&self) -> bool {
+        let start = self.peek().map_or(0, |t| t.span.start);
+        self.src[..start]
+            .chars()
+            .rev()
+            .take_while(|c| *c != '\n')
+            .all(char::is_whitespace)
+    }
+
+    // # name: value
+    fn parameter(&mut self) -> Result<Statement, ParseError> {
+        let start = self.bump().span.start;
+        let name = self.ident()?;
+        let colon = self.bump();
+
+        let line_end = self.src[colon.span.end..]
+            .find('\n')
+            .map_or(self.src.len(), |i| colon.span.end + i);
+        let mut value = Span::new(line_end, line_end);
+        while let Some(t) = self.peek() {
+            if t.span.start >= line_end {
+                break;
+            }
+            if value.start == line_end {
+                value.start = t.span.start;
+            }
+            value.end = self.bump().span.end;
+        }
+        if value.start == value.end {
+            return Err(ParseError::new(
+                format!("missing value for parameter `{}`", name.name),
+                name.span,
+            ));
+        }
+
+        Ok(Statement::Parameter(Parameter {
+            name,
+            value: Expr { span: value },
+            span: Span::new(start, value.end),
+        }))
+    }
+
+    // pub input in1; or pub input in1 @ x;
+    fn signal(&mut self) -> Result<Statement, ParseError> {
+        let start = self.bump().span.start;
+        let kind = self.ident()?;
+        let kind = match kind.name.as_str() {
+            "input" => SignalKind::Input,
+            "output" => SignalKind::Output,
+            other => {
+                return Err(ParseError::new(
+                    format!(
+                        "unknown signal kind `{}`, expected `input` or `output`",
+                        other
+                    ),
+                    kind.span,
+                ))
+            }
+        };
+        let name = self.ident()?;
+        let column = if self.is_punct(0, "@") {
+            self.bump();
+            Some(self.ident()?)
+        } else {
+            None
+        };
+        let end = self.bump().span.end;
+        Ok(Statement::Signal(Signal {
+            kind,
+            name,
+            column,
+            span: Span::new(start, end),
+        }))
+    }
+
+    // col advice a; or col advice a @equality;
+    fn column(&mut self) -> Result<Statement, ParseError> {
+        let start = self.bump().span.start;
+        let kind = self.ident()?;
+        let kind = match kind.name.as_str() {
+            "advice" => ColumnKind::Advice,
+            "fixed" => ColumnKind::Fixed,
+            "instance" => ColumnKind::Instance,
+            "selector" => ColumnKind::Selector,
+            "lookup" => ColumnKind::Lookup,
+            other => {
+                return Err(ParseError::new(
+                    format!("unknown column kind `{}`", other),
+                    kind.span,
+                ))
+            }
+        };
+        let name = self.ident()?;
+        let equality = if self.is_punct(0, "@") {
+            self.bump();
+            let annotation = self.ident()?;
+            if annotation.name != "equality" {
+                return Err(ParseError::new(
+                    format!(
+                        "unknown column annotation `@{}`, expected `@equality`",
+                        annotation.name
+                    ),
+                    annotation.span,
+                ));
+            }
+            true
+        } else {
+            false
+        };
+        let end = self.bump().span.end;
+        Ok(Statement::Column(ColumnDeclaration {
+            kind,
+            name,
+            equality,
+            span: Span::new(start, end),
+        }))
+    }
+
+    // region name(a, b) { ... }
+    fn region(&mut self) -> Result<Statement, ParseError> {
+        let start = self.bump().span.start;
+        let name = self.ident()?;
+        let parameters = self.parameters()?;
+
+        let body = self.block()?;
+        Ok(Statement::Region(Region {
+            name,
+            parameters,
+            span: Span::new(start, body.span.end),
+            body,
+        }))
+    }
+
+    // (a, b)
+    fn parameters(&mut self) -> Result<Vec<Ident>, ParseError> {
+        self.bump();
+        let mut parameters = vec![];
+        if !self.is(0, TokenKind::RParen) {
+            loop {
+                parameters.push(self.ident()?);
+                if !self.is(0, TokenKind::Comma) {
+                    break;
+                }
+                self.bump();
+            }
+        }
+        self.expect(TokenKind::RParen, "`)`")?;
+        Ok(parameters)
+    }
+
+    // gate name(a, b) { a[0] - b[0]; a[1] * b[1]; }
+    fn gate(&mut self) -> Result<Statement, ParseError> {
+        let start = self.bump().span.start;
+        let name = self.ident()?;
+        let parameters = self.parameters()?;
+
+        let body_start = self.expect(TokenKind::LBrace, "`{`")?.span.start;
+        let mut constraints = vec![];
+        while !self.is(0, TokenKind::RBrace) {
+            constraints.push(self.gate_constraint()?);
+        }
+        let end = self.bump().span.end;
+        if constraints.is_empty() {
+            return Err(ParseError::new(
+                format!("gate `{}` has no constraints", name.name),
+                name.span,
+            ));
+        }
+
+        Ok(Statement::Gate(Gate {
+            name,
+            parameters,
+            constraints,
+            body: Span::new(body_start, end),
+            span: Span::new(start, end),
+        }))
+    }
+
+    // an expression of a gate, up to its `;`
+    fn gate_constraint(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek().map_or(self.src.len(), |t| t.span.start);
+        let mut depth = 0usize;
+        loop {
+            let Some(t) = self.peek() else {
+                return Err(self.eof_error("`}`"));
+            };
+            match t.kind {
+                TokenKind::Semicolon if depth == 0 => break,
+                TokenKind::RBrace if depth == 0 => {
+                    return Err(ParseError::new("expected `;` after the constraint", t.span))
+                }
+                TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => depth += 1,
+                TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => {
+                    depth = depth.saturating_sub(1)
+                }
+                TokenKind::ConstrainAssign | TokenKind::Assign | TokenKind::ConstrainEqual => {
+                    return Err(ParseError::new(
+                        format!(
+                            "unexpected `{}`, the constraints of a gate are expressions that must be zero",
+                            t.span.text(self.src)
+                        ),
+                        t.span,
+                    ));
+                }
+                _ => {}
+            }
+            self.bump();
+        }
+        let span = Span::new(start, self.last_end);
+        let semicolon = self.bump();
+        if span.start >= span.end {
+            return Err(ParseError::new("empty constraint", semicolon.span));
+        }
+        Ok(Expr { span })
+    }
+
+    fn block(&mut self) -> Result<Block, ParseError> {
+        let start = self.expect(TokenKind::LBrace, "`{`")?.span.start;
+        let statements = self.statements(true)?;
+        let end = self.bump().span.end;
+        Ok(Block {
+            statements,
+            span: Span::new(start, end),
+        })
+    }
+
+    fn host_or_constraint(&mut self) -> Result<Statement, ParseError> {
+        let start = self.peek().map_or(0, |t| t.span.start);
+        let mut parts = vec![];
+        let mut code_start = start;
+        let mut depth = 0usize;
+
+        while let Some(t) = self.peek() {
+            match t.kind {
+                TokenKind::LParen | TokenKind::LBracket => {
+                    depth += 1;
+                    self.bump();
+                }
+                TokenKind::RParen | TokenKind::RBracket => {
+                    depth = depth.saturating_sub(1);
+                    self.bump();
+                }
+                TokenKind::RBrace => break,
+                TokenKind::LBrace => {
+                    if code_start < t.span.start {
+                        parts.push(HostPart::Code(Span::new(code_start, t.span.start)));
+                    }
+                    let block = self.block()?;
+                    code_start = block.span.end;
+                    parts.push(HostPart::Block(block));
+
+                    // a block ends the statement unless the expression goes on
+                    if depth == 0 && !(self.is_ident(0, "else") || self.continues_expression()) {
+                        if self.is(0, TokenKind::Semicolon) {
+                            self.bump();
+                        }
+                        break;
+                    }
+                }
+                TokenKind::Semicolon if depth == 0 => {
+                    self.bump();
+                    break;
+                }
+                TokenKind::ConstrainAssign | TokenKind::Assign | TokenKind::ConstrainEqual => {
+                    if depth > 0 || !parts.is_empty() || t.span.start == start {
+                        return Err(ParseError::new(
+                            format!("`{}` must follow the target cell", t.span.text(self.src)),
+                            t.span,
+                        ));
+                    }
+                    return self.constraint(start);
+                }
+                _ => {
+                    self.bump();
+                }
+            }
+        }
+
+        if code_start < self.last_end {
+            parts.push(HostPart::Code(Span::new(code_start, self.last_end)));
+        }
+        Ok(Statement::Host(HostStatement {
+            parts,
+            span: Span::new(start, self.last_end),
+        }))
+    }
+
+    fn continues_expression(&self) -> bool {
+        self.peek().is_some_and(|t| {
+            t.kind == TokenKind::Punct && matches!(t.span.text(self.src), "." | "?." | "?[" | "??")
+        })
+    }
+
+    // target <== value;
+    fn constraint(&mut self, start: usize) -> Result<Statement, ParseError> {
+        let target = Span::new(start, self.last_end);
+        let op_token = self.bump();
+        let op = match op_token.kind {
+            TokenKind::ConstrainAssign => ConstraintOp::ConstrainAssign,
+            TokenKind::Assign => ConstraintOp::Assign,
+            _ => ConstraintOp::ConstrainEqual,
+        };
+
+        let value_start = self.peek().map_or(self.src.len(), |t| t.span.start);
+        let mut depth = 0usize;
+        loop {
+            let Some(t) = self.peek() else {
+                return Err(self.eof_error("`;`"));
+            };
+            match t.kind {
+                TokenKind::Semicolon if depth == 0 => break,
+                TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => depth += 1,
+                TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => {
+                    if depth == 0 {
+                        return Err(ParseError::new(
+                            format!("expected `;` after `{}`", op_token.span.text(self.src)),
+                            t.span,
+                        ));
+                    }
+                    depth -= 1;
+                }
+                TokenKind::ConstrainAssign | TokenKind::Assign | TokenKind::ConstrainEqual => {
+                    return Err(ParseError::new(
+                        format!("unexpected `{}`, missing `;`?", t.span.text(self.src)),
+                        t.span,
+                    ));
+                }
+                _ => {}
+            }
+            self.bump();
+        }
+        let value = Span::new(value_start, self.last_end);
+        let end = self.bump().span.end;
+
+        if value.start >= value.end {
+            return Err(ParseError::new(
+                format!("missing value after `{}`", op_token.span.text(self.src)),
+                op_token.span,
+            ));
+        }
+
+        let span = Span::new(start, end);
+        let target = Expr { span: target };
+        if op == ConstraintOp::Assign && value.text(self.src) == "enable" {
+            return Ok(Statement::EnableSelector(EnableSelector { target, span }));
+        }
+        Ok(Statement::Constraint(Constraint {
+            target,
+            op,
+            value: Expr { span: value },
+            span,
+        }))
+    }
+}
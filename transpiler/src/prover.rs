@@ -0,0 +1,389 @@
+use std::{
+    io,
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    halo2curves::{
+        bn256::{Bn256, Fr, G1Affine},
+        ff::{FromUniformBytes, PrimeField, WithSmallOrderMulGroup},
+        serde::SerdeObject,
+    },
+    plonk::{self, Error, ProvingKey, VerifyingKey},
+    poly::{
+        commitment::{Params, ParamsProver},
+        ipa::{
+            commitment::{IPACommitmentScheme, ParamsIPA},
+            multiopen::{ProverIPA, VerifierIPA},
+            strategy::SingleStrategy as IpaStrategy,
+        },
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG},
+            multiopen::{ProverSHPLONK, VerifierSHPLONK},
+            strategy::SingleStrategy as KzgStrategy,
+        },
+    },
+    transcript::{Blake2bRead, Blake2bWrite, Challenge255},
+    SerdeFormat,
+};
+use rand_core::OsRng;
+
+use crate::{
+    circuit::MyCircuit,
+    system::{cell_expression::field_to_string, SimplifiedConstraitSystem},
+};
+
+/// Commitment scheme the circuits over one field are proven with.
+pub trait Backend {
+    /// curve the commitments are made on, the circuit is defined over its scalar field
+    type Curve: CurveAffine<ScalarExt = Self::Scalar> + SerdeObject;
+    type Scalar: PrimeField + FromUniformBytes<64> + SerdeObject + Ord;
+    type Params;
+    /// whether [`Backend::setup`] makes a secret that lets whoever keeps it
+    /// forge proofs
+    const TRUSTED_SETUP: bool;
+
+    fn setup(k: u32) -> Self::Params;
+    fn read_params(bytes: &[u8]) -> io::Result<Self::Params>;
+    fn write_params(params: &Self::Params) -> io::Result<Vec<u8>>;
+    fn params_k(params: &Self::Params) -> u32;
+    fn downsize_params(params: &mut Self::Params, k: u32);
+    fn keygen_vk(
+        params: &Self::Params,
+        circuit: &MyCircuit<Self::Scalar>,
+    ) -> Result<VerifyingKey<Self::Curve>, Error>;
+    fn keygen_pk(
+        params: &Self::Params,
+        vk: VerifyingKey<Self::Curve>,
+        circuit: &MyCircuit<Self::Scalar>,
+    ) -> Result<ProvingKey<Self::Curve>, Error>;
+    fn create_proof(
+        params: &Self::Params,
+        pk: &ProvingKey<Self::Curve>,
+        circuit: &MyCircuit<Self::Scalar>,
+        instances: &[&[Self::Scalar]],
+    ) -> Result<Vec<u8>, Error>;
+    fn verify_proof(
+        params: &Self::Params,
+        vk: &VerifyingKey<Self::Curve>,
+        instances: &[&[Self::Scalar]],
+        proof: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Serializes `vk` with [`VerifyingKey::write`], in the raw bytes format.
+    fn write_vk(vk: &VerifyingKey<Self::Curve>) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        vk.write(&mut bytes, SerdeFormat::RawBytes)?;
+        Ok(bytes)
+    }
+}
+
+/// IPA commitments on the Pasta curve `C`, for circuits over its scalar field.
+/// The params need no trusted setup.
+#[derive(Debug)]
+pub struct Ipa<C>(PhantomData<C>);
+
+impl<C: CurveAffine + SerdeObject> Backend for Ipa<C>
+where
+    C::Scalar: FromUniformBytes<64> + WithSmallOrderMulGroup<3> + SerdeObject + Ord,
+{
+    type Curve = C;
+    type Scalar = C::Scalar;
+    type Params = ParamsIPA<C>;
+    const TRUSTED_SETUP: bool = false;
+
+    fn setup(k: u32) -> Self::Params {
+        ParamsIPA::new(k)
+    }
+
+    fn read_params(mut bytes: &[u8]) -> io::Result<Self::Params> {
+        ParamsIPA::read(&mut bytes)
+    }
+
+    fn write_params(params: &Self::Params) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        params.write(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn params_k(params: &Self::Params) -> u32 {
+        params.k()
+    }
+
+    fn downsize_params(params: &mut Self::Params, k: u32) {
+        params.downsize(k)
+    }
+
+    fn keygen_vk(
+        params: &Self::Params,
+        circuit: &MyCircuit<C::Scalar>,
+    ) -> Result<VerifyingKey<C>, Error> {
+        plonk::keygen_vk(params, circuit)
+    }
+
+    fn keygen_pk(
+        params: &Self::Params,
+        vk: VerifyingKey<C>,
+        circuit: &MyCircuit<C::Scalar>,
+    ) -> Result<ProvingKey<C>, Error> {
+        plonk::keygen_pk(params, vk, circuit)
+    }
+
+    fn create_proof(
+        params: &Self::Params,
+        pk: &ProvingKey<C>,
+        circuit: &MyCircuit<C::Scalar>,
+        instances: &[&[C::Scalar]],
+    ) -> Result<Vec<u8>, Error> {
+        let mut transcript = Blake2bWrite::<_, C, Challenge255<_>>::init(vec![]);
+        plonk::create_proof::<IPACommitmentScheme<C>, ProverIPA<C>, _, _, _, _>(
+            params,
+            pk,
+            std::slice::from_ref(circuit),
+            &[instances],
+            OsRng,
+            &mut transcript,
+        )?;
+        Ok(transcript.finalize())
+    }
+
+    fn verify_proof(
+        params: &Self::Params,
+        vk: &VerifyingKey<C>,
+        instances: &[&[C::Scalar]],
+        proof: &[u8],
+    ) -> Result<(), Error> {
+        let mut transcript = Blake2bRead::<_, C, Challenge255<_>>::init(proof);
+        plonk::verify_proof::<IPACommitmentScheme<C>, VerifierIPA<C>, _, _, _>(
+            params,
+            vk,
+            IpaStrategy::new(params),
+            &[instances],
+            &mut transcript,
+        )
+    }
+}
+
+/// KZG commitments on BN254 opened with SHPLONK, for circuits over its scalar
+/// field.
+///
+/// The params need a trusted setup. Unless params from a ceremony are given to
+/// [`prove`], the setup is run on this machine, whoever keeps its secret can
+/// forge proofs, so such params only serve to try circuits out.
+#[derive(Debug)]
+pub struct Kzg;
+
+impl Backend for Kzg {
+    type Curve = G1Affine;
+    type Scalar = Fr;
+    type Params = ParamsKZG<Bn256>;
+    const TRUSTED_SETUP: bool = true;
+
+    fn setup(k: u32) -> Self::Params {
+        ParamsKZG::setup(k, OsRng)
+    }
+
+    fn read_params(mut bytes: &[u8]) -> io::Result<Self::Params> {
+        ParamsKZG::read(&mut bytes)
+    }
+
+    fn write_params(params: &Self::Params) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        params.write(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn params_k(params: &Self::Params) -> u32 {
+        params.k()
+    }
+
+    fn downsize_params(params: &mut Self::Params, k: u32) {
+        params.downsize(k)
+    }
+
+    fn keygen_vk(
+        params: &Self::Params,
+        circuit: &MyCircuit<Fr>,
+    ) -> Result<VerifyingKey<G1Affine>, Error> {
+        plonk::keygen_vk(params, circuit)
+    }
+
+    fn keygen_pk(
+        params: &Self::Params,
+        vk: VerifyingKey<G1Affine>,
+        circuit: &MyCircuit<Fr>,
+    ) -> Result<ProvingKey<G1Affine>, Error> {
+        plonk::keygen_pk(params, vk, circuit)
+    }
+
+    fn create_proof(
+        params: &Self::Params,
+        pk: &ProvingKey<G1Affine>,
+        circuit: &MyCircuit<Fr>,
+        instances: &[&[Fr]],
+    ) -> Result<Vec<u8>, Error> {
+        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+        plonk::create_proof::<KZGCommitmentScheme<Bn256>, ProverSHPLONK<Bn256>, _, _, _, _>(
+            params,
+            pk,
+            std::slice::from_ref(circuit),
+            &[instances],
+            OsRng,
+            &mut transcript,
+        )?;
+        Ok(transcript.finalize())
+    }
+
+    fn verify_proof(
+        params: &Self::Params,
+        vk: &VerifyingKey<G1Affine>,
+        instances: &[&[Fr]],
+        proof: &[u8],
+    ) -> Result<(), Error> {
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(proof);
+        plonk::verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<Bn256>, _, _, _>(
+            params,
+            vk,
+            KzgStrategy::new(params),
+            &[instances],
+            &mut transcript,
+        )
+    }
+}
+
+/// A proof of a PlonkScript circuit, produced with the [`Backend`] of its field.
+#[derive(Debug)]
+pub struct ProveResult {
+    pub k: u32,
+    /// params of the commitment scheme, as written by `Params::write`
+    pub params: Vec<u8>,
+    pub proof: Vec<u8>,
+    /// verifying key, as written by [`Backend::write_vk`]
+    pub verifying_key: Vec<u8>,
+    /// the params come from a trusted setup run by [`prove`] itself, the proof
+    /// is only fit for testing
+    pub local_setup: bool,
+    /// name and value of every public signal, in instance column order
+    pub public_inputs: Vec<(String, String)>,
+    /// layout of the proven circuit, see [`SimplifiedConstraitSystem::without_witnesses`]
+    pub circuit: SimplifiedConstraitSystem,
+    pub timings: ProveTimings,
+}
+
+impl ProveResult {
+    pub fn verifying_key_digest(&self) -> String {
+        digest(&self.verifying_key)
+    }
+}
+
+/// Wall-clock time of every step of [`prove`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProveTimings {
+    pub setup: Duration,
+    pub keygen_vk: Duration,
+    pub keygen_pk: Duration,
+    pub prove: Duration,
+    pub verify: Duration,
+}
+
+impl ProveTimings {
+    pub fn total(&self) -> Duration {
+        self.setup + self.keygen_vk + self.keygen_pk + self.prove + self.verify
+    }
+}
+
+/// Generates keys for `circuit`, proves it with the values of its `instance`
+/// columns and verifies the proof.
+///
+/// Commitments are made with the scheme of `B`, the circuit is defined over
+/// its scalar field. `params` made for a larger `k` are downsized, without
+/// them a setup is run on this machine.
+pub fn prove<B: Backend>(
+    circuit: &MyCircuit<B::Scalar>,
+    k: u32,
+    instance: &[Vec<B::Scalar>],
+    params: Option<B::Params>,
+) -> Result<ProveResult, Error> {
+    let mut timings = ProveTimings::default();
+    let instances = instance.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    let instances = instances.as_slice();
+
+    let local_setup = B::TRUSTED_SETUP && params.is_none();
+    let params = timed(&mut timings.setup, || match params {
+        Some(mut params) if B::params_k(&params) >= k => {
+            B::downsize_params(&mut params, k);
+            Ok(params)
+        }
+        Some(_) => Err(Error::NotEnoughRowsAvailable { current_k: k }),
+        None => Ok(B::setup(k)),
+    })?;
+    let pk = circuit.scope(|circuit| {
+        let vk = timed(&mut timings.keygen_vk, || B::keygen_vk(&params, circuit))?;
+        timed(&mut timings.keygen_pk, || {
+            B::keygen_pk(&params, vk, circuit)
+        })
+    })?;
+
+    let proof = timed(&mut timings.prove, || {
+        circuit.scope(|circuit| B::create_proof(&params, &pk, circuit, instances))
+    })?;
+
+    timed(&mut timings.verify, || {
+        verify::<B>(&params, pk.get_vk(), instance, &proof)
+    })?;
+
+    let public_inputs = circuit
+        .scs
+        .instance_columns()
+        .into_iter()
+        .flat_map(|(_, signals)| signals)
+        .map(|signal| signal.name.clone())
+        .zip(instance.iter().flatten().map(field_to_string))
+        .collect();
+
+    Ok(ProveResult {
+        k,
+        verifying_key: B::write_vk(pk.get_vk())?,
+        local_setup,
+        params: B::write_params(&params)?,
+        proof,
+        public_inputs,
+        circuit: circuit.scs.without_witnesses(),
+        timings,
+    })
+}
+
+/// Reads back a verifying key written by [`Backend::write_vk`]. Reading it
+/// configures `circuit`, which only needs its layout.
+pub fn read_vk<B: Backend>(
+    mut bytes: &[u8],
+    circuit: &MyCircuit<B::Scalar>,
+) -> io::Result<VerifyingKey<B::Curve>> {
+    circuit
+        .scope(|_| VerifyingKey::read::<_, MyCircuit<B::Scalar>>(&mut bytes, SerdeFormat::RawBytes))
+}
+
+/// Checks `proof` against the verifying key and the values of the instance columns.
+pub fn verify<B: Backend>(
+    params: &B::Params,
+    vk: &VerifyingKey<B::Curve>,
+    instance: &[Vec<B::Scalar>],
+    proof: &[u8],
+) -> Result<(), Error> {
+    let instances = instance.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    B::verify_proof(params, vk, &instances, proof)
+}
+
+/// Hex digest identifying a verifying key by its serialized bytes.
+pub fn digest(verifying_key: &[u8]) -> String {
+    hex::encode(blake2b_simd::blake2b(verifying_key).as_bytes())
+}
+
+fn timed<T>(elapsed: &mut Duration, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    let start = Instant::now();
+    let result = f();
+    *elapsed = start.elapsed();
+    result
+}
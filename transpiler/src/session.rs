@@ -0,0 +1,68 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use rhai::{Engine, EvalAltResult};
+
+use crate::{
+    engine::{PlonkScriptEngine, FIELD_PARAMETER},
+    error::PlonkScriptError,
+    system::SimplifiedConstraitSystem,
+};
+
+/// Constraint system shared between a session and the functions it registers on the
+/// rhai engine.
+pub type Context = Rc<RefCell<SimplifiedConstraitSystem>>;
+
+/// One compilation of a PlonkScript program.
+///
+/// Every session owns its own engine and constraint system, so several sessions can
+/// live in the same process (or on different threads) without interfering.
+pub struct Session {
+    engine: Engine,
+    context: Context,
+}
+
+impl Session {
+    /// Fails when one of the library `modules` cannot be loaded.
+    pub fn new(modules: HashMap<String, String>) -> Result<Self, PlonkScriptError> {
+        let context = Context::default();
+        let mut engine = Engine::new();
+        engine.register_plonk_script(&context, modules)?;
+        Ok(Session { engine, context })
+    }
+
+    /// Supplies parameters and public input values, overriding the ones the script
+    /// sets itself.
+    ///
+    /// Fails when the `field` given is not one PlonkScript supports.
+    pub fn set_inputs(&self, inputs: HashMap<String, String>) -> Result<(), PlonkScriptError> {
+        let mut cs = self.context.borrow_mut();
+        if let Some(field) = inputs.get(FIELD_PARAMETER) {
+            cs.field = field.parse().map_err(|_| PlonkScriptError::InvalidInput {
+                name: FIELD_PARAMETER.to_string(),
+                value: field.clone(),
+            })?;
+        }
+        cs.inputs.extend(inputs.clone());
+        cs.overrides = inputs;
+        Ok(())
+    }
+
+    /// Runs a transpiled script, recording its constraints into this session.
+    pub fn run(&self, script: &str) -> Result<(), Box<EvalAltResult>> {
+        self.engine.run(script)
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Consumes the session and hands back the constraint system built so far.
+    pub fn into_system(self) -> SimplifiedConstraitSystem {
+        // the engine holds clones of the context, release them first
+        drop(self.engine);
+        match Rc::try_unwrap(self.context) {
+            Ok(cs) => cs.into_inner(),
+            Err(context) => context.take(),
+        }
+    }
+}
@@ -1,10 +1,18 @@
-use once_cell::sync::Lazy;
-use std::{collections::HashMap, fmt};
+use std::{cmp, collections::HashMap, fmt};
+
+use rhai::Position;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{DEFAULT_INSTANCE_COLUMN_NAME, MAX_DEGREE_PARAMETER};
+use cell_expression::Degree;
 
 pub mod cell_expression;
+pub mod field;
+pub mod stats;
 pub use cell_expression::ToCellExpression;
+pub use field::FieldKind;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum ColumnType {
     Selector,
@@ -15,7 +23,7 @@ pub enum ColumnType {
     TableLookup,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum SpecialType {
     Input,
@@ -24,7 +32,7 @@ pub enum SpecialType {
     None,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct Column {
     pub name: String,
@@ -43,7 +51,19 @@ impl fmt::Debug for Column {
     }
 }
 
-#[derive(Debug, Clone)]
+impl Column {
+    /// Complex selector enabling the rows of `self`, an advice column read by
+    /// lookups as a table, its other rows are left out of the table.
+    pub fn table_selector(&self) -> Column {
+        Column {
+            name: format!("{}_selector", self.name),
+            ctype: ColumnType::ComplexSelector,
+            stype: SpecialType::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct Cell {
     pub column: Column,
@@ -52,7 +72,7 @@ pub struct Cell {
     pub index: i64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum CellExpression {
     Calculated(String),
@@ -64,45 +84,391 @@ pub enum CellExpression {
     Scaled(Box<CellExpression>, String),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct SimplifiedConstraitSystem {
+    #[serde(default)]
+    pub field: FieldKind,
     pub signals: Vec<Cell>,
     pub columns: Vec<Column>,
     pub regions: Vec<InstructionBundle>,
     pub tables: Vec<InstructionBundle>,
     pub instance_count: i64,
-    pub gates: Vec<(String, String, Column, CellExpression)>,// name, expression string(key), column, expression
-    pub inputs: Lazy<HashMap<String, String>>,
-    pub cells: Lazy<HashMap<String, Cell>>,
+    pub gates: Vec<(String, String, Column, CellExpression)>,// name, expression string(key), column, expression; a declared gate has one entry per constraint
+    pub inputs: HashMap<String, String>,
+    pub overrides: HashMap<String, String>, // inputs given from outside the script, they win over its `#` parameters
+    pub cells: HashMap<String, Cell>,
     pub lookups: Vec<LookupParameter>,
+    #[serde(default)]
+    pub equality_columns: Vec<String>, // columns annotated with `@equality`
+    #[serde(skip)]
+    pub fixed_tables: HashMap<String, String>, // table column name, the fixed column it is a copy of
+    #[serde(skip)]
+    pub advice_tables: HashMap<String, String>, // table column name, the advice column it is a copy of
+    #[serde(skip)]
+    pub inverses: HashMap<String, Cell>, // witnessed inverse cell name, the cell it inverts
+    #[serde(skip)]
+    pub gate_positions: HashMap<String, Position>, // gate name, or `<gate>.<constraint>` once merged, where it was created
+    #[serde(skip)]
+    pub position: Position, // script position of the call being executed
 }
 
-#[derive(Debug, Clone, Default)]
+impl SimplifiedConstraitSystem {
+    /// The layout of the circuit alone: columns, gates, lookups, fixed values and
+    /// regions, with every advice and public value cleared.
+    ///
+    /// It is all that is needed to generate the circuit's keys.
+    pub fn without_witnesses(&self) -> Self {
+        let clear = |cell: &Cell| Cell {
+            value: None,
+            ..cell.clone()
+        };
+        let regions = self
+            .regions
+            .iter()
+            .map(|region| InstructionBundle {
+                instructions: region
+                    .instructions
+                    .iter()
+                    .map(|instruction| match instruction {
+                        Instruction::AssignAdvice(a, _) => Instruction::AssignAdvice(
+                            clear(a),
+                            CellExpression::Constant("0".to_string()),
+                        ),
+                        Instruction::AssignAdviceFromInstance(a, b) => {
+                            Instruction::AssignAdviceFromInstance(clear(a), clear(b))
+                        }
+                        Instruction::ConstrainEqual(a, b) => {
+                            Instruction::ConstrainEqual(clear(a), clear(b))
+                        }
+                        // constants are fixed, they stay part of the circuit
+                        Instruction::AssignAdviceFromConstant(a, c) => {
+                            Instruction::AssignAdviceFromConstant(clear(a), c.clone())
+                        }
+                        Instruction::ConstrainConstant(a, c) => {
+                            Instruction::ConstrainConstant(clear(a), c.clone())
+                        }
+                        other => other.clone(),
+                    })
+                    .collect(),
+                ..region.clone()
+            })
+            .collect();
+
+        SimplifiedConstraitSystem {
+            field: self.field,
+            signals: self.signals.iter().map(clear).collect(),
+            columns: self.columns.clone(),
+            regions,
+            tables: self.tables.clone(),
+            instance_count: self.instance_count,
+            equality_columns: self.equality_columns.clone(),
+            gates: self
+                .gates
+                .iter()
+                .map(|(name, key, selector, exp)| {
+                    (name.clone(), key.clone(), selector.clone(), exp.without_values())
+                })
+                .collect(),
+            lookups: self
+                .lookups
+                .iter()
+                .map(|lookup| LookupParameter {
+                    map: lookup
+                        .map
+                        .iter()
+                        .map(|(exp, col)| (exp.without_values(), col.clone()))
+                        .collect(),
+                    ..lookup.clone()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl SimplifiedConstraitSystem {
+    /// Puts the gates created by `<==` whose selectors are enabled on exactly
+    /// the same rows under one selector, as constraints of a single gate, and
+    /// drops the selectors that are no longer used.
+    ///
+    /// Declared gates keep their own selector. The position of every merged
+    /// constraint is kept in [`Self::gate_positions`] as `<gate>.<constraint>`.
+    pub fn merge_gates(&mut self) {
+        // rows each selector is enabled on, as (region id, row)
+        let mut enabled = HashMap::<String, Vec<(i64, i64)>>::new();
+        for region in &self.regions {
+            for instruction in &region.instructions {
+                if let Instruction::EnableSelector(c) = instruction {
+                    enabled
+                        .entry(c.column.name.clone())
+                        .or_default()
+                        .push((region.id, c.index));
+                }
+            }
+        }
+        for rows in enabled.values_mut() {
+            rows.sort();
+            rows.dedup();
+        }
+
+        // gate a merged gate goes into, by name
+        let mut merged_into = HashMap::<String, String>::new();
+        let mut order = Vec::<String>::new();
+        for (name, key, _, _) in &self.gates {
+            if is_declared_gate(name, key) || order.contains(name) {
+                continue;
+            }
+            let rows = enabled.get(name);
+            let target = rows.and_then(|rows| {
+                order.iter().find(|other| {
+                    merged_into.get(*other).is_none() && enabled.get(*other) == Some(rows)
+                })
+            });
+            if let Some(target) = target {
+                merged_into.insert(name.clone(), target.clone());
+            }
+            order.push(name.clone());
+        }
+        if merged_into.is_empty() {
+            return;
+        }
+
+        let mut gates = Vec::<(String, String, Column, CellExpression)>::with_capacity(self.gates.len());
+        for (name, _, selector, exp) in &self.gates {
+            if merged_into.contains_key(name) || gates.iter().any(|g| &g.0 == name) {
+                continue;
+            }
+            let CellExpression::Product(selector_cell, _) = exp else {
+                unreachable!("gates are the product of their selector and a polynomial")
+            };
+            let merged = merged_into.values().any(|target| target == name);
+            // the gate and the ones merged into it, in the order they were created
+            let members = self
+                .gates
+                .iter()
+                .filter(|(other, ..)| other == name || merged_into.get(other) == Some(name));
+            for (i, (member, key, _, member_exp)) in members.enumerate() {
+                if merged {
+                    if let Some(position) = self.gate_positions.get(member).copied() {
+                        self.gate_positions.insert(format!("{}.{}", name, i), position);
+                    }
+                }
+                let poly = match member_exp {
+                    CellExpression::Product(_, poly) => poly.clone(),
+                    other => Box::new(other.clone()),
+                };
+                gates.push((
+                    name.clone(),
+                    key.clone(),
+                    selector.clone(),
+                    CellExpression::Product(selector_cell.clone(), poly),
+                ));
+            }
+        }
+        self.gates = gates;
+
+        self.columns.retain(|c| !merged_into.contains_key(&c.name));
+        for name in merged_into.keys() {
+            self.gate_positions.remove(name);
+        }
+        for region in &mut self.regions {
+            region.instructions.retain(|instruction| match instruction {
+                Instruction::EnableSelector(c) => !merged_into.contains_key(&c.column.name),
+                _ => true,
+            });
+        }
+    }
+
+    /// Gates as halo2 creates them: name and polynomials, consecutive entries
+    /// of [`Self::gates`] with the same name make up one gate.
+    pub fn gate_groups(&self) -> Vec<(&str, Vec<&CellExpression>)> {
+        let mut groups = Vec::<(&str, Vec<&CellExpression>)>::new();
+        for (name, _, _, exp) in &self.gates {
+            match groups.last_mut() {
+                Some((last, polys)) if last == name => polys.push(exp),
+                _ => groups.push((name, vec![exp])),
+            }
+        }
+        groups
+    }
+
+    /// Degree of every gate, the largest of its polynomials, selector included.
+    pub fn gate_degrees(&self) -> Vec<(&str, usize)> {
+        self.gate_groups()
+            .into_iter()
+            .map(|(name, polys)| (name, polys.iter().map(|p| p.degree()).max().unwrap_or(0)))
+            .collect()
+    }
+
+    /// Degree of the circuit as halo2 computes it, the largest of the gates,
+    /// of the lookups, `max(4, 2 + input + table)`, and of the permutation, 3.
+    pub fn degree(&self) -> usize {
+        let lookups = self.lookups.iter().map(|lookup| {
+            let input = lookup.map.iter().map(|(exp, _)| exp.degree()).max().unwrap_or(0);
+            // table columns are of degree 1, advice tables are gated by their selector
+            let table = if lookup.reads_advice() { 2 } else { 1 };
+            cmp::max(4, 2 + cmp::max(input, 1) + table)
+        });
+        self.gate_degrees()
+            .into_iter()
+            .map(|(_, degree)| degree)
+            .chain(lookups)
+            .fold(3, cmp::max)
+    }
+
+    /// How many times the `2^k` rows the extended domain, where the quotient
+    /// polynomial is computed, is larger: `degree - 1` rounded up to a power of two.
+    pub fn extended_domain_factor(&self) -> usize {
+        (self.degree() - 1).next_power_of_two()
+    }
+
+    /// Degree bound set with `# max_degree`, gates made by `<==` above it are
+    /// split into intermediate cells.
+    pub fn max_degree(&self) -> Option<usize> {
+        self.inputs.get(MAX_DEGREE_PARAMETER)?.parse().ok()
+    }
+
+    /// Columns some cell of which is copied from or to another cell or a
+    /// constant, and the columns annotated with `@equality`; only they get
+    /// equality enabled, every other column stays out of the permutation.
+    pub fn columns_in_equality(&self) -> Vec<String> {
+        let mut names = self.equality_columns.clone();
+        for instruction in self.regions.iter().flat_map(|r| &r.instructions) {
+            let cells = match instruction {
+                Instruction::ConstrainEqual(a, b) | Instruction::AssignAdviceFromInstance(a, b) => {
+                    vec![a, b]
+                }
+                Instruction::ConstrainConstant(a, _) | Instruction::AssignAdviceFromConstant(a, _) => {
+                    vec![a]
+                }
+                _ => continue,
+            };
+            for cell in cells {
+                if !names.contains(&cell.column.name) {
+                    names.push(cell.column.name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// Whether some cell is bound to a constant, the circuit then needs a
+    /// fixed column to hold them.
+    pub fn uses_constants(&self) -> bool {
+        self.regions.iter().flat_map(|r| &r.instructions).any(|instruction| {
+            matches!(
+                instruction,
+                Instruction::AssignAdviceFromConstant(_, _) | Instruction::ConstrainConstant(_, _)
+            )
+        })
+    }
+
+    /// Instance columns in the order the circuit creates them, each with the
+    /// signals of its rows. The default column only exists while a signal is
+    /// placed in it.
+    pub fn instance_columns(&self) -> Vec<(String, Vec<&Cell>)> {
+        let mut names = Vec::<String>::new();
+        if self
+            .signals
+            .iter()
+            .any(|x| x.column.name == DEFAULT_INSTANCE_COLUMN_NAME)
+        {
+            names.push(DEFAULT_INSTANCE_COLUMN_NAME.to_string());
+        }
+        names.extend(
+            self.columns
+                .iter()
+                .filter(|col| col.ctype == ColumnType::Instance)
+                .map(|col| col.name.clone()),
+        );
+        names
+            .into_iter()
+            .map(|name| {
+                let mut signals = self
+                    .signals
+                    .iter()
+                    .filter(|x| x.column.name == name)
+                    .collect::<Vec<_>>();
+                signals.sort_by_key(|x| x.index);
+                (name, signals)
+            })
+            .collect()
+    }
+}
+
+/// Key of a constraint of the gate `name` declared in the script, it matches no
+/// expression so `<==` never reuses such a gate.
+pub fn declared_gate_key(name: &str, exp: &CellExpression) -> String {
+    format!("{}: {}", name, cell_expression::ToString::to_string(exp))
+}
+
+fn is_declared_gate(name: &str, key: &str) -> bool {
+    key.starts_with(&format!("{}: ", name))
+}
+
+impl CellExpression {
+    // the same expression, with the values the script computed for its cells dropped
+    pub(crate) fn without_values(&self) -> Self {
+        match self {
+            CellExpression::CellValue(c) => CellExpression::CellValue(Cell {
+                value: None,
+                ..c.clone()
+            }),
+            CellExpression::Negated(a) => CellExpression::Negated(Box::new(a.without_values())),
+            CellExpression::Product(a, b) => CellExpression::Product(
+                Box::new(a.without_values()),
+                Box::new(b.without_values()),
+            ),
+            CellExpression::Sum(a, b) => {
+                CellExpression::Sum(Box::new(a.without_values()), Box::new(b.without_values()))
+            }
+            CellExpression::Scaled(a, b) => {
+                CellExpression::Scaled(Box::new(a.without_values()), b.clone())
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct LookupParameter {
     pub name: String,
     pub map: Vec<(CellExpression, Column)>,// expression, lookup column
+    #[serde(skip)]
+    pub position: Position,
+}
+
+impl LookupParameter {
+    /// Whether the tables are advice columns, gated by their
+    /// [`Column::table_selector`], rather than table columns; a lookup does
+    /// not mix both.
+    pub fn reads_advice(&self) -> bool {
+        self.map.iter().any(|(_, col)| col.ctype == ColumnType::Advice)
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct InstructionBundle {
     pub name: String,
     pub id: i64,
     pub instructions: Vec<Instruction>,
+    #[serde(skip)]
+    pub position: Position,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum Instruction {
     // CopyAdvice(),
     AssignFixed(Cell, CellExpression), // fixed, fix_row(offset), value expression
     EnableSelector(Cell),              // selector, row(offset)
     AssignAdvice(Cell, CellExpression), // advice, adv_row(offset), value expression
-    AssignAdviceFromConstant(Cell, i64), // advice, adv_row(offset), constant
+    AssignAdviceFromConstant(Cell, String), // advice, adv_row(offset), constant
     AssignAdviceFromInstance(Cell, Cell), // advice, adv_row(offset), instance, ins_row
     ConstrainEqual(Cell, Cell),        // advice, adv_row(offset), advice, adv_row(offset)
-    ConstrainConstant(),               //
+    ConstrainConstant(Cell, String),   // assigned cell, constant
     AssignCell(Column, String), // table, column (instead of cell because always add to latest), constant value
 }
\ No newline at end of file
@@ -1,39 +1,43 @@
 use std::cmp;
 
-use super::{Cell, CellExpression, Column};
-use halo2_proofs::pasta::{group::ff::PrimeField, Fp};
+use super::{Cell, CellExpression, Column, SimplifiedConstraitSystem};
+use halo2_proofs::halo2curves::{
+    bn256::Fr,
+    ff::PrimeField,
+    pasta::{Fp, Fq},
+};
 
 pub trait ToCellExpression {
-    fn to_cell_expression(self) -> CellExpression;
+    fn to_cell_expression(self, cs: &SimplifiedConstraitSystem) -> CellExpression;
 }
 
 impl ToCellExpression for Cell {
-    fn to_cell_expression(self) -> CellExpression {
+    fn to_cell_expression(self, _cs: &SimplifiedConstraitSystem) -> CellExpression {
         CellExpression::CellValue(self)
     }
 }
 
 impl ToCellExpression for String {
-    fn to_cell_expression(self) -> CellExpression {
+    fn to_cell_expression(self, _cs: &SimplifiedConstraitSystem) -> CellExpression {
         CellExpression::Constant(self)
     }
 }
 
 impl ToCellExpression for i64 {
-    fn to_cell_expression(self) -> CellExpression {
+    fn to_cell_expression(self, _cs: &SimplifiedConstraitSystem) -> CellExpression {
         CellExpression::Constant(self.to_string())
     }
 }
 
 impl ToCellExpression for CellExpression {
-    fn to_cell_expression(self) -> CellExpression {
+    fn to_cell_expression(self, _cs: &SimplifiedConstraitSystem) -> CellExpression {
         self
     }
 }
 
 impl ToCellExpression for Column {
-    fn to_cell_expression(self) -> CellExpression {
-        CellExpression::CellValue(self.clone().get_field(0))
+    fn to_cell_expression(self, cs: &SimplifiedConstraitSystem) -> CellExpression {
+        CellExpression::CellValue(self.get_field(cs, 0))
     }
 }
 
@@ -43,40 +47,57 @@ pub trait ToField {
 }
 
 impl ToField for String {
-    fn to_field<F: PrimeField>(&self) -> Option<F>
-    where
-        F: PrimeField,
-    {
-        match self {
-            s if s.starts_with("0x") => {
-                let mut bytes = F::Repr::default();
-                let mut view = bytes.as_mut();
-                hex::decode_to_slice(&s[2..], &mut view).ok()?;
-                view.reverse();
-                // F::from_repr_vartime(bytes).ok_or("Decoding failed".to_string())
-                F::from_repr_vartime(bytes)
-                // match F::from_repr_vartime(bytes) {
-                //     None => panic!("Decoding failed: {}", s),
-                //     Some(x) => x,
-                // }
-            }
-            // s => Ok(F::from(s.parse::<u64>().map_err(|e| e.to_string())?)),
-            s => Some(F::from(s.parse::<u64>().ok()?)),
+    /// Decimal, or hexadecimal with a `0x` prefix, of any size and optionally
+    /// negative; the number is reduced modulo the field's prime.
+    fn to_field<F: PrimeField>(&self) -> Option<F> {
+        let s = self.trim();
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let (radix, digits) = match s.strip_prefix("0x") {
+            Some(hex) => (16, hex),
+            None => (10, s),
+        };
+        if digits.is_empty() {
+            return None;
         }
+        let base = F::from(radix as u64);
+        let value = digits.chars().try_fold(F::ZERO, |acc, c| {
+            Some(acc * base + F::from(c.to_digit(radix)? as u64))
+        })?;
+        Some(if negative { -value } else { value })
     }
 }
 
+// `0x` followed by the big endian bytes of the element
+pub fn field_to_string<F: PrimeField>(f: &F) -> String {
+    "0x".to_string()
+        + &*f
+            .to_repr()
+            .as_ref()
+            .iter()
+            .rev()
+            .map(|x| format!("{:02x}", x))
+            .collect::<Vec<String>>()
+            .join("")
+}
+
 impl ToString for Fp {
     fn to_string(&self) -> String {
-        "0x".to_string()
-            + &*self
-                .to_repr()
-                .as_ref()
-                .iter()
-                .rev()
-                .map(|x| format!("{:02x}", x))
-                .collect::<Vec<String>>()
-                .join("")
+        field_to_string(self)
+    }
+}
+
+impl ToString for Fq {
+    fn to_string(&self) -> String {
+        field_to_string(self)
+    }
+}
+
+impl ToString for Fr {
+    fn to_string(&self) -> String {
+        field_to_string(self)
     }
 }
 
@@ -93,67 +114,55 @@ pub trait ToString {
     fn to_string(&self) -> String;
 }
 pub trait ToValueString {
-    fn to_value_string(&self) -> Option<String>;
+    /// Value computed in the field `F`, or the value as given when nothing has
+    /// to be computed.
+    fn to_value_string<F: PrimeField>(&self) -> Option<String>;
 }
 impl ToField for CellExpression {
     fn to_field<F: PrimeField>(&self) -> Option<F> {
-        self.to_value_string().and_then(|x| x.to_field::<F>())
+        match self {
+            CellExpression::Calculated(c) | CellExpression::Constant(c) => c.to_field(),
+            CellExpression::CellValue(c) => c.value.as_ref()?.to_field(),
+            CellExpression::Negated(n) => n.to_field::<F>().map(|x| -x),
+            CellExpression::Product(a, b) => Some(a.to_field::<F>()? * b.to_field::<F>()?),
+            CellExpression::Sum(a, b) => Some(a.to_field::<F>()? + b.to_field::<F>()?),
+            CellExpression::Scaled(a, b) => Some(a.to_field::<F>()? * b.to_field::<F>()?),
+        }
     }
 }
 
 impl ToValueString for CellExpression {
-    fn to_value_string(&self) -> Option<String> {
+    fn to_value_string<F: PrimeField>(&self) -> Option<String> {
         match self {
             CellExpression::Calculated(c) => Some(c.clone()),
             CellExpression::Constant(c) => Some(c.clone()),
-            CellExpression::CellValue(c) => match c.column.ctype {
-                crate::system::ColumnType::Selector => todo!(),
-                crate::system::ColumnType::Advice => c.value.clone(),
-                crate::system::ColumnType::Fixed => c.value.clone(),
-                crate::system::ColumnType::Instance => todo!(),
-                crate::system::ColumnType::ComplexSelector => todo!(),
-                crate::system::ColumnType::TableLookup => todo!(),
-            },
-            CellExpression::Negated(n) => {
-                // get_known_value::<Fp>(convert_to_value(*n)?).map(|x| fp_to_string(&(-x)))
-                n.to_field::<Fp>().map(|x| (-x).to_string())
-            }
-            CellExpression::Product(a, b) => match (a.to_field::<Fp>(), b.to_field::<Fp>()) {
-                (None, _) | (_, None) => None,
-                (Some(a), Some(b)) => Some((a * b).to_string()),
-            },
-            CellExpression::Sum(a, b) => match (a.to_field::<Fp>(), b.to_field::<Fp>()) {
-                (None, _) | (_, None) => None,
-                (Some(a), Some(b)) => Some((a + b).to_string()),
-            },
-            CellExpression::Scaled(a, b) => match (a.to_field::<Fp>(), b.to_field::<Fp>()) {
-                (None, _) | (_, None) => None,
-                (Some(a), Some(b)) => Some((a * b).to_string()),
-            },
+            CellExpression::CellValue(c) => c.value.clone(),
+            _ => self.to_field::<F>().map(|x| field_to_string(&x)),
         }
     }
 }
 
 impl ToValueString for Cell {
-    fn to_value_string(&self) -> Option<String> {
+    fn to_value_string<F: PrimeField>(&self) -> Option<String> {
         self.value.clone()
     }
 }
 
 impl ToValueString for Column {
-    fn to_value_string(&self) -> Option<String> {
-        panic!("Column cannot be converted to value string")
+    fn to_value_string<F: PrimeField>(&self) -> Option<String> {
+        // a column has no value of its own, only its cells do
+        None
     }
 }
 
 impl ToValueString for String {
-    fn to_value_string(&self) -> Option<String> {
+    fn to_value_string<F: PrimeField>(&self) -> Option<String> {
         Some(self.clone())
     }
 }
 
 impl ToValueString for i64 {
-    fn to_value_string(&self) -> Option<String> {
+    fn to_value_string<F: PrimeField>(&self) -> Option<String> {
         Some(self.to_string())
     }
 }
@@ -163,14 +172,11 @@ impl ToString for CellExpression {
         match self {
             CellExpression::Calculated(c) => c.clone(),
             CellExpression::Constant(c) => c.clone(),
-            CellExpression::CellValue(c) => match c.column.ctype {
-                crate::system::ColumnType::Selector => todo!(),
-                crate::system::ColumnType::Advice => c.column.name.clone(),
-                crate::system::ColumnType::Fixed => c.column.name.clone(),
-                crate::system::ColumnType::Instance => todo!(),
-                crate::system::ColumnType::ComplexSelector => todo!(),
-                crate::system::ColumnType::TableLookup => todo!(),
-            },
+            // rotations of gates tell cells of one column apart
+            CellExpression::CellValue(c) if c.index != 0 => {
+                format!("{}@{}", c.column.name, c.index)
+            }
+            CellExpression::CellValue(c) => c.column.name.clone(),
             CellExpression::Negated(n) => {
                 format!("-({})", n.to_string())
             }
@@ -227,14 +233,7 @@ impl GetBaseIndex for CellExpression {
         match self {
             CellExpression::Calculated(_) => i64::MAX,
             CellExpression::Constant(_) => i64::MAX,
-            CellExpression::CellValue(c) => match c.column.ctype {
-                crate::system::ColumnType::Selector => todo!(),
-                crate::system::ColumnType::Advice => c.index,
-                crate::system::ColumnType::Fixed => c.index,
-                crate::system::ColumnType::Instance => todo!(),
-                crate::system::ColumnType::ComplexSelector => todo!(),
-                crate::system::ColumnType::TableLookup => todo!(),
-            },
+            CellExpression::CellValue(c) => c.index,
             CellExpression::Negated(n) => n.get_base_index(),
             CellExpression::Product(a, b) => cmp::min(a.get_base_index(), b.get_base_index()),
             CellExpression::Sum(a, b) => cmp::min(a.get_base_index(), b.get_base_index()),
@@ -243,6 +242,249 @@ impl GetBaseIndex for CellExpression {
     }
 }
 
+pub trait Degree {
+    /// Degree of the polynomial, the most cells multiplied in one of its terms.
+    fn degree(&self) -> usize;
+}
+
+impl Degree for CellExpression {
+    fn degree(&self) -> usize {
+        match self {
+            CellExpression::Calculated(_) => 0,
+            CellExpression::Constant(_) => 0,
+            CellExpression::CellValue(_) => 1,
+            CellExpression::Negated(n) => n.degree(),
+            CellExpression::Product(a, b) => a.degree() + b.degree(),
+            CellExpression::Sum(a, b) => cmp::max(a.degree(), b.degree()),
+            CellExpression::Scaled(a, _) => a.degree(),
+        }
+    }
+}
+
+pub trait Canonicalize {
+    /// The same polynomial written one way only, constants folded, sums and
+    /// products flattened with their terms sorted and their coefficients
+    /// merged, so that equal constraints have equal keys.
+    fn canonicalize<F: PrimeField>(&self) -> CellExpression;
+}
+
+impl Canonicalize for CellExpression {
+    fn canonicalize<F: PrimeField>(&self) -> CellExpression {
+        let mut terms = Vec::<(CellExpression, F)>::new();
+        let mut constant = F::ZERO;
+        collect_terms(self, F::ONE, &mut terms, &mut constant);
+
+        // like terms are merged, cancelled ones dropped
+        let mut merged = Vec::<(String, CellExpression, F)>::new();
+        for (term, coefficient) in terms {
+            let key = term.to_string();
+            match merged.iter_mut().find(|(k, _, _)| *k == key) {
+                Some((_, _, c)) => *c += coefficient,
+                None => merged.push((key, term, coefficient)),
+            }
+        }
+        merged.retain(|(_, _, c)| !bool::from(c.is_zero()));
+        merged.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut terms = merged.into_iter().map(|(_, term, c)| scale(term, c));
+        let first = match terms.next() {
+            Some(first) => first,
+            None => return CellExpression::Constant(constant_string(&constant)),
+        };
+        let sum = terms.fold(first, |sum, term| {
+            CellExpression::Sum(Box::new(sum), Box::new(term))
+        });
+        match bool::from(constant.is_zero()) {
+            true => sum,
+            false => CellExpression::Sum(
+                Box::new(sum),
+                Box::new(CellExpression::Constant(constant_string(&constant))),
+            ),
+        }
+    }
+}
+
+// adds `coefficient * exp` to the terms, a product being one term, and its
+// constant part to `constant`
+fn collect_terms<F: PrimeField>(
+    exp: &CellExpression,
+    coefficient: F,
+    terms: &mut Vec<(CellExpression, F)>,
+    constant: &mut F,
+) {
+    match exp {
+        CellExpression::Constant(c) => match c.to_field::<F>() {
+            Some(c) => *constant += coefficient * c,
+            None => terms.push((exp.clone(), coefficient)),
+        },
+        CellExpression::Negated(n) => collect_terms(n, -coefficient, terms, constant),
+        CellExpression::Scaled(a, c) => match c.to_field::<F>() {
+            Some(c) => collect_terms(a, coefficient * c, terms, constant),
+            None => terms.push((exp.clone(), coefficient)),
+        },
+        CellExpression::Sum(a, b) => {
+            collect_terms(a, coefficient, terms, constant);
+            collect_terms(b, coefficient, terms, constant);
+        }
+        CellExpression::Product(_, _) => {
+            let mut factors = vec![];
+            let c = coefficient * collect_factors::<F>(exp, &mut factors);
+            factors.sort_by_key(|f| f.to_string());
+            let mut factors = factors.into_iter();
+            match factors.next() {
+                Some(first) => terms.push((
+                    factors.fold(first, |p, f| {
+                        CellExpression::Product(Box::new(p), Box::new(f))
+                    }),
+                    c,
+                )),
+                None => *constant += c,
+            }
+        }
+        CellExpression::CellValue(_) | CellExpression::Calculated(_) => {
+            terms.push((exp.clone(), coefficient))
+        }
+    }
+}
+
+// adds the factors of the product `exp` to `factors`, returns its constant part
+fn collect_factors<F: PrimeField>(exp: &CellExpression, factors: &mut Vec<CellExpression>) -> F {
+    match exp {
+        CellExpression::Constant(c) => match c.to_field::<F>() {
+            Some(c) => c,
+            None => {
+                factors.push(exp.clone());
+                F::ONE
+            }
+        },
+        CellExpression::Negated(n) => -collect_factors::<F>(n, factors),
+        CellExpression::Scaled(a, c) => match c.to_field::<F>() {
+            Some(c) => c * collect_factors::<F>(a, factors),
+            None => {
+                factors.push(exp.clone());
+                F::ONE
+            }
+        },
+        CellExpression::Product(a, b) => {
+            collect_factors::<F>(a, factors) * collect_factors::<F>(b, factors)
+        }
+        // a sum is a factor of its own, unless it comes down to a single term
+        CellExpression::Sum(_, _) => match exp.canonicalize::<F>() {
+            sum @ CellExpression::Sum(_, _) => {
+                factors.push(sum);
+                F::ONE
+            }
+            term => collect_factors::<F>(&term, factors),
+        },
+        CellExpression::CellValue(_) | CellExpression::Calculated(_) => {
+            factors.push(exp.clone());
+            F::ONE
+        }
+    }
+}
+
+// coefficient * term, small coefficients and their negations kept readable
+fn scale<F: PrimeField>(term: CellExpression, coefficient: F) -> CellExpression {
+    if coefficient == F::ONE {
+        term
+    } else if coefficient == -F::ONE {
+        CellExpression::Negated(Box::new(term))
+    } else if is_small(&-coefficient) && !is_small(&coefficient) {
+        CellExpression::Negated(Box::new(CellExpression::Scaled(
+            Box::new(term),
+            constant_string(&-coefficient),
+        )))
+    } else {
+        CellExpression::Scaled(Box::new(term), constant_string(&coefficient))
+    }
+}
+
+// whether the element fits in a u64
+fn is_small<F: PrimeField>(f: &F) -> bool {
+    f.to_repr().as_ref()[8..].iter().all(|b| *b == 0)
+}
+
+// decimal when small, hexadecimal otherwise
+fn constant_string<F: PrimeField>(f: &F) -> String {
+    if is_small(f) {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&f.to_repr().as_ref()[..8]);
+        u64::from_le_bytes(bytes).to_string()
+    } else {
+        field_to_string(f)
+    }
+}
+
+#[test]
+fn test_canonicalize() {
+    let cell = |name: &str, index: i64| {
+        CellExpression::CellValue(Cell {
+            name: format!("{}[{}]", name, index),
+            index,
+            value: None,
+            column: Column {
+                name: name.to_string(),
+                ctype: super::ColumnType::Advice,
+                stype: super::SpecialType::None,
+            },
+        })
+    };
+    let sum = |a, b| CellExpression::Sum(Box::new(a), Box::new(b));
+    let product = |a, b| CellExpression::Product(Box::new(a), Box::new(b));
+    let negated = |a| CellExpression::Negated(Box::new(a));
+    let scaled = |a, c: &str| CellExpression::Scaled(Box::new(a), c.to_string());
+    let constant = |c: &str| CellExpression::Constant(c.to_string());
+    let key = |exp: CellExpression| exp.canonicalize::<Fp>().to_string();
+
+    assert_eq!(
+        key(sum(cell("a", 0), cell("b", 0))),
+        key(sum(cell("b", 0), cell("a", 0)))
+    );
+    assert_eq!(
+        key(scaled(cell("a", 0), "2")),
+        key(product(constant("2"), cell("a", 0)))
+    );
+    assert_eq!(
+        key(sum(cell("a", 0), cell("a", 0))),
+        key(scaled(cell("a", 0), "2"))
+    );
+    assert_eq!(key(negated(negated(cell("a", 0)))), "a");
+    assert_eq!(key(sum(constant("2"), constant("3"))), "5");
+    assert_eq!(key(sum(cell("a", 0), negated(cell("a", 0)))), "0");
+    assert_eq!(
+        key(product(product(cell("b", 0), cell("a", 1)), cell("a", 0))),
+        key(product(cell("a", 0), product(cell("a", 1), cell("b", 0))))
+    );
+    assert_eq!(key(scaled(negated(cell("a", 0)), "3")), "-((a) x (3))");
+    // rotations keep cells of one column apart
+    assert_ne!(
+        key(sum(cell("a", 0), cell("a", 1))),
+        key(sum(cell("a", 0), cell("a", 0)))
+    );
+}
+
+#[test]
+fn test_string_to_field() {
+    let field = |s: &str| s.to_string().to_field::<Fp>();
+    assert_eq!(field("42"), Some(Fp::from(42)));
+    assert_eq!(field("0x2a"), Some(Fp::from(42)));
+    assert_eq!(field("-1"), Some(-Fp::one()));
+    assert_eq!(field("-0x1"), Some(-Fp::one()));
+    // 2^64 + 1 and p + 1 do not fit a u64
+    assert_eq!(
+        field("18446744073709551617"),
+        Some(Fp::from(u64::MAX) + Fp::from(2))
+    );
+    assert_eq!(
+        field("0x40000000000000000000000000000000224698fc094cf91b992d30ed00000002"),
+        Some(Fp::one())
+    );
+    assert_eq!(field(""), None);
+    assert_eq!(field("0x"), None);
+    assert_eq!(field("1.5"), None);
+    assert_eq!(field("x"), None);
+}
+
 #[test]
 fn test_convert_cell_expression_to_value() {
     let a = "0x0000000000000000000000000000000000000000000000000000000000000002".to_string();
@@ -253,7 +495,7 @@ fn test_convert_cell_expression_to_value() {
             Box::new(CellExpression::Constant(a.clone())),
             Box::new(CellExpression::Constant(b.clone())),
         ))
-        .to_value_string()
+        .to_value_string::<Fp>()
     );
     assert_eq!(
         Some("0x0000000000000000000000000000000000000000000000000000000000002004".to_string()),
@@ -261,16 +503,17 @@ fn test_convert_cell_expression_to_value() {
             Box::new(CellExpression::Constant(a.clone())),
             Box::new(CellExpression::Constant(b.clone())),
         ))
-        .to_value_string()
+        .to_value_string::<Fp>()
     );
     assert_eq!(
         Some("0x0000000000000000000000000000000000000000000000000000000000004004".to_string()),
         (CellExpression::Scaled(Box::new(CellExpression::Constant(a.clone())), b.clone(),))
-            .to_value_string()
+            .to_value_string::<Fp>()
     );
     assert_eq!(
         Some("0x40000000000000000000000000000000224698fc094cf91b992d30ecffffffff".to_string()),
-        (CellExpression::Negated(Box::new(CellExpression::Constant(a.clone())))).to_value_string()
+        (CellExpression::Negated(Box::new(CellExpression::Constant(a.clone()))))
+            .to_value_string::<Fp>()
     );
 }
 
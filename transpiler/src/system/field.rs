@@ -0,0 +1,114 @@
+use std::{fmt, str::FromStr};
+
+use halo2_proofs::halo2curves::{
+    bn256::Fr,
+    ff::PrimeField,
+    pasta::{Fp, Fq},
+};
+use serde::{Deserialize, Serialize};
+
+use super::cell_expression::{field_to_string, Canonicalize, ToField, ToValueString};
+use super::CellExpression;
+
+/// Prime field a circuit is defined over, chosen with `# field: <name>`.
+///
+/// The Pasta fields are proven with IPA commitments on the other curve of the
+/// cycle, BN254 with KZG commitments, see [`crate::prover::Backend`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    /// base field of Pallas (`pasta::Fp`), proven with commitments on Vesta
+    #[default]
+    Pallas,
+    /// base field of Vesta (`pasta::Fq`), proven with commitments on Pallas
+    Vesta,
+    /// scalar field of BN254 (`bn256::Fr`), proven with KZG commitments
+    Bn254,
+}
+
+impl FieldKind {
+    pub const NAMES: [&'static str; 3] = ["pallas", "vesta", "bn254"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldKind::Pallas => "pallas",
+            FieldKind::Vesta => "vesta",
+            FieldKind::Bn254 => "bn254",
+        }
+    }
+
+    /// Value of `value` reduced in this field, see [`ToValueString`].
+    pub fn value_string<T: ToValueString + ?Sized>(&self, value: &T) -> Option<String> {
+        match self {
+            FieldKind::Pallas => value.to_value_string::<Fp>(),
+            FieldKind::Vesta => value.to_value_string::<Fq>(),
+            FieldKind::Bn254 => value.to_value_string::<Fr>(),
+        }
+    }
+
+    /// Inverse of `value` in this field, `None` when it is zero or no number.
+    pub fn inverse(&self, value: &str) -> Option<String> {
+        match self {
+            FieldKind::Pallas => inverse::<Fp>(value),
+            FieldKind::Vesta => inverse::<Fq>(value),
+            FieldKind::Bn254 => inverse::<Fr>(value),
+        }
+    }
+
+    /// `exp` written one way only in this field, see [`Canonicalize`].
+    pub fn canonicalize(&self, exp: &CellExpression) -> CellExpression {
+        match self {
+            FieldKind::Pallas => exp.canonicalize::<Fp>(),
+            FieldKind::Vesta => exp.canonicalize::<Fq>(),
+            FieldKind::Bn254 => exp.canonicalize::<Fr>(),
+        }
+    }
+
+    /// `value` reduced in this field, as the `0x` string of the element.
+    pub fn canonical(&self, value: &str) -> Option<String> {
+        let value = value.to_string();
+        match self {
+            FieldKind::Pallas => value.to_field::<Fp>().map(|x| field_to_string(&x)),
+            FieldKind::Vesta => value.to_field::<Fq>().map(|x| field_to_string(&x)),
+            FieldKind::Bn254 => value.to_field::<Fr>().map(|x| field_to_string(&x)),
+        }
+    }
+
+    /// Whether `value` is an element of this field.
+    pub fn is_element(&self, value: &str) -> bool {
+        let value = value.to_string();
+        match self {
+            FieldKind::Pallas => value.to_field::<Fp>().is_some(),
+            FieldKind::Vesta => value.to_field::<Fq>().is_some(),
+            FieldKind::Bn254 => value.to_field::<Fr>().is_some(),
+        }
+    }
+}
+
+fn inverse<F: PrimeField>(value: &str) -> Option<String> {
+    let value = value.to_string().to_field::<F>()?;
+    Option::<F>::from(value.invert()).map(|x| field_to_string(&x))
+}
+
+impl FromStr for FieldKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "pallas" => Ok(FieldKind::Pallas),
+            "vesta" => Ok(FieldKind::Vesta),
+            "bn254" | "bn256" => Ok(FieldKind::Bn254),
+            _ => Err(format!(
+                "unknown field `{}`, expected one of {}",
+                s,
+                FieldKind::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
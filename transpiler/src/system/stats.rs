@@ -0,0 +1,190 @@
+use std::{cmp, collections::HashSet};
+
+use serde::Serialize;
+
+use super::*;
+use crate::error::PlonkScriptError;
+
+/// Size of a circuit, see [`SimplifiedConstraitSystem::stats`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CircuitStats {
+    pub advice_columns: usize,
+    /// fixed columns, the one holding constants included
+    pub fixed_columns: usize,
+    pub instance_columns: usize,
+    pub selector_columns: usize,
+    pub table_columns: usize,
+    /// rows each region takes, by region name in the order they are laid out
+    pub region_rows: Vec<(String, usize)>,
+    /// rows the circuit needs: the regions one after another, or the longest
+    /// table, instance column or list of constants when they take more
+    pub rows: usize,
+    pub gates: usize,
+    pub degree: usize,
+    pub lookups: usize,
+    /// columns taking part in the permutation argument
+    pub permutation_columns: usize,
+    /// rows at the end of the circuit halo2 keeps for blinding, the last
+    /// usable row included
+    pub blinding_rows: usize,
+    /// smallest `k` whose `2^k` rows hold `rows` and the blinding rows
+    pub min_k: u32,
+}
+
+impl SimplifiedConstraitSystem {
+    /// Column, row, gate and lookup counts of the circuit, and the smallest `k`
+    /// it fits in.
+    ///
+    /// Regions are counted one after another, the floor planner may place
+    /// regions that use different columns side by side, so `rows` and `min_k`
+    /// are upper bounds.
+    pub fn stats(&self) -> CircuitStats {
+        let count = |types: &[ColumnType]| {
+            self.columns
+                .iter()
+                .filter(|c| types.contains(&c.ctype))
+                .count()
+        };
+        let uses_constants = self.uses_constants() as usize;
+
+        let region_rows = self
+            .regions
+            .iter()
+            .map(|region| (region.name.clone(), region.rows()))
+            .collect::<Vec<_>>();
+        let table_rows = self
+            .tables
+            .iter()
+            .map(|t| t.instructions.len())
+            .max()
+            .unwrap_or(0);
+        let instance_rows = self
+            .instance_columns()
+            .iter()
+            .map(|(_, signals)| signals.len())
+            .max()
+            .unwrap_or(0);
+        // the floor planner gives every constant a row of the constants column
+        let constants = self
+            .regions
+            .iter()
+            .flat_map(|r| &r.instructions)
+            .filter_map(|instruction| match instruction {
+                Instruction::AssignAdviceFromConstant(_, c)
+                | Instruction::ConstrainConstant(_, c) => Some(c),
+                _ => None,
+            })
+            .count();
+        let rows = [
+            region_rows.iter().map(|(_, rows)| rows).sum(),
+            table_rows,
+            instance_rows,
+            constants,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0);
+
+        // as `blinding_factors` in halo2: the most queries of an advice column,
+        // 3 at least, one more for the multiopen argument and one for safety,
+        // then the row of `l_last`
+        let blinding_rows = cmp::max(3, self.max_advice_queries()) + 2 + 1;
+        // halo2 also wants a usable row and one for `l_0`
+        let needed = cmp::max(rows, 2) + blinding_rows;
+        let min_k = needed.next_power_of_two().trailing_zeros();
+
+        CircuitStats {
+            advice_columns: count(&[ColumnType::Advice]),
+            fixed_columns: count(&[ColumnType::Fixed]) + uses_constants,
+            instance_columns: self.instance_columns().len(),
+            selector_columns: count(&[ColumnType::Selector, ColumnType::ComplexSelector]),
+            table_columns: count(&[ColumnType::TableLookup]),
+            region_rows,
+            rows,
+            gates: self.gate_groups().len(),
+            degree: self.degree(),
+            lookups: self.lookups.len(),
+            permutation_columns: self.columns_in_equality().len() + uses_constants,
+            blinding_rows,
+            min_k,
+        }
+    }
+
+    /// `k` of the circuit, the `k` parameter when the script or its inputs
+    /// set one, the smallest `k` it fits in otherwise.
+    pub fn k(&self) -> Result<u32, PlonkScriptError> {
+        match self.inputs.get("k") {
+            Some(k) => k
+                .parse::<u32>()
+                .map_err(|_| PlonkScriptError::InvalidInput {
+                    name: "k".to_string(),
+                    value: k.clone(),
+                }),
+            None => Ok(self.stats().min_k),
+        }
+    }
+
+    // distinct rotations advice columns are queried at, by gates, lookups and
+    // the permutation, which queries the current row of its columns
+    fn max_advice_queries(&self) -> usize {
+        let mut queries = HashSet::<(&str, i64)>::new();
+        let expressions = self.gates.iter().map(|(_, _, _, exp)| exp).chain(
+            self.lookups
+                .iter()
+                .flat_map(|l| l.map.iter().map(|(exp, _)| exp)),
+        );
+        for exp in expressions {
+            advice_queries(exp, &mut queries);
+        }
+        let in_equality = self.columns_in_equality();
+        for column in &self.columns {
+            if column.ctype == ColumnType::Advice && in_equality.contains(&column.name) {
+                queries.insert((&column.name, 0));
+            }
+        }
+        self.columns
+            .iter()
+            .map(|column| {
+                queries
+                    .iter()
+                    .filter(|(name, _)| *name == column.name)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl InstructionBundle {
+    /// One more than the last row any cell of the region is assigned or
+    /// enabled on.
+    pub fn rows(&self) -> usize {
+        self.instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::AssignFixed(c, _)
+                | Instruction::EnableSelector(c)
+                | Instruction::AssignAdvice(c, _)
+                | Instruction::AssignAdviceFromConstant(c, _)
+                | Instruction::AssignAdviceFromInstance(c, _) => Some(c.index + 1),
+                // copies may read cells of other regions
+                _ => None,
+            })
+            .max()
+            .map_or(0, |rows| rows as usize)
+    }
+}
+
+fn advice_queries<'a>(exp: &'a CellExpression, queries: &mut HashSet<(&'a str, i64)>) {
+    match exp {
+        CellExpression::CellValue(c) if c.column.ctype == ColumnType::Advice => {
+            queries.insert((&c.column.name, c.index));
+        }
+        CellExpression::Negated(n) | CellExpression::Scaled(n, _) => advice_queries(n, queries),
+        CellExpression::Product(a, b) | CellExpression::Sum(a, b) => {
+            advice_queries(a, queries);
+            advice_queries(b, queries);
+        }
+        _ => {}
+    }
+}
@@ -3,7 +3,29 @@ mod tests {
     use std::fs;
     use std::path::{Path, PathBuf};
     use std::collections::HashMap;
-    use crate::{try_run, IncludeDetails};
+    use std::panic::AssertUnwindSafe;
+    use halo2_proofs::{
+        arithmetic::Field,
+        dev::{metadata::{self, VirtualCell}, FailureLocation, MockProver, VerifyFailure},
+        halo2curves::pasta::{Fp, Fq},
+        plonk::{Any, SecondPhase},
+    };
+    use crate::{
+        diagnostic::Diagnostic,
+        engine,
+        artifact::{self, ProofFile, VerifyingKeyFile},
+        circuit::MyCircuit,
+        error::{EngineError, PlonkScriptError},
+        failure::{self, Failure, FailureKind},
+        formatter,
+        generator::generate_rust_code,
+        inputs,
+        prover::{Backend, Kzg},
+        session::Session,
+        system::{cell_expression::ToString, ColumnType, FieldKind, Instruction},
+        transpiler::{transpile, transpile_with_source_map},
+        check, try_prove, try_prove_with_params, try_run, try_run_with_inputs, IncludeDetails,
+    };
 
     fn get_project_root() -> PathBuf {
         let current_dir = std::env::current_dir().unwrap();
@@ -163,6 +185,890 @@ mod tests {
         }
     }
     
+    #[test]
+    fn test_concurrent_sessions() {
+        let project_root = get_project_root();
+        let files = ["fibonacci.plonk", "simple_arith.plonk", "table_simple.plonk"];
+        let run = |file_name: &str| {
+            let code = fs::read_to_string(project_root.join(format!("plonk/src/{}", file_name)))
+                .expect("Failed to read plonk file");
+            let session = Session::new(resolve_lib_modules()).unwrap();
+            session.run(&transpile(code).unwrap()).expect("Script failed");
+            let scs = session.into_system();
+            format!("{:?} {:?} {:?}", scs.signals, scs.regions, scs.gates)
+        };
+
+        let sequential = files.map(run);
+        let concurrent = std::thread::scope(|s| {
+            files
+                .map(|file_name| s.spawn(move || run(file_name)))
+                .map(|h| h.join().unwrap())
+        });
+
+        assert_eq!(sequential, concurrent);
+    }
+
+    #[test]
+    fn test_scope_restored_after_panic() {
+        let cs = check("col advice a;\na[0] <-- 1;\n", HashMap::new(), HashMap::new()).unwrap();
+        let circuit = MyCircuit::<Fp>::new(cs);
+        let caught = std::panic::catch_unwind(AssertUnwindSafe(|| circuit.scope(|_| panic!("in scope"))));
+        assert!(caught.is_err());
+        // the system lent to the panicking scope is taken back, so configuring
+        // outside of a scope still fails instead of reusing it
+        let outside = std::panic::catch_unwind(AssertUnwindSafe(|| MockProver::run(4, &circuit, vec![])));
+        assert!(outside.is_err());
+        circuit.scope(|c| MockProver::run(4, c, vec![])).unwrap();
+    }
+
+    #[test]
+    fn test_check_circuit() {
+        let code = "col advice a;\ncol lookup t;\nt.fill(0..4);\na[0] <-- 1;\na[1] <== a[0] * 2;\nlookup([a[0]], [t]);\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        engine::gate::check_circuit(&cs).unwrap();
+
+        // a gate querying a column configure does not create
+        let mut gate = cs.clone();
+        gate.columns.retain(|c| c.name != "a");
+        let error = EngineError::find(&engine::gate::check_circuit(&gate).unwrap_err()).unwrap();
+        assert!(error.message.contains("Advice column a of cell"), "{}", error);
+
+        // a lookup into a column that is no table
+        let mut lookup = cs.clone();
+        lookup.lookups[0].map[0].1 = lookup.columns.iter().find(|c| c.name == "a").unwrap().clone();
+        let error = EngineError::find(&engine::gate::check_circuit(&lookup).unwrap_err()).unwrap();
+        assert!(error.message.contains("not a declared table column"), "{}", error);
+    }
+
+    #[test]
+    fn test_export_errors() {
+        let code = "pub input x;\ncol advice a;\na[0] <== x;\na[1] <== a[0] * a[0];\n";
+        let inputs = HashMap::from([("x".to_string(), "3".to_string())]);
+        let cs = check(code, HashMap::new(), inputs).unwrap();
+        let exported = generate_rust_code(&cs).unwrap();
+        assert!(exported.contains("config.get_assigned_cell(\"a[0]_default_0\")?"), "{}", exported);
+
+        // reported instead of panicking, for `plonkscript export` to exit with an error
+        let mut table_in_region = cs.clone();
+        let column = table_in_region.cells["a[0]_default_0"].column.clone();
+        table_in_region.regions[0].instructions.push(Instruction::AssignCell(column, "1".to_string()));
+        let error = generate_rust_code(&table_in_region).unwrap_err();
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+
+        let mut missing = cs.clone();
+        missing.signals[0].value = None;
+        let error = generate_rust_code(&missing).unwrap_err();
+        assert!(matches!(error, PlonkScriptError::MissingInput { .. }), "{}", error);
+    }
+
+    #[test]
+    fn test_transpile_multiline_constraint() {
+        let code = "a[0] <==\n    b[0] +\n    b[1];\nlet x = 1;";
+        let script = transpile(code.to_string()).unwrap();
+        assert_eq!(
+            script,
+            "a[0] = assign_constraint(a[0], b[0] +\n    b[1]);\nlet x = 1;\n"
+        );
+    }
+
+    #[test]
+    fn test_transpile_region_with_nested_blocks() {
+        let code = r#"region fib(a, b) {
+    for i in 0..2 {
+        if i == 0 { a[i] <-- b[i]; } else { s[i] <-- enable; }
+    }
+    a[2] === b[2];
+}"#;
+        let script = transpile(code.to_string()).unwrap();
+        assert_eq!(
+            script,
+            r#"fn fib(a, b) {define_region("fib");
+    for i in 0..2 {
+        if i == 0 { a[i] = assign_common(a[i], b[i]); } else { enable_selector(s[i]); }
+    }
+    a[2] = constrain_equal(a[2], b[2]);
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_transpile_gate() {
+        let code = "gate add(a,\n    b) {\n    a[0] + b[0] - a[1]; // sum\n    a[-1] * b[-1];\n}\nadd[1] <-- enable;\n";
+        let script = transpile(code.to_string()).unwrap();
+        assert_eq!(
+            script,
+            "let add = define_gate(\"add\", [a,\n    b], [\n    a[0] + b[0] - a[1], // sum\n    a[-1] * b[-1],\n]);\nenable_selector(add[1]);\n\n"
+        );
+
+        let err = transpile("gate g(a) {\n    a[0] <== 1;\n}".to_string()).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 10));
+        let err = transpile("gate g(a) {}".to_string()).unwrap_err();
+        assert!(err.message.contains("no constraints"), "{}", err);
+    }
+
+    #[test]
+    fn test_transpile_keeps_comments_and_strings() {
+        let code = "// a <== b;\n/* c <== d; */\nprint(\"e <== f;\");\n# k: 4\n";
+        let script = transpile(code.to_string()).unwrap();
+        assert_eq!(
+            script,
+            "// a <== b;\n/* c <== d; */\nprint(\"e <== f;\");\nset_parameter(\"k\", 4);\n\n"
+        );
+    }
+
+    #[test]
+    fn test_transpile_declarations() {
+        let hex = format!("0x{}", "0".repeat(63) + "1");
+        let code = format!("pub input x;\npub output out;\ncol advice a;\na[0] <== {};", hex);
+        let script = transpile(code).unwrap();
+        assert_eq!(
+            script,
+            format!(
+                "let x = init_input(\"x\");\nlet out = init_output(\"out\");\n\
+                 let a = init_advice_column(\"a\");\na[0] = assign_constraint(a[0], \"{}\");\n\
+                 set_output(\"out\", out);",
+                hex
+            )
+        );
+    }
+
+    #[test]
+    fn test_transpile_errors() {
+        let err = transpile("a[0] <== b\nc[0] <== d;".to_string()).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 6));
+
+        let err = transpile("region r() {\n  a <== b;\n".to_string()).unwrap_err();
+        assert!(err.message.contains("`}`"), "{}", err);
+
+        let err = transpile("pub inout i;".to_string()).unwrap_err();
+        assert_eq!((err.line, err.column), (1, 5));
+    }
+
+    fn run_error(code: &str) -> String {
+        match try_run(code.to_string(), HashMap::new(), None) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diagnostic_parse_error() {
+        let error = run_error("col advice a;\na[0] <== 1\na[1] <== 2;\n");
+        assert_eq!(
+            error,
+            "error: unexpected `<==`, missing `;`?\n --> 3:6\n  |\n3 | a[1] <== 2;\n  |      ^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_script_error_in_region() {
+        let code = "col advice a;\nregion r(a) {\n    a[0] <-- 1;\n    let x = missing(1);\n}\nr(a);\n";
+        let error = run_error(code);
+        assert!(error.contains("Function not found: missing"), "{}", error);
+        assert!(error.contains(" --> 4:13\n"), "{}", error);
+        assert!(error.contains("4 |     let x = missing(1);\n  |             ^^^^^^^\n"), "{}", error);
+        assert!(error.contains("note: in `r`, called at 6:1"), "{}", error);
+    }
+
+    #[test]
+    fn test_diagnostic_gate_error() {
+        let code = "pub input x;\ncol advice a;\n\n  a[0] <== a[1] * x;\n";
+        let error = run_error(code);
+        assert!(error.contains("Instance cannot be used in gate"), "{}", error);
+        assert!(error.contains(" --> 4:3\n"), "{}", error);
+    }
+
+    #[test]
+    fn test_diagnostic_verify_failure() {
+        let code = "col advice a;\nregion r(a) {\n    a[0] <== a[1] + a[2];\n}\nr(a);\n";
+        let (script, source_map) = transpile_with_source_map(code).unwrap();
+        let session = Session::new(HashMap::new()).unwrap();
+        session.run(&script).unwrap();
+        let cs = session.into_system();
+
+        let failure = VerifyFailure::ConstraintNotSatisfied {
+            constraint: ((0, "gate_0").into(), 0, "").into(),
+            location: FailureLocation::InRegion { region: (1, "r").into(), offset: 0 },
+            cell_values: vec![],
+        };
+        let diagnostic = Diagnostic::from_verify_failure(code, &script, &source_map, &cs, &failure);
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (3, 5));
+        assert_eq!(
+            diagnostic.notes,
+            vec![
+                "`gate_0` was created by this statement".to_string(),
+                "in region `r` defined at 2:1".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failure_fields() {
+        let code = "col advice a;\ncol selector s;\ncol lookup t;\nt.push(0);\na[0] <-- 1;\ns[0] <-- enable;\nlookup(\"zero\", [a * s], [t]);\n";
+        let (script, source_map) = transpile_with_source_map(code).unwrap();
+        let session = Session::new(HashMap::new()).unwrap();
+        session.run(&script).unwrap();
+        let circuit = MyCircuit::<Fp>::new(session.into_system());
+        let from = |failure| Failure::from_verify_failure(code, &script, &source_map, &circuit, &failure);
+
+        let permutation = from(VerifyFailure::Permutation {
+            column: (Any::advice(), 0).into(),
+            location: FailureLocation::InRegion { region: (0, "default").into(), offset: 0 },
+        });
+        assert_eq!(permutation.kind, FailureKind::Permutation);
+        assert_eq!(permutation.column.as_deref(), Some("a"));
+        assert_eq!((permutation.region.as_deref(), permutation.row), (Some("default"), Some(0)));
+
+        let lookup = from(VerifyFailure::Lookup {
+            name: "zero".to_string(),
+            lookup_index: 0,
+            location: FailureLocation::OutsideRegion { row: 1 },
+        });
+        assert_eq!((lookup.kind, lookup.lookup.as_deref(), lookup.row), (FailureKind::Lookup, Some("zero"), Some(1)));
+        let location = lookup.diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (7, 1));
+        assert_eq!(lookup.diagnostic.notes, vec!["lookup `zero` was defined by this statement".to_string()]);
+    }
+
+    #[test]
+    fn test_merge_gates() {
+        let code = "col advice a;\ncol advice b;\ncol advice c;\nfor i in 0..3 {\n    a[i] <-- i;\n    b[i] <== a[i] + 1;\n    c[i] <== a[i] * a[i];\n}\nc[3] <== a[0] + a[1];\n";
+        let (script, source_map) = transpile_with_source_map(code).unwrap();
+        let session = Session::new(HashMap::new()).unwrap();
+        session.run(&script).unwrap();
+        let mut cs = session.into_system();
+        assert_eq!(cs.gate_groups().len(), 3);
+
+        // b and c are constrained on the same rows, under one selector
+        cs.merge_gates();
+        let groups = cs.gate_groups();
+        assert_eq!(groups.iter().map(|(name, polys)| (*name, polys.len())).collect::<Vec<_>>(), vec![("gate_0", 2), ("gate_2", 1)]);
+        assert!(!cs.columns.iter().any(|c| c.name == "gate_1"));
+        let enabled = cs.regions.iter().flat_map(|r| &r.instructions).filter(|i| matches!(i, crate::system::Instruction::EnableSelector(_))).count();
+        assert_eq!(enabled, 4);
+
+        let failure = VerifyFailure::ConstraintNotSatisfied {
+            constraint: ((0, "gate_0").into(), 1, "").into(),
+            location: FailureLocation::InRegion { region: (0, "default").into(), offset: 2 },
+            cell_values: vec![],
+        };
+        let diagnostic = Diagnostic::from_verify_failure(code, &script, &source_map, &cs, &failure);
+        let location = diagnostic.location.unwrap();
+        assert_eq!((location.line, location.column), (7, 5));
+
+        assert_eq!(check(code, HashMap::new(), HashMap::new()).unwrap().gate_groups().len(), 2);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+    }
+
+    fn run_error_kind(code: &str) -> PlonkScriptError {
+        match try_run(code.to_string(), HashMap::new(), None) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let error = run_error_kind("pub input x;\ncol advice a;\na[0] <== x;\n");
+        assert!(
+            matches!(&error, PlonkScriptError::MissingInput { name } if name == "x"),
+            "{}",
+            error
+        );
+
+        let error = run_error_kind("#k: \"big\"\ncol advice a;\na[0] <-- 1;\n");
+        assert!(
+            matches!(&error, PlonkScriptError::InvalidInput { name, value } if name == "k" && value == "big"),
+            "{}",
+            error
+        );
+
+        let error = run_error_kind("col advice a;\na.push(1);\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+
+        let error = run_error_kind("pub input x;\ncol advice a;\na[0] <== a[1] * x;\n");
+        assert!(matches!(error, PlonkScriptError::GateError(_)), "{}", error);
+
+        let error = run_error_kind("col advice a;\nlet b = a[0] / 0;\n");
+        assert!(matches!(&error, PlonkScriptError::ScriptError(_)), "{}", error);
+        assert!(error.to_string().contains("division by zero"), "{}", error);
+
+        let error = run_error_kind("col advice a;\na[0] <-- \"x\";\n");
+        assert!(error.to_string().contains("x is not a field element"), "{}", error);
+    }
+
+    #[test]
+    fn test_module_error() {
+        let modules = HashMap::from([("broken".to_string(), "let x = 1 +;".to_string())]);
+        match Session::new(modules) {
+            Err(PlonkScriptError::ModuleError { module, .. }) => assert_eq!(module, "broken"),
+            _ => panic!("expected a module error"),
+        }
+    }
+
+    #[test]
+    fn test_failure_column_names() {
+        let code = "pub input x;\ncol advice a;\ncol lookup t;\ncol fixed f;\ncol advice b;\n";
+        let (script, _) = transpile_with_source_map(code).unwrap();
+        let session = Session::new(HashMap::new()).unwrap();
+        session.run(&script).unwrap();
+        let circuit = MyCircuit::<Fp>::new(session.into_system());
+
+        let name = |column_type, index| circuit.column_name(&column_type, index);
+        assert_eq!(name(Any::advice(), 1).as_deref(), Some("b"));
+        assert_eq!(name(Any::Fixed, 0).as_deref(), Some("t"));
+        assert_eq!(name(Any::Fixed, 1).as_deref(), Some("f"));
+        assert_eq!(name(Any::Fixed, 2), None);
+        assert_eq!(name(Any::Instance, 0).as_deref(), Some("defins"));
+
+        // cells as MockProver prints them, the phase shows for later phases only
+        let cell = |column_type, index, rotation| {
+            VirtualCell::from((metadata::Column::from((column_type, index)), rotation)).to_string()
+        };
+        assert_eq!(failure::cell_name(&circuit, &cell(Any::advice(), 1, -1)), "b@-1");
+        assert_eq!(failure::cell_name(&circuit, &cell(Any::advice_in(SecondPhase), 1, 0)), "b@0");
+        assert_eq!(failure::cell_name(&circuit, &cell(Any::Fixed, 1, 0)), "f@0");
+        assert_eq!(failure::cell_name(&circuit, &cell(Any::Fixed, 3, 0)), cell(Any::Fixed, 3, 0));
+    }
+
+    #[test]
+    fn test_failure_permutation() {
+        let code = "col advice a;\na[0] <-- 1;\na[1] <-- 2;\na[0] === a[1];\n";
+        match run_error_kind(code) {
+            PlonkScriptError::ConstraintUnsatisfied { failures } => {
+                assert!(failures.iter().any(|f| f.kind == FailureKind::Permutation
+                    && f.column.as_deref() == Some("a")
+                    && f.region.as_deref() == Some("default")));
+            }
+            e => panic!("expected unsatisfied constraints, got {}", e),
+        }
+    }
+
+    #[test]
+    fn test_prove() {
+        let code = "#k: 4\n#x: 3\npub input x;\npub output y;\ncol advice a;\na[0] <== x;\na[1] <== a[0] * a[0];\ny <== a[1];\n";
+        let result = try_prove(code.to_string(), HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(result.k, 4);
+        assert!(!result.proof.is_empty());
+        assert!(!result.verifying_key.is_empty());
+    }
+
+    #[test]
+    fn test_proof_files() {
+        let code = "#k: 4\n#x: 3\npub input x;\ncol advice a;\na[0] <== x;\na[1] <== a[0] * a[0];\n";
+        let result = try_prove(code.to_string(), HashMap::new(), HashMap::new()).unwrap();
+        let dir = std::env::temp_dir().join(format!("plonkscript-proof-{}", std::process::id()));
+        result.write_to(&dir).unwrap();
+        artifact::verify_dir(&dir).unwrap();
+
+        let params = artifact::read_params(&dir.join(artifact::PARAMS_FILE)).unwrap();
+        let vk = VerifyingKeyFile::read(&dir.join(artifact::VERIFYING_KEY_FILE)).unwrap();
+        let mut proof = ProofFile::read(&dir.join(artifact::PROOF_FILE)).unwrap();
+        assert_eq!(proof.public_inputs[0].name, "x");
+        assert!(vk.circuit.signals.iter().all(|s| s.value.is_none()));
+        assert_eq!(hex::decode(&vk.key).unwrap(), result.verifying_key);
+
+        let mut tampered = vk.clone();
+        tampered.key = hex::encode([0u8; 4]);
+        assert!(matches!(
+            artifact::verify(&params, &tampered, &proof),
+            Err(PlonkScriptError::InvalidArtifact { .. })
+        ));
+        proof.verifying_key = "0".repeat(64);
+        assert!(matches!(
+            artifact::verify(&params, &vk, &proof),
+            Err(PlonkScriptError::InvalidArtifact { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prove_with_params() {
+        let code = "#k: 4\n#field: bn254\npub output y;\ncol advice a;\na[0] <== 3;\ny <== a[0];\n";
+        let path = std::env::temp_dir().join(format!("plonkscript-params-{}.bin", std::process::id()));
+        let prove = |k: u32| {
+            let params = Kzg::write_params(&Kzg::setup(k)).unwrap();
+            artifact::write_params(&params, &path).unwrap();
+            try_prove_with_params(code.to_string(), HashMap::new(), HashMap::new(), Some(&path))
+        };
+
+        let result = prove(6).unwrap();
+        assert_eq!(result.k, 4);
+        assert!(!result.local_setup);
+        assert_eq!(Kzg::params_k(&Kzg::read_params(&result.params).unwrap()), 4);
+        assert!(matches!(prove(3), Err(PlonkScriptError::InvalidArtifact { .. })));
+        fs::remove_file(&path).unwrap();
+
+        let result = try_prove(code.to_string(), HashMap::new(), HashMap::new()).unwrap();
+        assert!(result.local_setup);
+    }
+
+    #[test]
+    fn test_input_overrides() {
+        let code = "#k: 4\npub input x;\npub output y;\ncol advice a;\na[0] <== x;\ny <== a[0];\n";
+        let inputs = HashMap::from([
+            ("k".to_string(), "6".to_string()),
+            ("x".to_string(), "5".to_string()),
+        ]);
+        let cs = check(code, HashMap::new(), inputs.clone()).unwrap();
+        assert_eq!(cs.inputs.get("k").map(String::as_str), Some("6"));
+        try_run_with_inputs(code.to_string(), HashMap::new(), inputs, None).unwrap();
+
+        let error = run_error_kind(code);
+        assert!(matches!(&error, PlonkScriptError::MissingInput { name } if name == "x"), "{}", error);
+    }
+
+    #[test]
+    fn test_field_selection() {
+        let code = |field: &str| {
+            format!("{}pub output y;\ncol advice a;\na[0] <-- 2;\na[1] <== a[0] - 3;\ny <== a[1];\n", field)
+        };
+        let minus_one = |code: String, inputs: HashMap<String, String>| {
+            let cs = check(&code, HashMap::new(), inputs).unwrap();
+            (cs.field, cs.signals[0].value.clone().unwrap())
+        };
+
+        let (field, value) = minus_one(code(""), HashMap::new());
+        assert_eq!(field, FieldKind::Pallas);
+        assert_eq!(value, (-Fp::one()).to_string());
+
+        let (field, value) = minus_one(code("#field: vesta\n"), HashMap::new());
+        assert_eq!(field, FieldKind::Vesta);
+        assert_eq!(value, (-Fq::one()).to_string());
+
+        let inputs = HashMap::from([("field".to_string(), "vesta".to_string())]);
+        let (field, _) = minus_one(code("#field: pallas\n"), inputs);
+        assert_eq!(field, FieldKind::Vesta);
+
+        try_run(code("#field: vesta\n"), HashMap::new(), None).unwrap();
+        let result = try_prove(code("#k: 4\n#field: vesta\n"), HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(result.circuit.field, FieldKind::Vesta);
+
+        let (field, value) = minus_one(code("#field: bn254\n"), HashMap::new());
+        assert_eq!(field, FieldKind::Bn254);
+        assert_eq!(value, "0x30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000000");
+        let result = try_prove(code("#k: 4\n#field: bn254\n"), HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(result.circuit.field, FieldKind::Bn254);
+        let dir = std::env::temp_dir().join(format!("plonkscript-bn254-{}", std::process::id()));
+        result.write_to(&dir).unwrap();
+        artifact::verify_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let error = run_error_kind(&code("#field: goldilocks\n"));
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+        let error = run_error_kind("col advice a;\n#field: vesta\n");
+        assert!(matches!(error, PlonkScriptError::ScriptError(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_field_values() {
+        // 2^100, -7 and 1/3 as witnesses, the constraints hold in the field
+        let code = "#k: 4\n#x: 1267650600228229401496703205376\npub input x;\npub output y;\ncol advice a;\n\
+            a[0] <== x;\na[1] <-- -7;\na[2] <== a[0] + a[1];\na[3] <-- 1;\na[4] <-- 3;\n\
+            a[5] <-- a[3] / a[4];\na[6] <== a[5] * a[4];\ny <== a[6];\na[7] <-- \"6\" / 4;\na[8] <-- 6 / 4;\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        let value = |name: &str| cs.cells[&format!("{}_default_0", name)].value.clone().unwrap();
+        assert_eq!(value("a[5]"), Fp::from(3).invert().unwrap().to_string());
+        // a field value makes the quotient a field one, two integers divide as integers
+        assert_eq!(value("a[7]"), (Fp::from(6) * Fp::from(4).invert().unwrap()).to_string());
+        assert_eq!(value("a[8]"), "1");
+        assert_eq!(value("a[2]"), (Fp::from(1 << 50).square() - Fp::from(7)).to_string());
+        assert_eq!(cs.signals[1].value.as_deref(), Some(Fp::one().to_string().as_str()));
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        let error = run_error_kind("col advice a;\na[0] <-- 1;\na[1] <-- 0;\na[2] <-- a[0] / a[1];\n");
+        assert!(error.to_string().contains("division by zero"), "{}", error);
+    }
+
+    #[test]
+    fn test_constants() {
+        let code = "col advice a;\na[0] <== 5;\na[1] <== a[0] + 1;\na[1] === 6;\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        let instructions = cs.regions.iter().flat_map(|r| &r.instructions).collect::<Vec<_>>();
+        assert!(instructions.iter().any(|i| matches!(i, crate::system::Instruction::AssignAdviceFromConstant(a, c) if a.name == "a[0]_default_0" && c == "5")));
+        assert!(instructions.iter().any(|i| matches!(i, crate::system::Instruction::ConstrainConstant(a, c) if a.name == "a[1]_default_0" && c == "6")));
+        let circuit = MyCircuit::<Fp>::new(cs);
+        assert_eq!(circuit.column_name(&Any::Fixed, 0).as_deref(), Some("constants"));
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        let error = run_error_kind("pub input x;\nx === 5;\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_copy_constraints() {
+        let code = "col advice a;\ncol fixed f;\npub output out;\n\
+            region r(a, c) {\n    a[0] <== c;\n    a[0] === c;\n}\n\
+            f[0] <-- 4;\na[0] <== f[0];\nr(a, a[0]);\nout <== a[0];\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        let copies = |region: usize| {
+            cs.regions[region]
+                .instructions
+                .iter()
+                .filter_map(|i| match i {
+                    crate::system::Instruction::ConstrainEqual(a, b) => Some((a.name.as_str(), b.name.as_str())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(copies(0), vec![("a[0]_default_0", "f[0]_default_0")]);
+        assert_eq!(
+            copies(1),
+            vec![
+                ("a[0]_r_1", "a[0]_default_0"),
+                ("a[0]_r_1", "a[0]_default_0"),
+                ("a[0]_r_1", "out"),
+            ]
+        );
+        assert_eq!(cs.columns_in_equality(), vec!["a", "f", "defins"]);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        for code in ["col advice a;\na[0] <== a[1];\n", "col advice a;\na[0] <== 1;\na[0] === a[2];\n"] {
+            let error = run_error_kind(code);
+            assert!(matches!(error, PlonkScriptError::ScriptError(_)), "{}", error);
+            assert!(error.to_string().contains("before it is assigned"), "{}", error);
+        }
+        let error = run_error_kind("pub input x;\npub input y;\nx === y;\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_equality_columns() {
+        let code = "col advice a;\ncol advice b;\ncol advice c @equality;\ncol advice d;\na[0] <-- 1;\nb[0] <== a[0];\nd[0] <== a[0] + 1;\n";
+        let script = transpile(code.to_string()).unwrap();
+        assert!(script.contains("let c = init_advice_column(\"c\"); enable_equality(c);\n"), "{}", script);
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        // d is only constrained by a gate
+        assert_eq!(cs.columns_in_equality(), vec!["c", "b", "a"]);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        let err = transpile("col advice a @eq;".to_string()).unwrap_err();
+        assert!(err.message.contains("unknown column annotation"), "{}", err);
+        let error = run_error_kind("col selector s @equality;\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_lookup_tables() {
+        let code = "col advice a;\ncol selector s;\ncol lookup t;\ncol lookup u;\n\
+            t.fill(0..4);\nu.fill([0, 1, \"0x04\", 9]);\n\
+            a[0] <-- 2;\ns[0] <-- enable;\nlookup([a * s], [t]);\nlookup(\"square\", [a * s, a * a * s], [t, u]);\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        let names = cs.lookups.iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["lookup_0", "square"]);
+        assert!(cs.tables.iter().all(|t| t.instructions.len() == 4));
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        // a fixed column is copied into a table once the script is done
+        let code = "col advice a;\ncol selector s;\ncol fixed f;\n\
+            a[0] <-- 1;\ns[0] <-- enable;\nlookup([a * s], [f]);\nf[0] <-- 0;\nf[1] <-- 1;\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.lookups[0].map[0].1.name, "f_table");
+        assert_eq!(cs.tables[0].instructions.len(), 2);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        // an advice column is copied into a table gated by its selector
+        let code = "col advice a;\ncol selector s;\ncol advice b;\n\
+            b[0] <-- 3;\nb[1] <-- 5;\na[0] <-- 5;\ns[0] <-- enable;\nlookup(\"dynamic\", [a * s], [b]);\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.lookups[0].map[0].1.name, "b_table");
+        assert!(cs.lookups[0].reads_advice());
+        assert!(cs.columns.iter().any(|c| c.name == "b_table_selector" && c.ctype == ColumnType::ComplexSelector));
+        let region = cs.regions.last().unwrap();
+        assert_eq!(region.name, "advice_tables");
+        assert_eq!(region.rows(), 2);
+        assert_eq!(region.instructions.len(), 6);
+        assert_eq!(cs.cells["b_table[1]_advice_tables_1"].value, cs.cells["b[1]_default_0"].value);
+        assert!(cs.columns_in_equality().contains(&"b".to_string()));
+        // the selector adds a degree to the table
+        assert_eq!(cs.degree(), 6);
+        let exported = generate_rust_code(&cs).unwrap();
+        assert!(exported.contains("lookup_any"), "{}", exported);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        let error = run_error("col advice a;\ncol lookup t;\ncol lookup u;\nt.fill(0..4);\nu.fill(0..=4);\nlookup([a[0], a[0]], [t, u]);\n");
+        assert!(error.contains("have different lengths: t has 4 rows, u has 5 rows"), "{}", error);
+        assert!(error.contains("6:1"), "{}", error);
+        let error = run_error("col advice a;\ncol advice b;\nlookup([a[0]], [b]);\n");
+        assert!(error.contains("table column b_table of lookup `lookup_0` is empty"), "{}", error);
+        let error = run_error_kind("col advice a;\ncol advice b;\ncol lookup t;\nb[0] <-- 1;\nt.push(1);\nlookup([a[0], a[0]], [b, t]);\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+        let error = run_error("col advice a;\ncol lookup t;\nt.push(1);\nlookup(\"x\", [a[0]], [t]);\nlookup(\"x\", [a[0]], [t]);\n");
+        assert!(error.contains("lookup `x` is already defined"), "{}", error);
+    }
+
+    #[test]
+    fn test_range_check() {
+        let code = "#k: 6\ncol advice a;\na[0] <-- 200;\na[1] <-- 5;\na[2] <-- 7;\n\
+            range_check(a[0], 8);\nrange_check(a[1], 3);\nrange_check(a[2], 3);\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        // one table for every call
+        assert_eq!(cs.tables.len(), 1);
+        assert_eq!(cs.tables[0].instructions.len(), 16);
+        let names = cs.lookups.iter().map(|l| l.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["range_check_a_limb_0", "range_check_a_limb_1", "range_check_a_limb_0_shl_1"]);
+        assert_eq!(cs.cells["a_limb_1[0]_default_0"].value, Some("12".to_string()));
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        match run_error_kind("#k: 6\ncol advice a;\na[0] <-- 9;\nrange_check(a[0], 3);\n") {
+            PlonkScriptError::ConstraintUnsatisfied { failures } => {
+                assert!(failures.iter().any(|f| f.kind == FailureKind::Lookup
+                    && f.lookup.as_deref() == Some("range_check_a_limb_0_shl_1")));
+            }
+            e => panic!("expected unsatisfied constraints, got {}", e),
+        }
+
+        let error = run_error_kind("col fixed f;\nf[0] <-- 1;\nrange_check(f[0], 8);\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+        let error = run_error("col advice a;\nrange_check(a[0], 8);\n");
+        assert!(error.contains("is range checked before it is assigned"), "{}", error);
+    }
+
+    #[test]
+    fn test_degree() {
+        let code = "col advice a;\ncol fixed f;\na[0] <-- 2;\nf[0] <-- 1;\na[1] <== (a[0] + f[0]) ** 5;\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        // the selector makes it 6
+        assert_eq!(cs.gate_degrees(), vec![("gate_0", 6)]);
+        assert_eq!(cs.degree(), 6);
+        assert_eq!(cs.extended_domain_factor(), 8);
+
+        let reduced = format!("#max_degree: 4\n{}", code);
+        let cs = check(&reduced, HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.degree(), 4);
+        assert!(cs.columns.iter().any(|c| c.name == "deg_0"));
+        assert_eq!(cs.cells["a[1]_default_0"].value, Some(Fp::from(243).to_string()));
+        try_run(reduced, HashMap::new(), None).unwrap();
+
+        let cs = check("col advice a;\ncol selector s;\ncol lookup t;\nt.push(0);\nlookup([a * s], [t]);\n", HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.degree(), 5);
+
+        let error = run_error("#max_degree: 2\ncol advice a;\n");
+        assert!(error.contains("max_degree must be a number of 3 or more"), "{}", error);
+        let error = run_error_kind("#max_degree: 3\ncol advice a;\ngate cube(a) {\n    a[0] * a[0] * a[0] - a[1];\n}\n");
+        assert!(matches!(error, PlonkScriptError::GateError(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_canonical_gates() {
+        let code = "col advice a;\ncol advice b;\ncol advice c;\n\
+            a[0] <-- 1;\nc[0] <-- 2;\na[1] <-- 3;\nc[1] <-- 4;\n\
+            b[0] <== a[0] + c[0];\nb[1] <== c[1] + a[1];\nb[2] <== a[0] * 2;\nb[3] <== 2 * a[1];\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.gates.len(), 2);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        // a[0] + a[1] is another gate than a[0] + a[0]
+        let code = "col advice a;\ncol advice b;\na[0] <-- 1;\na[1] <-- 2;\nb[0] <== a[0] + a[1];\nb[1] <== a[1] + a[1];\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.gates.len(), 2);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+    }
+
+    #[test]
+    fn test_stats() {
+        let code = "pub input x;\ncol advice a;\ncol lookup t;\nt.fill(0..4);\n\
+            a[0] <== x;\na[1] <== a[0] * 2;\na[2] <== a[1] + a[0];\n";
+        let stats = check(code, HashMap::new(), HashMap::from([("x".to_string(), "1".to_string())]))
+            .unwrap()
+            .stats();
+        assert_eq!((stats.advice_columns, stats.fixed_columns, stats.instance_columns), (1, 0, 1));
+        // both gates are enabled on row 0, they are merged under one selector
+        assert_eq!((stats.selector_columns, stats.table_columns), (1, 1));
+        assert_eq!(stats.region_rows, vec![("default".to_string(), 3)]);
+        // the table is longer than the region
+        assert_eq!(stats.rows, 4);
+        assert_eq!((stats.gates, stats.degree, stats.lookups), (1, 3, 0));
+        assert_eq!(stats.permutation_columns, 2);
+        // a is queried at 3 rotations, fewer than the 3 halo2 always reserves
+        assert_eq!(stats.blinding_rows, 6);
+        assert_eq!(stats.min_k, 4);
+
+        // without `# k` the circuit gets the smallest k
+        let code = "col advice a;\nfor i in 0..200 {\n    a[i] <-- i;\n}\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.stats().min_k, 8);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+        // the exported test runs with that k too
+        assert!(generate_rust_code(&cs).unwrap().contains("MockProver::run(8u32,"));
+    }
+
+    #[test]
+    fn test_row_cursors() {
+        let script = transpile("c[$] <== a[$ - 1] + b[$ - 1];".to_string()).unwrap();
+        assert_eq!(script, "c[c.next()] = assign_constraint(c[c.next()], a[a.next() - 1] + b[b.next() - 1]);\n");
+
+        // each call fills the rows after the last one, whatever they took before
+        let code = "region add(a, b, c, x, y) {\n    a[$] <== x;\n    b[$] <== y;\n    c[$] <== a[$ - 1] + b[$ - 1];\n    \
+            [region_rows(), c[c.next() - 1]]\n}\n\
+            col advice a;\ncol advice b;\ncol advice c;\npub input x;\n\
+            let r = add(a, b, c, x, x);\nlet s = add(a, b, c, r[1], r[1]);\nlet t = add(a, b, c, s[1], r[1]);\n\
+            c[$] <== t[1] * (region_rows() + r[0] + 1);\n";
+        let cs = check(code, HashMap::new(), HashMap::from([("x".to_string(), "1".to_string())])).unwrap();
+        let rows = cs.regions.iter().map(|r| (r.name.as_str(), r.rows())).collect::<Vec<_>>();
+        assert_eq!(rows, vec![("default", 0), ("add", 1), ("add", 1), ("add", 2)]);
+        // 6 * (1 + 1 + 1), the rows of the last region and of the first one, plus one
+        assert_eq!(cs.cells["c[1]_add_3"].value.as_deref(), Some(Fp::from(18).to_string().as_str()));
+        try_run_with_inputs(code.to_string(), HashMap::new(), HashMap::from([("x".to_string(), "1".to_string())]), None).unwrap();
+
+        let error = run_error_kind("col selector s;\nlet r = s.next();\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_division_constraint() {
+        let code = "col advice a;\na[0] <-- 6;\na[1] <-- 3;\na[2] <== a[0] / a[1];\n\
+            a[3] <-- 0;\na[4] <== is_zero(a[3]);\na[5] <-- 3;\na[6] <== is_zero(a[5]);\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        let value = |name: &str| cs.cells[&format!("{}_default_0", name)].value.clone().unwrap();
+        assert_eq!(value("a[2]"), Fp::from(2).to_string());
+        assert_eq!(value("a[4]"), Fp::one().to_string());
+        assert_eq!(value("a[6]"), Fp::zero().to_string());
+        assert_eq!(value("a_inv[1]"), Fp::from(3).invert().unwrap().to_string());
+        assert_eq!(value("a_inv[3]"), "0");
+        assert!(cs.columns.iter().any(|c| c.name == "a_inv"));
+        // the inverse, the quotient, and is_zero's output and its check, shared by both calls
+        assert_eq!(cs.gates.len(), 4);
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        // dividing by a constant needs no inverse cell
+        let cs = check("col advice a;\na[0] <-- 6;\na[1] <== a[0] / 2;\n", HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.cells["a[1]_default_0"].value.as_deref(), Some(Fp::from(3).to_string().as_str()));
+        assert!(!cs.columns.iter().any(|c| c.name == "a_inv"));
+
+        let error = run_error_kind("col advice a;\na[0] <-- 1;\na[1] <-- 1;\na[2] <-- a[0] / (a[0] + a[1]);\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_gate_rotations() {
+        // a[9] is queried 9 rows after a[0]
+        let code = "col advice a;\nfor i in 0..9 {\n    a[i] <-- i;\n}\na[9] <== a[0] + a[8];\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        assert_eq!(cs.cells["a[9]_default_0"].value.as_deref(), Some("0x0000000000000000000000000000000000000000000000000000000000000008"));
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        // f[1] is a row below the gate, it is used through a copy in an advice column
+        let code = "col advice a;\ncol fixed f;\nf[0] <-- 2;\nf[1] <-- 3;\na[0] <-- 5;\na[1] <== a[0] * f[0] + f[1];\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        assert!(cs.columns.iter().any(|c| c.name == "f_copy"));
+        assert_eq!(cs.columns_in_equality(), vec!["f_copy", "f"]);
+        assert_eq!(cs.cells["a[1]_default_0"].value, Some(Fp::from(13).to_string()));
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+    }
+
+    #[test]
+    fn test_declared_gate() {
+        let code = "col advice a;\ncol advice b;\n\
+            gate add_mul(a, b) {\n    a[0] + b[0] - a[1];\n    a[0] * b[0] - b[1];\n}\n\
+            a[0] <-- 2;\nb[0] <-- 3;\na[1] <-- 5;\nb[1] <-- 6;\nadd_mul[0] <-- enable;\n";
+        let cs = check(code, HashMap::new(), HashMap::new()).unwrap();
+        let groups = cs.gate_groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!((groups[0].0, groups[0].1.len()), ("add_mul", 2));
+        try_run(code.to_string(), HashMap::new(), None).unwrap();
+
+        let error = run_error_kind("col advice a;\ncol advice b;\ngate g(a) {\n    a[0] - b[0];\n}\n");
+        assert!(matches!(error, PlonkScriptError::GateError(_)), "{}", error);
+        assert!(error.to_string().contains("column b is not a parameter of gate `g`"), "{}", error);
+
+        let error = run_error_kind("col advice a;\ngate g(a) {\n    a[0];\n}\ngate g(a) {\n    a[1];\n}\n");
+        assert!(error.to_string().contains("already defined"), "{}", error);
+    }
+
+    #[test]
+    fn test_instance_columns() {
+        let code = "col instance x;\ncol instance y;\ncol advice a;\n\
+            pub input in1 @ x;\npub input in2 @ x;\npub input in3;\npub output out @ y;\n\
+            gate double(a, x) {\n    a[0] - x[0] * 2;\n}\n\
+            a[0] <== in3;\na[1] <-- 8;\ndouble[1] <-- enable;\nout <== a[1];\n";
+        let script = transpile(code.to_string()).unwrap();
+        assert!(script.starts_with("let x = init_instance_column(\"x\");"), "{}", script);
+        assert!(script.contains("let in1 = init_input(\"in1\", x);"), "{}", script);
+        assert!(script.contains("let out = init_output(\"out\", y);"), "{}", script);
+
+        let inputs = HashMap::from([
+            ("in1".to_string(), "3".to_string()),
+            ("in2".to_string(), "4".to_string()),
+            ("in3".to_string(), "5".to_string()),
+        ]);
+        let cs = check(code, HashMap::new(), inputs.clone()).unwrap();
+        let columns = cs
+            .instance_columns()
+            .into_iter()
+            .map(|(name, signals)| (name, signals.iter().map(|x| x.name.as_str()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            columns,
+            vec![
+                ("defins".to_string(), vec!["in3"]),
+                ("x".to_string(), vec!["in1", "in2"]),
+                ("y".to_string(), vec!["out"]),
+            ]
+        );
+        try_run_with_inputs(code.to_string(), HashMap::new(), inputs, None).unwrap();
+
+        // without signals of its own the default column is left out
+        let cs = check("col instance x;\n", HashMap::new(), HashMap::new()).unwrap();
+        let columns = cs.instance_columns();
+        assert_eq!((columns.len(), columns[0].0.as_str(), columns[0].1.len()), (1, "x", 0));
+
+        let error = run_error_kind("col advice a;\npub input x @ a;\n");
+        assert!(matches!(error, PlonkScriptError::UnsupportedOperation(_)), "{}", error);
+        let error = run_error_kind("col instance defins;\n");
+        assert!(error.to_string().contains("default instance column"), "{}", error);
+        let error = run_error_kind("col instance x;\ncol advice a;\na[0] <== a[1] + x[0];\n");
+        assert!(error.to_string().contains("Instance cannot be used in gate"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_inputs() {
+        let inputs = inputs::parse_inputs(r#"{ "k": 6, "x": "0x05", "big": "123456789012345678901234567890", "n": -1 }"#).unwrap();
+        assert_eq!(inputs["k"], "6");
+        assert_eq!(inputs["x"], "0x05");
+        assert_eq!(inputs["big"], "123456789012345678901234567890");
+        assert_eq!(inputs["n"], "-1");
+
+        assert!(inputs::parse_inputs(r#"{ "x": 1.5 }"#).is_err());
+        assert!(inputs::parse_inputs(r#"{ "x": [1] }"#).is_err());
+        assert!(inputs::parse_inputs("[]").is_err());
+        // a missing file is an IO error, not a malformed one
+        let missing = inputs::read_inputs(Path::new("no/such/inputs.json"));
+        assert!(matches!(missing, Err(PlonkScriptError::Io { .. })), "{:?}", missing);
+
+        let code = "pub input x;\npub output y;\ncol advice a;\na[0] <== x;\ny <== a[0];\n";
+        for json in [r#"{ "x": 5 }"#, r#"{ "x": "5" }"#, r#"{ "x": "0x05" }"#] {
+            let inputs = inputs::parse_inputs(json).unwrap();
+            try_run_with_inputs(code.to_string(), HashMap::new(), inputs, None).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let code = "# k: 4\n\n\n\ncol advice a;   \nregion r(a) {\na[0]<==1;\n  a[1]  <==  a[0]\n+ 1;\n    for i in 0..2 {\n  a[i + 2] <-- a[i + 1]; // next\n}\n}\n";
+        let formatted = formatter::format(code).unwrap();
+        assert_eq!(
+            formatted,
+            "# k: 4\n\ncol advice a;\nregion r(a) {\n    a[0] <== 1;\n    a[1] <== a[0]\n        + 1;\n    for i in 0..2 {\n        a[i + 2] <-- a[i + 1]; // next\n    }\n}\n"
+        );
+        assert_eq!(formatter::format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_examples_is_stable() {
+        let dir = get_project_root().join("plonk/src");
+        for entry in fs::read_dir(dir).unwrap() {
+            let code = fs::read_to_string(entry.unwrap().path()).unwrap();
+            let formatted = formatter::format(&code).unwrap();
+            assert_eq!(formatter::format(&formatted).unwrap(), formatted);
+            // formatting only touches whitespace
+            let strip = |s: &str| s.split_whitespace().collect::<String>();
+            assert_eq!(strip(&formatted), strip(&code));
+        }
+    }
+
     // Individual tests for each plonk file
     macro_rules! generate_test {
         ($test_name:ident, $file_name:expr) => {
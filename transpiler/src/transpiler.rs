@@ -1,164 +1,359 @@
-use regex::{Captures, Regex};
-
-pub fn transpile(code: String) -> String {
-    let code = format_parameters(code);
-    let code = format_hex(code);
-    let code = format_region_fn(code);
-    let (code, outputs) = format_declaration(code);
-    let code = format_assignment(code);
-    let code = append_output_assignment(code, outputs);
-    code
+use rhai::Position;
+
+use crate::engine::FIELD_PARAMETER;
+use crate::parser::{
+    ast::*,
+    lexer::{tokenize, Token, TokenKind},
+    parse, ParseError, Span,
+};
+
+/// Lowers PlonkScript to rhai.
+///
+/// Host code, whitespace and comments are copied verbatim, so every line of the
+/// output corresponds to the same line of the input (the appended `set_output`
+/// calls aside).
+pub fn transpile(code: String) -> Result<String, ParseError> {
+    transpile_with_source_map(&code).map(|(script, _)| script)
+}
+
+/// Same as [`transpile`], also returning where each part of the script came from.
+pub fn transpile_with_source_map(code: &str) -> Result<(String, SourceMap), ParseError> {
+    let program = parse(code)?;
+    let mut lowering = Lowering {
+        src: code,
+        out: String::with_capacity(code.len()),
+        cursor: 0,
+        outputs: vec![],
+        source_map: SourceMap::default(),
+    };
+    lowering.statements(&program.statements)?;
+    lowering.copy_to(code.len());
+    lowering.append_output_assignment();
+    Ok((lowering.out, lowering.source_map))
 }
 
-fn append_output_assignment(code: String, outputs: Vec<String>) -> String {
-    format!(
-        "{}\n{}",
-        code,
-        outputs
-            .iter()
-            .map(|x| format!("set_output(\"{}\", {});", x, x).to_string())
-            .collect::<Vec<String>>()
-            .join("\n")
-    )
+/// Maps byte offsets of a transpiled script back to spans of the PlonkScript source.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    // sorted by `out`, each segment lasts until the next one starts
+    segments: Vec<Segment>,
 }
 
-fn format_declaration(code: String) -> (String, Vec<String>) {
-    let mut outputs = Vec::new();
-    // pub input in1;
-    let re_exp = Regex::new(
-        r"(?x)
-        (?P<modifier>(?:pub|col))
-        \s*
-        (?P<type>(?:input|output|advice|fixed|instance|selector|lookup))
-        \s*
-        (?P<name>[\w\d]+?)
-        \s*
-        ;",
-    )
-    .unwrap();
-    let code = re_exp
-        .replace_all(&code, |x: &Captures| match (&x["modifier"], &x["type"]) {
-            ("pub", "input") => format!("let {} = init_input(\"{}\");", &x["name"], &x["name"]),
-            ("pub", "output") => {
-                outputs.push(x["name"].to_string());
-                format!("let {} = init_output(\"{}\");", &x["name"], &x["name"])
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    out: usize,
+    src: Span,
+    // copied from the source as is, so offsets inside it can be mapped one to one
+    verbatim: bool,
+}
+
+impl SourceMap {
+    /// Source span of the script position reported by rhai.
+    pub fn source_span(&self, src: &str, script: &str, position: Position) -> Option<Span> {
+        let offset = script_offset(script, position)?;
+        let index = self
+            .segments
+            .partition_point(|s| s.out <= offset)
+            .checked_sub(1)?;
+        let segment = self.segments[index];
+        if !segment.verbatim {
+            return Some(segment.src);
+        }
+
+        let start = (segment.src.start + offset - segment.out).min(segment.src.end);
+        let line_end = src[start..].find('\n').map_or(src.len(), |i| start + i);
+        let end = tokenize(&src[start..line_end])
+            .ok()
+            .and_then(|tokens| tokens.first().map(|t| start + t.span.end))
+            .unwrap_or(start);
+        Some(Span::new(start, end))
+    }
+}
+
+// byte offset of a rhai position (1-based line, 1-based column in chars)
+fn script_offset(script: &str, position: Position) -> Option<usize> {
+    let line = position.line()?;
+    let column = position.position().unwrap_or(1);
+    let line_start = if line == 1 {
+        0
+    } else {
+        script.match_indices('\n').nth(line - 2)?.0 + 1
+    };
+    let offset = script[line_start..]
+        .char_indices()
+        .nth(column - 1)
+        .map_or(script.len(), |(i, _)| line_start + i);
+    Some(offset)
+}
+
+struct Lowering<'a> {
+    src: &'a str,
+    out: String,
+    // everything before `cursor` has been written to `out`
+    cursor: usize,
+    outputs: Vec<Signal>,
+    source_map: SourceMap,
+}
+
+impl<'a> Lowering<'a> {
+    fn emit(&mut self, text: &str, src: Span, verbatim: bool) {
+        let out = self.out.len();
+        match self.source_map.segments.last_mut() {
+            // extend the previous segment while the output keeps following the source
+            Some(last)
+                if verbatim
+                    && last.verbatim
+                    && last.src.end == src.start
+                    && last.out + last.src.end - last.src.start == out => {}
+            _ => self
+                .source_map
+                .segments
+                .push(Segment { out, src, verbatim }),
+        }
+        if let Some(last) = self.source_map.segments.last_mut() {
+            last.src.end = last.src.end.max(src.end);
+        }
+        self.out.push_str(text);
+    }
+
+    fn copy_to(&mut self, end: usize) {
+        self.emit_code(Span::new(self.cursor, end));
+        self.cursor = end;
+    }
+
+    // source text of `span`, with 256-bit hex literals turned into strings since
+    // they do not fit into a rhai integer, and row cursors into calls
+    fn emit_code(&mut self, span: Span) {
+        let text = span.text(self.src);
+        let Ok(tokens) = tokenize(text) else {
+            self.emit(text, span, true);
+            return;
+        };
+        for (i, t) in tokens.iter().enumerate() {
+            let s = t.span.text(text);
+            let src = Span::new(span.start + t.span.start, span.start + t.span.end);
+            if t.kind == TokenKind::Number && is_field_literal(s) {
+                self.emit(&format!("\"{}\"", s), src, false);
+            } else if let Some(next) = row_cursor(&tokens, i, text) {
+                self.emit(&next, src, false);
+            } else {
+                self.emit(s, src, true);
             }
-            ("col", "advice") => format!(
-                "let {} = init_advice_column(\"{}\");",
-                &x["name"], &x["name"]
-            ),
-            ("col", "fixed") => format!(
-                "let {} = init_fixed_column(\"{}\");",
-                &x["name"], &x["name"]
-            ),
-            ("col", "selector") => format!(
-                "let {} = init_selector_column(\"{}\");",
-                &x["name"], &x["name"]
-            ),
-            ("col", "lookup") => format!(
-                "let {} = init_table_column(\"{}\");",
-                &x["name"], &x["name"]
-            ),
-            (modifier, t) => {
-                println!("{} {} is not supported", modifier, t);
-                todo!()
+        }
+    }
+
+    // text between two constraints of a gate, which become elements of an array:
+    // the `;` ending the first one is turned into `,`
+    fn emit_gate_separator(&mut self, span: Span) {
+        let semicolon = tokenize(span.text(self.src))
+            .ok()
+            .and_then(|tokens| tokens.into_iter().find(|t| t.kind == TokenKind::Semicolon));
+        match semicolon {
+            Some(t) => {
+                let start = span.start + t.span.start;
+                self.emit_code(Span::new(span.start, start));
+                self.emit(",", Span::new(start, start + 1), false);
+                self.emit_code(Span::new(start + 1, span.end));
             }
-        })
-        .to_string();
-    (code, outputs)
-}
+            None => self.emit_code(span),
+        }
+    }
 
-fn format_assignment(code: String) -> String {
-    // a[0] <== in1;
-    let re_exp = Regex::new(
-        r"(?x)
-        (?P<indent>\x20*)
-        (?P<to>[\w\d\[\]+\-*\x20]+?)
-        \s*
-        (?P<assignment><==|<--|===)
-        \s*
-        (?P<from>[\w\d\[\]+\-*\/\x20_\(\)^&|><]+?)
-        \s*
-        ;",
-    )
-    .unwrap();
-    re_exp
-        .replace_all(&code, |x: &Captures| match (&x["assignment"], &x["from"]) {
-            ("<==", _) => format!(
-                "{}{} = assign_constraint({}, {});",
-                &x["indent"], &x["to"], &x["to"], &x["from"]
-            ),
-            ("<--", "enable") => format!("{}enable_selector({});", &x["indent"], &x["to"]),
-            ("<--", _) => format!(
-                "{}{} = assign_common({}, {});",
-                &x["indent"], &x["to"], &x["to"], &x["from"]
-            ),
-            ("===", _) => format!(
-                "{}{} = constrain_equal({}, {});",
-                &x["indent"], &x["to"], &x["to"], &x["from"]
-            ),
-            (ass, val) => {
-                println!("{} {} {} is not supported", &x["to"], ass, val);
-                todo!()
+    fn append_output_assignment(&mut self) {
+        let end = Span::new(self.src.len(), self.src.len());
+        self.emit("\n", end, false);
+        let outputs = std::mem::take(&mut self.outputs);
+        for (i, output) in outputs.iter().enumerate() {
+            if i > 0 {
+                self.emit("\n", end, false);
             }
-        })
-        .to_string()
-}
+            let name = &output.name.name;
+            self.emit(
+                &format!("set_output(\"{}\", {});", name, name),
+                output.span,
+                false,
+            );
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), ParseError> {
+        for statement in statements {
+            self.copy_to(statement.span().start);
+            self.statement(statement)?;
+            self.cursor = self.cursor.max(statement.span().end);
+        }
+        Ok(())
+    }
 
-fn format_region_fn(code: String) -> String {
-    let re_region_fn = Regex::new(
-        r"(?s)(?x)
-        region\s+(?P<name>[\w\d]+)
-        \((?P<parameters>
-        [\w\d,\s]*)\)
-        \s+\{
-        (?P<code>.*?)
-        \}",
-    )
-    .unwrap();
-    re_region_fn
-        .replace_all(&code, |x: &Captures| {
-            format!(
-                "fn {}({}) {{define_region(\"{}\");{}}}",
-                &x["name"],
-                &x["parameters"],
-                &x["name"],
-                &x["code"],
-            )
-            .to_string()
-        })
-        .to_string()
+    fn block(&mut self, block: &Block) -> Result<(), ParseError> {
+        self.copy_to(block.span.start + 1);
+        self.statements(&block.statements)?;
+        self.copy_to(block.span.end);
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), ParseError> {
+        let span = statement.span();
+        match statement {
+            Statement::Parameter(p) => {
+                self.emit(&format!("set_parameter(\"{}\", ", p.name.name), span, false);
+                let value = p.value.span.text(self.src);
+                // `# field: vesta` names the field, it is no variable
+                if p.name.name == FIELD_PARAMETER
+                    && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    self.emit(&format!("\"{}\"", value), p.value.span, false);
+                } else {
+                    self.emit_code(p.value.span);
+                }
+                self.emit(");", span, false);
+            }
+            Statement::Signal(s) => {
+                let name = &s.name.name;
+                let init = match s.kind {
+                    SignalKind::Input => "init_input",
+                    SignalKind::Output => {
+                        self.outputs.push(s.clone());
+                        "init_output"
+                    }
+                };
+                let column = match &s.column {
+                    Some(column) => format!(", {}", column.name),
+                    None => String::new(),
+                };
+                self.emit(
+                    &format!("let {} = {}(\"{}\"{});", name, init, name, column),
+                    span,
+                    false,
+                );
+            }
+            Statement::Column(c) => {
+                let init = match c.kind {
+                    ColumnKind::Advice => "init_advice_column",
+                    ColumnKind::Fixed => "init_fixed_column",
+                    ColumnKind::Selector => "init_selector_column",
+                    ColumnKind::Lookup => "init_table_column",
+                    ColumnKind::Instance => "init_instance_column",
+                };
+                let name = &c.name.name;
+                let equality = match c.equality {
+                    true => format!(" enable_equality({});", name),
+                    false => String::new(),
+                };
+                self.emit(
+                    &format!("let {} = {}(\"{}\");{}", name, init, name, equality),
+                    span,
+                    false,
+                );
+            }
+            Statement::Region(r) => {
+                let name = &r.name.name;
+                self.emit(&format!("fn {}", name), span, false);
+                // keep the parameter list as written, it may span several lines
+                self.emit_code(Span::new(r.name.span.end, r.body.span.start));
+                self.emit(&format!("{{define_region(\"{}\");", name), span, false);
+                self.cursor = r.body.span.start + 1;
+                self.statements(&r.body.statements)?;
+                self.copy_to(r.body.span.end);
+            }
+            Statement::Gate(g) => {
+                let name = &g.name.name;
+                self.emit(&format!("let {} = define_gate(\"{}\", [", name, name), span, false);
+                // keep the parameter list as written, it may span several lines
+                let header = Span::new(g.name.span.end, g.body.start).text(self.src);
+                let open = g.name.span.end + header.find('(').unwrap_or(0) + 1;
+                let close = g.name.span.end + header.rfind(')').unwrap_or(0);
+                self.emit_code(Span::new(open, close));
+                self.emit("], [", span, false);
+                let mut cursor = g.body.start + 1;
+                for constraint in &g.constraints {
+                    self.emit_gate_separator(Span::new(cursor, constraint.span.start));
+                    self.emit_code(constraint.span);
+                    cursor = constraint.span.end;
+                }
+                self.emit_gate_separator(Span::new(cursor, g.body.end - 1));
+                self.emit("]);", span, false);
+            }
+            Statement::Constraint(c) => {
+                let func = match c.op {
+                    ConstraintOp::ConstrainAssign => "assign_constraint",
+                    ConstraintOp::Assign => "assign_common",
+                    ConstraintOp::ConstrainEqual => "constrain_equal",
+                };
+                // the target is written twice, only the first copy keeps its line breaks
+                let target = c.target.span;
+                let to_arg = target.text(self.src).split_whitespace().collect::<Vec<_>>().join(" ");
+                self.emit_code(target);
+                self.emit(&format!(" = {}(", func), span, false);
+                self.emit(&with_row_cursors(&to_arg), target, false);
+                self.emit(", ", span, false);
+                self.emit_code(c.value.span);
+                self.emit(");", span, false);
+            }
+            Statement::EnableSelector(e) => {
+                self.emit("enable_selector(", span, false);
+                self.emit_code(e.target.span);
+                self.emit(");", span, false);
+            }
+            Statement::Host(h) => {
+                for part in &h.parts {
+                    match part {
+                        HostPart::Code(span) => self.copy_to(span.end),
+                        HostPart::Block(block) => self.block(block)?,
+                    }
+                }
+                self.copy_to(h.span.end);
+            }
+        }
+        self.cursor = self.cursor.max(span.end);
+        Ok(())
+    }
 }
 
-fn format_parameters(code: String) -> String {
-    let re_parameters = Regex::new(
-        r"(?m)(?x)
-        ^\#
-        \s*
-        (?P<name>[\d\w]+)
-        :
-        \s*
-        (?P<val>.*)
-        $",
-    )
-    .unwrap();
-    re_parameters
-        .replace_all(&code, |x: &Captures| {
-            format!("set_parameter(\"{}\", {});", &x["name"], &x["val"]).to_string()
-        })
-        .to_string()
+// `$` in the index of a column, as in `a[$]` or `a[$ - 1]`, is the next free row
+// of the column in the current region, `a.next()`
+fn row_cursor(tokens: &[Token], i: usize, text: &str) -> Option<String> {
+    if tokens[i].kind != TokenKind::Punct || tokens[i].span.text(text) != "$" {
+        return None;
+    }
+    let mut depth = 0;
+    let open = tokens[..i].iter().rposition(|t| match t.kind {
+        TokenKind::RBracket => {
+            depth += 1;
+            false
+        }
+        TokenKind::LBracket if depth > 0 => {
+            depth -= 1;
+            false
+        }
+        kind => kind == TokenKind::LBracket,
+    })?;
+    let column = tokens[..open].iter().rev().find(|t| !t.is_trivia())?;
+    match column.kind {
+        TokenKind::Ident => Some(format!("{}.next()", column.span.text(text))),
+        _ => None,
+    }
 }
 
+fn with_row_cursors(text: &str) -> String {
+    let Ok(tokens) = tokenize(text) else {
+        return text.to_string();
+    };
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, t)| row_cursor(&tokens, i, text).unwrap_or_else(|| t.span.text(text).to_string()))
+        .collect()
+}
 
-fn format_hex(code: String) -> String {
-    let re_hex = Regex::new(
-        r"(?x)
-        (?P<hex>0x[0-9a-f]{64})",
-    )
-    .unwrap();
-    re_hex
-        .replace_all(&code, |x: &Captures| {
-            format!("\"{}\"", &x["hex"]).to_string()
-        })
-        .to_string()
+// integer literals rhai cannot hold in an i64 are passed on as strings, field
+// elements written out in hex always are
+fn is_field_literal(s: &str) -> bool {
+    match s.strip_prefix("0x") {
+        Some(hex) => {
+            hex.chars().all(|c| c.is_ascii_hexdigit())
+                && (hex.len() == 64 || i64::from_str_radix(hex, 16).is_err())
+        }
+        None => s.chars().all(|c| c.is_ascii_digit()) && s.parse::<i64>().is_err(),
+    }
 }
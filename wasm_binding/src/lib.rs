@@ -9,6 +9,8 @@ const TS_APPEND_CONTENT: &'static str = r#"
 export interface TryRunRequest {
   code: string;
   modules?: Record<string, string>;
+  // overrides `#` parameters (`k` included) and supplies public input values
+  inputs?: Record<string, string | number>;
   include_details?: "none" | "transpiled_script" | "context_debug" | "all";
 }
 
@@ -34,6 +36,8 @@ pub struct TryRunRequest {
     #[serde(default)]
     pub modules: std::collections::HashMap<String, String>,
     #[serde(default)]
+    pub inputs: std::collections::HashMap<String, transpiler::inputs::InputValue>,
+    #[serde(default)]
     pub include_details: Option<String>,
 }
 
@@ -83,7 +87,8 @@ fn main() {
             _ => None, // Default to None if not specified or invalid
         };
         
-        match transpiler::try_run(req.code, req.modules, include_details) {
+        let inputs = transpiler::inputs::from_values(req.inputs);
+        match transpiler::try_run_with_inputs(req.code, req.modules, inputs, include_details) {
             Ok(result) => Ok(TypedTryRunResult {
                 obj: serde_wasm_bindgen::to_value(&TryRunResult {
                     prover_result: result.prover_result,